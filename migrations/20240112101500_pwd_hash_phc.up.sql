@@ -0,0 +1,2 @@
+-- Add up migration script here
+ALTER TABLE users ALTER COLUMN pwd_hash TYPE TEXT;
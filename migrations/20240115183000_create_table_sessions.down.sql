@@ -0,0 +1,5 @@
+-- Add down migration script here
+ALTER TABLE users ADD COLUMN IF NOT EXISTS access_token TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS refresh_token TEXT;
+
+DROP TABLE sessions;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- existing accounts keep the ability to create events,
+-- admins are promoted by setting role = 'admin', permissions = 7
+ALTER TABLE users ADD COLUMN IF NOT EXISTS role VARCHAR(16) NOT NULL DEFAULT 'organizer';
+ALTER TABLE users ADD COLUMN IF NOT EXISTS permissions INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,13 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS event_roles(
+    event_id UUID NOT NULL,
+    user_id UUID NOT NULL,
+    role VARCHAR(16) NOT NULL,
+    PRIMARY KEY(event_id, user_id),
+    FOREIGN KEY(event_id) REFERENCES events(id),
+    FOREIGN KEY(user_id) REFERENCES users(id)
+);
+
+INSERT INTO event_roles (event_id, user_id, role)
+SELECT id, creator, 'owner' FROM events
+ON CONFLICT DO NOTHING;
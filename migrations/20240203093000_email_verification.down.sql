@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE one_time_tokens;
+
+ALTER TABLE users DROP COLUMN email_verified_at;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE user_identities;
+
+DROP TABLE oidc_states;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE users DROP COLUMN listed;
+ALTER TABLE users DROP COLUMN show_participations;
+ALTER TABLE users DROP COLUMN show_email;
@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- what other users may see of a profile, the owner always sees everything
+ALTER TABLE users ADD COLUMN show_email BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN show_participations BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN listed BOOLEAN NOT NULL DEFAULT TRUE;
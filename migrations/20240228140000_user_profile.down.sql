@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS users_username_key;
+
+ALTER TABLE users DROP COLUMN locale;
+ALTER TABLE users DROP COLUMN time_zone;
+ALTER TABLE users DROP COLUMN bio;
+ALTER TABLE users DROP COLUMN display_name;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+ALTER TABLE users ADD COLUMN display_name VARCHAR(64);
+ALTER TABLE users ADD COLUMN bio TEXT;
+ALTER TABLE users ADD COLUMN time_zone VARCHAR(64);
+ALTER TABLE users ADD COLUMN locale VARCHAR(35);
+
+-- registration already checks for taken names, this also covers concurrent requests
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_key ON users(username);
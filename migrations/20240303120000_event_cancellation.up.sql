@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- set when the event is called off, the row is kept for participants and exports
+ALTER TABLE events ADD COLUMN cancelled_at TIMESTAMPTZ;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS events_search_vector_idx;
+
+ALTER TABLE events DROP COLUMN search_vector;
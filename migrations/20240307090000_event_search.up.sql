@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- 'simple' keeps words as they are, so prefixes match whatever language an event is written in
+ALTER TABLE events ADD COLUMN search_vector TSVECTOR GENERATED ALWAYS AS (
+    setweight(to_tsvector('simple', coalesce(title, '')), 'A') ||
+    setweight(to_tsvector('simple', coalesce(descr, '')), 'B')
+) STORED;
+
+CREATE INDEX IF NOT EXISTS events_search_vector_idx ON events USING GIN (search_vector);
@@ -0,0 +1,10 @@
+-- Add down migration script here
+DELETE FROM participations WHERE occurrence_dt IS NOT NULL;
+DROP INDEX participations_occurrence_key;
+DROP INDEX participations_series_key;
+ALTER TABLE participations DROP COLUMN occurrence_dt;
+ALTER TABLE participations ADD PRIMARY KEY(event_id, user_id);
+DROP TABLE IF EXISTS event_occurrences;
+ALTER TABLE events DROP COLUMN exdates;
+ALTER TABLE events DROP COLUMN rrule;
+ALTER TABLE events DROP COLUMN time_zone;
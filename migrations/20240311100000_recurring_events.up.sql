@@ -0,0 +1,24 @@
+-- Add up migration script here
+-- RFC 5545 recurrence, expanded in the event's time zone (NULL means UTC)
+ALTER TABLE events ADD COLUMN time_zone VARCHAR(64);
+ALTER TABLE events ADD COLUMN rrule TEXT;
+ALTER TABLE events ADD COLUMN exdates TIMESTAMPTZ[] NOT NULL DEFAULT '{}';
+
+-- a single occurrence of a recurring event that was changed or cancelled,
+-- keyed by the start the rule gives it, NULL fields keep the value of the series
+CREATE TABLE IF NOT EXISTS event_occurrences(
+    event_id UUID NOT NULL REFERENCES events(id) ON DELETE CASCADE,
+    occurrence_dt TIMESTAMPTZ NOT NULL,
+    title TEXT,
+    descr TEXT,
+    dt TIMESTAMPTZ,
+    place TEXT,
+    cancelled_at TIMESTAMPTZ,
+    PRIMARY KEY(event_id, occurrence_dt)
+);
+
+-- NULL subscribes to the whole series
+ALTER TABLE participations ADD COLUMN occurrence_dt TIMESTAMPTZ;
+ALTER TABLE participations DROP CONSTRAINT participations_pkey;
+CREATE UNIQUE INDEX participations_series_key ON participations(event_id, user_id) WHERE occurrence_dt IS NULL;
+CREATE UNIQUE INDEX participations_occurrence_key ON participations(event_id, user_id, occurrence_dt) WHERE occurrence_dt IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS calendar_feeds;
+ALTER TABLE events DROP COLUMN updated_at;
+ALTER TABLE events DROP COLUMN sequence;
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- bumped on every change so calendar clients replace their copy
+ALTER TABLE events ADD COLUMN sequence INT NOT NULL DEFAULT 0;
+ALTER TABLE events ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- one private feed url per user, only the hash of its token is stored
+CREATE TABLE IF NOT EXISTS calendar_feeds(
+    user_id UUID PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL,
+    FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+);
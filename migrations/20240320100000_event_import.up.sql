@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- UID of events imported from other calendars, re-imports update the event instead of adding another
+ALTER TABLE events ADD COLUMN ical_uid TEXT UNIQUE;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX events_series_idx;
+ALTER TABLE events DROP COLUMN recurrence_end;
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- start of the last occurrence of a series, NULL while it has no end, so listings only expand the series that reach their window
+ALTER TABLE events ADD COLUMN recurrence_end TIMESTAMPTZ;
+CREATE INDEX events_series_idx ON events (dt, recurrence_end) WHERE rrule IS NOT NULL;
@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE events DROP CONSTRAINT events_creator_ical_uid_key;
+ALTER TABLE events ADD CONSTRAINT events_ical_uid_key UNIQUE (ical_uid);
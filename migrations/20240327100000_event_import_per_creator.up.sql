@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- every user imports into their own calendar, so the same feed can be imported by several users
+ALTER TABLE events DROP CONSTRAINT events_ical_uid_key;
+ALTER TABLE events ADD CONSTRAINT events_creator_ical_uid_key UNIQUE (creator, ical_uid);
@@ -0,0 +1,554 @@
+use std::{collections::BTreeMap, env, fs, net::IpAddr, path::PathBuf, sync::OnceLock};
+use clap::Parser;
+use dotenv::dotenv;
+use serde::Deserialize;
+use toml::{Table, Value};
+
+use crate::service::rbac::Role;
+
+/// read when no **`--config`** or **`EPS_CONFIG`** is given, a missing file is fine
+const DEFAULT_FILE: &str = "config.toml";
+/// **`EPS__SERVER__PORT=8081`** sets **`server.port`**
+const ENV_PREFIX: &str = "EPS__";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// settings of the whole service, every layer overrides the one before:</br>
+/// built-in defaults, the TOML file, environment variables and finally CLI flags
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub password: PasswordConfig,
+    pub ldap: LdapConfig,
+    pub oidc: OidcConfig,
+    pub mail: MailConfig,
+    pub cookies: CookieConfig,
+    pub log: LogConfig,
+    pub features: Features,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// defaults to the number of cpus
+    pub workers: Option<usize>,
+    /// where clients reach the service, used for links in invitations and oidc redirects
+    pub public_url: String,
+    /// reverse proxies whose **`X-Forwarded-For`** and **`Forwarded`** headers are believed,</br>
+    /// requests from anywhere else are attributed to the connecting address
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            workers: None,
+            public_url: "http://127.0.0.1:8080".to_string(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    /// also read from **`DATABASE_URL`**
+    pub url: String,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_secs: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            max_connections: 5,
+            min_connections: 0,
+            acquire_timeout_secs: 30,
+        }
+    }
+}
+
+/// who issues tokens and for whom, lifetimes are in seconds
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// **`iss`** claim of every token, also the issuer shown in authenticator apps
+    pub issuer: String,
+    /// **`aud`** claim, defaults to **`issuer`**
+    pub audience: Option<String>,
+    /// clock skew tolerated on **`exp`** and **`nbf`**
+    pub leeway_secs: u64,
+    /// every key verifies tokens, the one named by **`signing_kid`** signs new ones
+    pub keys: Vec<JwtKey>,
+    /// defaults to the first of **`keys`**
+    pub signing_kid: Option<String>,
+    /// credential backends tried in order on login, **`db`** and **`ldap`**
+    pub backends: Vec<String>,
+    pub access_token_ttl: i64,
+    pub refresh_token_ttl: i64,
+    pub email_verification_ttl: i64,
+    pub password_reset_ttl: i64,
+    pub two_factor_challenge_ttl: i64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            issuer: "event-planning-service".to_string(),
+            audience: None,
+            leeway_secs: 30,
+            keys: Vec::new(),
+            signing_kid: None,
+            backends: vec!["db".to_string()],
+            access_token_ttl: 60 * 60,
+            refresh_token_ttl: 5 * 24 * 60 * 60,
+            email_verification_ttl: 24 * 60 * 60,
+            password_reset_ttl: 60 * 60,
+            two_factor_challenge_ttl: 5 * 60,
+        }
+    }
+}
+
+impl AuthConfig {
+    pub fn audience(&self) -> &str {
+        self.audience.as_deref().unwrap_or(&self.issuer)
+    }
+}
+
+/// PEM file of an RSA or Ed25519 private key, **`kid`** names it in token headers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JwtKey {
+    pub kid: String,
+    pub path: PathBuf,
+}
+
+/// Argon2id cost of new password hashes, stored hashes with other costs are upgraded on login
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PasswordConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// directory used by the **`ldap`** credential backend
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LdapConfig {
+    pub url: String,
+    pub starttls: bool,
+    pub timeout_secs: u64,
+    /// dn template with a **`{username}`** placeholder, users bind directly when set
+    pub user_dn: Option<String>,
+    /// service account used to look up the user's dn when **`user_dn`** is not set
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    pub search_base: String,
+    pub user_filter: String,
+    pub email_attribute: String,
+    /// groups are searched below this base, otherwise read from the user's **`memberOf`**
+    pub group_base: Option<String>,
+    /// with a **`{dn}`** placeholder for the user's dn
+    pub group_filter: String,
+    /// members of a group get its role, the highest one wins
+    pub group_roles: Vec<GroupRole>,
+    /// role of users in none of the groups
+    pub default_role: Role,
+    /// turns users in none of the groups away instead of giving them **`default_role`**
+    pub refuse_unmapped: bool,
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            starttls: false,
+            timeout_secs: 5,
+            user_dn: None,
+            bind_dn: None,
+            bind_password: None,
+            search_base: String::new(),
+            user_filter: "(uid={username})".to_string(),
+            email_attribute: "mail".to_string(),
+            group_base: None,
+            group_filter: "(member={dn})".to_string(),
+            group_roles: Vec::new(),
+            default_role: Role::User,
+            refuse_unmapped: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupRole {
+    pub group: String,
+    pub role: Role,
+}
+
+/// OpenID Connect providers users can log in with, by the name used in **`/auth/oidc/{provider}`**
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    pub providers: BTreeMap<String, OidcProviderConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcProviderConfig {
+    /// discovery starts at **`<issuer>/.well-known/openid-configuration`**
+    pub issuer: String,
+    pub client_id: String,
+    /// public clients rely on PKCE alone
+    pub client_secret: Option<String>,
+    /// defaults to **`<public_url>/auth/oidc/<name>/callback`**
+    pub redirect_url: Option<String>,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    ["openid", "email", "profile"].map(str::to_string).to_vec()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    None,
+    Starttls,
+    Tls,
+}
+
+/// outgoing mail, without **`smtp_host`** mail is written to the log
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    pub smtp_host: Option<String>,
+    /// defaults to the port of **`smtp_tls`**
+    pub smtp_port: Option<u16>,
+    pub smtp_tls: SmtpTls,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub from: String,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            smtp_host: None,
+            smtp_port: None,
+            smtp_tls: SmtpTls::Starttls,
+            smtp_username: None,
+            smtp_password: None,
+            from: "Event Planning <no-reply@localhost>".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// browser clients can keep their tokens in cookies, see **`service::cookies`**
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CookieConfig {
+    pub enabled: bool,
+    /// only plain http development setups should turn this off
+    pub secure: bool,
+    pub same_site: SameSite,
+    pub domain: Option<String>,
+}
+
+impl Default for CookieConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            secure: true,
+            same_site: SameSite::Strict,
+            domain: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// env_logger filter, e.g. **`info`** or **`warn,event_planning_service=debug`**,</br>
+    /// also read from **`RUST_LOG`**
+    pub level: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self { level: "info".to_string() }
+    }
+}
+
+/// endpoints that can be switched off, disabled ones answer **`404`**
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Features {
+    pub registration: bool,
+    pub password_reset: bool,
+    /// creating keys and authenticating with them
+    pub api_keys: bool,
+    pub data_export: bool,
+    pub account_deletion: bool,
+    /// private iCalendar feed urls
+    pub calendar_feeds: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Self {
+            registration: true,
+            password_reset: true,
+            api_keys: true,
+            data_export: true,
+            account_deletion: true,
+            calendar_feeds: true,
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Event planning service")]
+struct Cli {
+    /// TOML file with the settings, also read from EPS_CONFIG
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    #[arg(long)]
+    host: Option<String>,
+    #[arg(long)]
+    port: Option<u16>,
+    #[arg(long)]
+    workers: Option<usize>,
+    #[arg(long, value_name = "URL")]
+    public_url: Option<String>,
+    #[arg(long, value_name = "URL")]
+    database_url: Option<String>,
+    #[arg(long, value_name = "FILTER")]
+    log_level: Option<String>,
+    /// any other setting, e.g. --set features.registration=false
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    set: Vec<String>,
+}
+
+/// a raw value from the environment or the command line, typed the way TOML would read it</br>
+/// anything that is not a TOML literal stays a string
+fn parse_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {raw}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+/// sets **`path`**, e.g. **`database.max_connections`**, creating the tables on the way
+fn set_path(table: &mut Table, path: &str, value: Value) -> Result<(), String> {
+    let mut keys = path.split('.').peekable();
+    let mut current = table;
+    while let Some(key) = keys.next() {
+        if key.is_empty() {
+            return Err(format!("invalid setting {path:?}"));
+        }
+        if keys.peek().is_none() {
+            current.insert(key.to_string(), value);
+            return Ok(());
+        }
+        current = match current.entry(key.to_string()).or_insert_with(|| Value::Table(Table::new())) {
+            Value::Table(table) => table,
+            _ => return Err(format!("setting {path:?}: {key:?} is not a section")),
+        };
+    }
+    Ok(())
+}
+
+fn read_file(cli_path: Option<PathBuf>) -> Result<Table, String> {
+    let (path, required) = match cli_path.or_else(|| env::var("EPS_CONFIG").ok().map(PathBuf::from)) {
+        Some(path) => (path, true),
+        None => (PathBuf::from(DEFAULT_FILE), false),
+    };
+    match fs::read_to_string(&path) {
+        Ok(content) => content.parse::<Table>().map_err(|err| format!("{}: {err}", path.display())),
+        Err(_) if !required => Ok(Table::new()),
+        Err(err) => Err(format!("{}: {err}", path.display())),
+    }
+}
+
+fn apply_env(table: &mut Table) -> Result<(), String> {
+    if let Ok(url) = env::var("DATABASE_URL") {
+        set_path(table, "database.url", Value::String(url))?;
+    }
+    if let Ok(level) = env::var("RUST_LOG") {
+        set_path(table, "log.level", Value::String(level))?;
+    }
+    for (key, raw) in env::vars() {
+        if let Some(path) = key.strip_prefix(ENV_PREFIX) {
+            let path = path.to_lowercase().replace("__", ".");
+            set_path(table, &path, parse_value(&raw))?;
+        }
+    }
+    Ok(())
+}
+
+fn apply_cli(table: &mut Table, cli: Cli) -> Result<(), String> {
+    let flags = [
+        ("server.host", cli.host.map(Value::String)),
+        ("server.port", cli.port.map(|port| Value::Integer(port.into()))),
+        ("server.workers", cli.workers.map(|workers| Value::Integer(workers as i64))),
+        ("server.public_url", cli.public_url.map(Value::String)),
+        ("database.url", cli.database_url.map(Value::String)),
+        ("log.level", cli.log_level.map(Value::String)),
+    ];
+    for (path, value) in flags {
+        if let Some(value) = value {
+            set_path(table, path, value)?;
+        }
+    }
+    for setting in cli.set {
+        let (path, raw) = setting.split_once('=')
+            .ok_or_else(|| format!("--set {setting:?} must look like key=value"))?;
+        set_path(table, path.trim(), parse_value(raw.trim()))?;
+    }
+    Ok(())
+}
+
+impl Config {
+    /// merges every layer and validates the result
+    pub fn load() -> Result<Self, String> {
+        dotenv().ok();
+        let cli = Cli::parse();
+        let mut table = read_file(cli.config.clone())?;
+        apply_env(&mut table)?;
+        apply_cli(&mut table, cli)?;
+        let config: Config = Value::Table(table).try_into().map_err(|err: toml::de::Error| err.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let Config { server, database, auth, password, ldap, oidc, cookies, log, .. } = self;
+        if server.host.is_empty() {
+            return Err("server.host must not be empty".to_string());
+        }
+        if server.workers == Some(0) {
+            return Err("server.workers must be at least 1".to_string());
+        }
+        if !(server.public_url.starts_with("http://") || server.public_url.starts_with("https://")) {
+            return Err(format!("server.public_url {:?} must be an http(s) url", server.public_url));
+        }
+        if !(database.url.starts_with("postgres://") || database.url.starts_with("postgresql://")) {
+            return Err("database.url (or DATABASE_URL) must be a postgres:// url".to_string());
+        }
+        if database.max_connections == 0 || database.min_connections > database.max_connections {
+            return Err("database.max_connections must be at least 1 and not below min_connections".to_string());
+        }
+        if database.acquire_timeout_secs == 0 {
+            return Err("database.acquire_timeout_secs must be at least 1".to_string());
+        }
+        if auth.issuer.trim().is_empty() || auth.audience().trim().is_empty() {
+            return Err("auth.issuer and auth.audience must not be empty".to_string());
+        }
+        let ttls = [
+            ("access_token_ttl", auth.access_token_ttl),
+            ("refresh_token_ttl", auth.refresh_token_ttl),
+            ("email_verification_ttl", auth.email_verification_ttl),
+            ("password_reset_ttl", auth.password_reset_ttl),
+            ("two_factor_challenge_ttl", auth.two_factor_challenge_ttl),
+        ];
+        if let Some((name, _)) = ttls.iter().find(|(_, ttl)| *ttl <= 0) {
+            return Err(format!("auth.{name} must be positive"));
+        }
+        if auth.refresh_token_ttl < auth.access_token_ttl {
+            return Err("auth.refresh_token_ttl must not be shorter than auth.access_token_ttl".to_string());
+        }
+        if auth.keys.is_empty() {
+            return Err("auth.keys must list at least one signing key".to_string());
+        }
+        if let Some(kid) = auth.signing_kid.as_ref().filter(|kid| !auth.keys.iter().any(|key| key.kid == **kid)) {
+            return Err(format!("auth.signing_kid {kid:?} is not listed in auth.keys"));
+        }
+        if auth.backends.is_empty() {
+            return Err("auth.backends must name at least one backend".to_string());
+        }
+        if let Some(name) = auth.backends.iter().find(|name| !matches!(name.as_str(), "db" | "ldap")) {
+            return Err(format!("auth.backends: unknown credential backend {name:?}"));
+        }
+        if auth.backends.iter().any(|name| name == "ldap") {
+            if ldap.url.is_empty() {
+                return Err("ldap.url must be set for the ldap backend".to_string());
+            }
+            if ldap.user_dn.is_none() && ldap.search_base.is_empty() {
+                return Err("either ldap.user_dn or ldap.search_base must be set".to_string());
+            }
+        }
+        for (name, provider) in &oidc.providers {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-')) {
+                return Err(format!("oidc provider {name:?} must be named with letters, digits, _ and -"));
+            }
+            if !(provider.issuer.starts_with("http://") || provider.issuer.starts_with("https://")) {
+                return Err(format!("oidc.providers.{name}.issuer must be an http(s) url"));
+            }
+            if provider.client_id.is_empty() {
+                return Err(format!("oidc.providers.{name}.client_id must not be empty"));
+            }
+            if !provider.scopes.iter().any(|scope| scope == "openid") {
+                return Err(format!("oidc.providers.{name}.scopes must include openid"));
+            }
+        }
+        if cookies.same_site == SameSite::None && !cookies.secure {
+            return Err("cookies.same_site = \"none\" requires secure cookies".to_string());
+        }
+        if let Err(err) = argon2::Params::new(password.memory_kib, password.iterations, password.parallelism, None) {
+            return Err(format!("password: invalid Argon2 parameters: {err}"));
+        }
+        if log.level.trim().is_empty() {
+            return Err("log.level must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    /// **`public_url`** without a trailing slash
+    pub fn public_url(&self) -> &str {
+        self.server.public_url.trim_end_matches('/')
+    }
+}
+
+/// makes the config available to services, must be called once at startup</br>
+/// handlers receive it as **`web::Data<Config>`**
+pub fn init(config: Config) -> Result<(), String> {
+    CONFIG.set(config).map_err(|_| "config is already initialized".to_string())
+}
+
+pub fn get() -> &'static Config {
+    // unit tests never run the startup, they see the defaults
+    if cfg!(test) {
+        return CONFIG.get_or_init(Config::default);
+    }
+    CONFIG.get().expect("config must be initialized at startup")
+}
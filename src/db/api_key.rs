@@ -0,0 +1,48 @@
+use chrono::Utc;
+use sqlx::postgres::PgQueryResult;
+use uuid::Uuid;
+
+use crate::{models::ApiKey, PGPool};
+
+pub async fn create(key: ApiKey, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO api_keys (id, user_id, name, key_hash, scopes, created_at, expires_at, last_used_at, revoked_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+        key.id, key.user_id, key.name, key.key_hash, key.scopes, key.created_at, key.expires_at,
+        key.last_used_at, key.revoked_at
+    ).execute(pool)
+    .await
+}
+
+pub async fn get_by_id(id: Uuid, pool: &PGPool) -> Result<ApiKey, sqlx::Error> {
+    sqlx::query_as!(ApiKey, "SELECT * FROM api_keys WHERE id = $1", id)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn get_active_by_user(user_id: Uuid, pool: &PGPool) -> Result<Vec<ApiKey>, sqlx::Error> {
+    sqlx::query_as!(
+        ApiKey,
+        "SELECT * FROM api_keys WHERE user_id = $1 AND revoked_at IS NULL ORDER BY created_at DESC",
+        user_id
+    ).fetch_all(pool)
+    .await
+}
+
+pub async fn touch(id: Uuid, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE api_keys SET last_used_at = $1 WHERE id = $2",
+        Utc::now(), id
+    ).execute(pool)
+    .await
+}
+
+/// revokes the key if it belongs to **`user_id`**, returns the number of rows affected
+pub async fn revoke(id: Uuid, user_id: Uuid, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE api_keys SET revoked_at = $1 WHERE id = $2 AND user_id = $3 AND revoked_at IS NULL",
+        Utc::now(), id, user_id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
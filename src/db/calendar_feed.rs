@@ -0,0 +1,33 @@
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{models::CalendarFeed, PGPool};
+
+/// gives the user a feed token, replacing the one they had
+pub async fn replace(user_id: Uuid, token_hash: &str, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "INSERT INTO calendar_feeds (user_id, token_hash, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id) DO UPDATE SET token_hash = EXCLUDED.token_hash, created_at = EXCLUDED.created_at",
+        user_id, token_hash, Utc::now()
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+pub async fn get_by_token_hash(token_hash: &str, pool: &PGPool) -> Result<Option<CalendarFeed>, sqlx::Error> {
+    sqlx::query_as!(
+        CalendarFeed,
+        "SELECT * FROM calendar_feeds WHERE token_hash = $1",
+        token_hash
+    ).fetch_optional(pool)
+    .await
+}
+
+/// returns the number of rows affected
+pub async fn delete(user_id: Uuid, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!("DELETE FROM calendar_feeds WHERE user_id = $1", user_id)
+        .execute(pool)
+        .await?;
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,12 @@
+use uuid::Uuid;
+
+use crate::{models::Comment, PGPool};
+
+pub async fn get_by_user(user_id: Uuid, pool: &PGPool) -> Result<Vec<Comment>, sqlx::Error> {
+    sqlx::query_as!(
+        Comment,
+        "SELECT * FROM comments WHERE user_id = $1",
+        user_id
+    ).fetch_all(pool)
+    .await
+}
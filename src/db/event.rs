@@ -1,31 +1,116 @@
 use chrono::{DateTime, Utc};
-use log::info;
-use sqlx::postgres::PgQueryResult;
+use sqlx::{postgres::PgQueryResult, Acquire, PgExecutor, Postgres, QueryBuilder};
 use uuid::Uuid;
 
-use crate::{models::{Event, User, Participation}, PGPool, dto};
+use crate::{models::{Event, Participation}, PGPool, dto::{self, EventSearchHit, ParticipantDto}};
 
-pub enum Filter{
-    DT((DateTime<Utc>, DateTime<Utc>)),
-    PLACE(String),
-    CREATOR(Uuid)
+/// every column but the generated **`search_vector`**
+const EVENT_COLUMNS: &str = "id, title, descr, dt, place, creator, cancelled_at, time_zone, rrule, exdates, sequence, updated_at, ical_uid";
+/// the text with html special characters escaped, so only the highlight markers are markup
+const ESCAPED_TITLE: &str = "replace(replace(replace(title, '&', '&amp;'), '<', '&lt;'), '>', '&gt;')";
+const ESCAPED_DESCR: &str = "replace(replace(replace(descr, '&', '&amp;'), '<', '&lt;'), '>', '&gt;')";
+
+/// conditions of an event listing, unset fields match every event
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /// events at or after
+    pub from: Option<DateTime<Utc>>,
+    /// events before
+    pub to: Option<DateTime<Utc>>,
+    pub place: Option<String>,
+    pub creator: Option<Uuid>,
+    /// case-insensitive substring of **`title`** or **`descr`**
+    pub q: Option<String>,
+    /// only recurring events if **`true`**, only single ones if **`false`**
+    pub recurring: Option<bool>,
+    /// series with an occurrence at or after, series without end always match
+    pub ends_after: Option<DateTime<Utc>>,
+    /// hides cancelled events from everyone but their organizers, members and participants,</br>
+    /// **`None`** shows every event
+    pub visible_to: Option<Uuid>,
 }
 
-pub async fn create(event: Event, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
-    let res = sqlx::query_as!(Event, "INSERT INTO events (id, title, descr, dt, place, creator) 
-    VALUES ($1, $2, $3, $4, $5, $6)", 
-    event.id, event.title, event.descr, event.dt, event.place, event.creator)
-    .execute(pool)
-    .await;
-    match res {
-        Ok(v) => Ok(v),
-        Err(err) => Err(err)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sort {
+    DtAsc,
+    DtDesc,
+    TitleAsc,
+    TitleDesc,
+}
+
+/// position after the last event of a page, the sort key of that event and its id
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cursor {
+    Dt(DateTime<Utc>, Uuid),
+    Title(String, Uuid),
+}
+
+/// escapes **`LIKE`** wildcards
+fn like_pattern(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+fn push_filter<'a>(query: &mut QueryBuilder<'a, Postgres>, filter: &'a Filter) {
+    query.push(" WHERE TRUE");
+    if let Some(from) = filter.from {
+        query.push(" AND dt >= ").push_bind(from);
+    }
+    if let Some(to) = filter.to {
+        query.push(" AND dt < ").push_bind(to);
+    }
+    if let Some(place) = &filter.place {
+        query.push(" AND place = ").push_bind(place);
+    }
+    if let Some(creator) = filter.creator {
+        query.push(" AND creator = ").push_bind(creator);
+    }
+    if let Some(q) = &filter.q {
+        let pattern = like_pattern(q);
+        query.push(" AND (title ILIKE ").push_bind(pattern.clone())
+            .push(" OR descr ILIKE ").push_bind(pattern)
+            .push(")");
+    }
+    if let Some(recurring) = filter.recurring {
+        query.push(" AND (rrule IS NOT NULL) = ").push_bind(recurring);
     }
+    if let Some(ends_after) = filter.ends_after {
+        query.push(" AND (recurrence_end IS NULL OR recurrence_end >= ").push_bind(ends_after).push(")");
+    }
+    if let Some(viewer) = filter.visible_to {
+        query.push(" AND (cancelled_at IS NULL OR creator = ").push_bind(viewer)
+            .push(" OR EXISTS (SELECT 1 FROM participations p WHERE p.event_id = events.id AND p.user_id = ").push_bind(viewer)
+            .push(") OR EXISTS (SELECT 1 FROM event_roles r WHERE r.event_id = events.id AND r.user_id = ").push_bind(viewer)
+            .push("))");
+    }
+}
+
+/// stores the event and makes its **`creator`** the owner in one transaction,</br>
+/// so there never is an event without an owner; **`recurrence_end`** is the start of the last occurrence of a series
+pub async fn create(
+    event: Event,
+    recurrence_end: Option<DateTime<Utc>>,
+    conn: impl Acquire<'_, Database = Postgres>
+) -> Result<PgQueryResult, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let res = sqlx::query_as!(Event, "INSERT INTO events (id, title, descr, dt, place, creator, time_zone, rrule, exdates, ical_uid, recurrence_end) 
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)", 
+    event.id, event.title, event.descr, event.dt, event.place, event.creator, event.time_zone, event.rrule, &event.exdates, event.ical_uid,
+    recurrence_end)
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO event_roles (event_id, user_id, role) VALUES ($1, $2, 'owner')",
+        event.id, event.creator
+    ).execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(res)
 }
 // /events/id
-pub async fn get_by_id(id: Uuid, pool: &PGPool) -> Result<Event, sqlx::Error> {
-    let res = sqlx::query_as!(Event, "SELECT * FROM events WHERE id = $1", id)
-    .fetch_one(pool)
+pub async fn get_by_id(id: Uuid, conn: impl PgExecutor<'_>) -> Result<Event, sqlx::Error> {
+    let res = sqlx::query_as!(Event, "SELECT id, title, descr, dt, place, creator, cancelled_at, time_zone, rrule, exdates, sequence, updated_at, ical_uid FROM events WHERE id = $1", id)
+    .fetch_one(conn)
     .await;
     match res {
         Ok(event) => Ok(event),
@@ -34,16 +119,13 @@ pub async fn get_by_id(id: Uuid, pool: &PGPool) -> Result<Event, sqlx::Error> {
 }
 
 pub async fn exists(id: Uuid, pool: &PGPool) -> bool {
-    let res = sqlx::query_as!(Event, "SELECT * FROM events WHERE id = $1", id)
+    let res = sqlx::query_as!(Event, "SELECT id, title, descr, dt, place, creator, cancelled_at, time_zone, rrule, exdates, sequence, updated_at, ical_uid FROM events WHERE id = $1", id)
         .fetch_one(pool)
         .await;
-    match res {
-        Ok(_) => true,
-        Err(_) => false
-    }
+    res.is_ok()
 }
 pub async fn get_all(pool: &PGPool) -> Result<Vec<Event>, sqlx::Error> {
-    let res = sqlx::query_as!(Event, "SELECT * FROM events")
+    let res = sqlx::query_as!(Event, "SELECT id, title, descr, dt, place, creator, cancelled_at, time_zone, rrule, exdates, sequence, updated_at, ical_uid FROM events")
     .fetch_all(pool)
     .await;
     match res {
@@ -52,24 +134,33 @@ pub async fn get_all(pool: &PGPool) -> Result<Vec<Event>, sqlx::Error> {
     }
 }
 
-pub async fn subscribe(event_id: Uuid, user_id: Uuid, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+/// **`occurrence`** is the start of a single occurrence of a recurring event, **`None`** subscribes to all of them
+pub async fn subscribe(event_id: Uuid, user_id: Uuid, occurrence: Option<DateTime<Utc>>, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
     sqlx::query_as!(
         Participation,
-        "INSERT INTO participations (event_id, user_id)
-        VALUES ($1, $2)",
-        event_id, user_id
+        "INSERT INTO participations (event_id, user_id, occurrence_dt)
+        VALUES ($1, $2, $3)",
+        event_id, user_id, occurrence
     ).execute(pool)
     .await
 }
 
-pub async fn get_participants(id: Uuid, pool: &PGPool) -> Result<Vec<User>, sqlx::Error> {
-    let res = sqlx::query_as!(
-        User, 
-        "SELECT * FROM users WHERE id IN (SELECT event_id FROM participations WHERE event_id = $1)",
+pub async fn get_participants(id: Uuid, conn: impl PgExecutor<'_>) -> Result<Vec<ParticipantDto>, sqlx::Error> {
+    sqlx::query_as!(
+        ParticipantDto, 
+        "SELECT id AS user_id, username FROM users WHERE id IN (SELECT user_id FROM participations WHERE event_id = $1)",
         id
-    ).fetch_all(pool)
-    .await;
-    res
+    ).fetch_all(conn)
+    .await
+}
+
+pub async fn remove_participant(event_id: Uuid, user_id: Uuid, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM participations WHERE event_id = $1 AND user_id = $2",
+        event_id, user_id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
 }
 
 pub async fn is_participant(user_id: Uuid, event_id: Uuid, pool: &PGPool) -> bool {
@@ -79,65 +170,168 @@ pub async fn is_participant(user_id: Uuid, event_id: Uuid, pool: &PGPool) -> boo
         event_id, user_id
     ).fetch_one(pool)
     .await;
-    match res {
-        Ok(_) => true,
-        Err(_) => false
-    }
-}
-
-pub async fn set_fields(id: Uuid, event_fields: dto::UpdateEventDto, pool: &PGPool) -> Result<u64, sqlx::Error> {
-    let fields = event_fields.get_values();
-
-    if let Some(fields) = fields {
-        let mut sql = "UPDATE events SET ".to_string();
-        for (i, (key, _)) in fields.iter().enumerate() {
-            sql.push_str(&format!("{} = ${}, ", key, i + 1));
-        }
-        sql.truncate(sql.len() - 2);
-        sql.push_str(" WHERE id = $");
-        sql.push_str(&(fields.len() + 1).to_string());
-        info!("SQL string: {:}", sql);
-        let mut query = sqlx::query(&sql);
-        for (_, value) in fields.iter() {
-            query = query.bind(value);
-        }
-        query = query.bind(id);
-        let result = query.execute(pool).await?;
-        Ok(result.rows_affected())
-    } else {
-        Ok(0)
-    }
-}
-
-pub async fn filter(filters: Filter, pool: &PGPool) -> Result<Vec<Event>, sqlx::Error> {
-    match filters {
-        Filter::DT(datetime) => {
-            sqlx::query_as!(
-                Event,
-                "SELECT * FROM events WHERE dt >= $1 AND dt < $2",
-                datetime.0,
-                datetime.1
-            ).fetch_all(pool)
-            .await
-        },
-        Filter::PLACE(place) => {
-            sqlx::query_as!(
-                Event,
-                "SELECT * FROM events WHERE place = $1",
-                place
-            ).fetch_all(pool)
-            .await
+    res.is_ok()
+}
+
+/// writes the fields set in **`event_fields`** and bumps the sequence, returns **`0`** if none is set
+pub async fn set_fields(id: Uuid, event_fields: dto::UpdateEventDto, conn: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    if event_fields.is_empty() {
+        return Ok(0);
+    }
+    let mut query = QueryBuilder::new("UPDATE events SET sequence = sequence + 1, updated_at = now()");
+    if let Some(title) = event_fields.title {
+        query.push(", title = ").push_bind(title);
+    }
+    if let Some(descr) = event_fields.descr {
+        query.push(", descr = ").push_bind(descr);
+    }
+    if let Some(dt) = event_fields.dt {
+        query.push(", dt = ").push_bind(dt);
+    }
+    if let Some(place) = event_fields.place {
+        query.push(", place = ").push_bind(place);
+    }
+    query.push(" WHERE id = ").push_bind(id);
+    let result = query.build().execute(conn).await?;
+    Ok(result.rows_affected())
+}
+
+/// every event matching **`filter`**, oldest first
+pub async fn filter(filter: Filter, conn: impl PgExecutor<'_>) -> Result<Vec<Event>, sqlx::Error> {
+    let mut query = QueryBuilder::new(format!("SELECT {EVENT_COLUMNS} FROM events"));
+    push_filter(&mut query, &filter);
+    query.push(" ORDER BY dt, id");
+    query.build_query_as::<Event>()
+        .fetch_all(conn)
+        .await
+}
+
+/// one page of events matching **`filter`** in **`sort`** order, starting after **`after`**
+pub async fn page(filter: &Filter, sort: Sort, after: Option<Cursor>, limit: i64, pool: &PGPool) -> Result<Vec<Event>, sqlx::Error> {
+    let mut query = QueryBuilder::new(format!("SELECT {EVENT_COLUMNS} FROM events"));
+    push_filter(&mut query, filter);
+    let comparison = match sort {
+        Sort::DtAsc | Sort::TitleAsc => " > (",
+        Sort::DtDesc | Sort::TitleDesc => " < (",
+    };
+    match after {
+        Some(Cursor::Dt(dt, id)) => {
+            query.push(" AND (dt, id)").push(comparison).push_bind(dt).push(", ").push_bind(id).push(")");
         },
-        Filter::CREATOR(creator_id) => {
-            sqlx::query_as!(
-                Event,
-                "SELECT * FROM events WHERE creator = $1",
-                creator_id
-            ).fetch_all(pool)
-            .await  
+        Some(Cursor::Title(title, id)) => {
+            query.push(" AND (title, id)").push(comparison).push_bind(title).push(", ").push_bind(id).push(")");
         },
+        None => {}
     }
+    query.push(match sort {
+        Sort::DtAsc => " ORDER BY dt, id",
+        Sort::DtDesc => " ORDER BY dt DESC, id DESC",
+        Sort::TitleAsc => " ORDER BY title, id",
+        Sort::TitleDesc => " ORDER BY title DESC, id DESC",
+    });
+    query.push(" LIMIT ").push_bind(limit);
+    query.build_query_as::<Event>()
+        .fetch_all(pool)
+        .await
 }
 
+/// events matching the full-text **`tsquery`** and **`filter`**, best matches first,</br>
+/// the matched words in **`title_highlight`** and **`snippet`** are wrapped in **`<mark>`**
+pub async fn search(
+    filter: &Filter,
+    tsquery: &str,
+    limit: i64,
+    offset: i64,
+    pool: &PGPool
+) -> Result<Vec<EventSearchHit>, sqlx::Error> {
+    let mut query = QueryBuilder::new(format!(
+        "SELECT {EVENT_COLUMNS}, ts_rank(search_vector, q) AS rank, \
+        ts_headline('simple', {ESCAPED_TITLE}, q, 'StartSel=<mark>, StopSel=</mark>, HighlightAll=true') AS title_highlight, \
+        ts_headline('simple', {ESCAPED_DESCR}, q, 'StartSel=<mark>, StopSel=</mark>, MaxWords=30, MinWords=10, MaxFragments=2') AS snippet \
+        FROM events, to_tsquery('simple', "
+    ));
+    query.push_bind(tsquery).push(") AS q");
+    push_filter(&mut query, filter);
+    query.push(" AND search_vector @@ q ORDER BY rank DESC, dt, id");
+    query.push(" LIMIT ").push_bind(limit);
+    query.push(" OFFSET ").push_bind(offset);
+    query.build_query_as::<EventSearchHit>()
+        .fetch_all(pool)
+        .await
+}
 
+/// marks the event as called off, returns the number of rows affected
+pub async fn cancel(id: Uuid, conn: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE events SET cancelled_at = $1, sequence = sequence + 1, updated_at = $1 WHERE id = $2 AND cancelled_at IS NULL",
+        Utc::now(), id
+    ).execute(conn)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// replaces the recurrence of the event, a **`None`** rule makes it a single event again
+pub async fn set_recurrence(
+    id: Uuid,
+    time_zone: Option<String>,
+    rrule: Option<String>,
+    exdates: &[DateTime<Utc>],
+    recurrence_end: Option<DateTime<Utc>>,
+    pool: &PGPool
+) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE events SET time_zone = $1, rrule = $2, exdates = $3, recurrence_end = $4, sequence = sequence + 1, updated_at = now()
+        WHERE id = $5",
+        time_zone, rrule, exdates, recurrence_end, id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// start of the last occurrence of the series, to be kept in step with its start and rule
+pub async fn set_recurrence_end(id: Uuid, recurrence_end: Option<DateTime<Utc>>, conn: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE events SET recurrence_end = $1 WHERE id = $2",
+        recurrence_end, id
+    ).execute(conn)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// marks the event as changed without touching its fields, e.g. after one of its occurrences changed
+pub async fn touch(id: Uuid, conn: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE events SET sequence = sequence + 1, updated_at = now() WHERE id = $1",
+        id
+    ).execute(conn)
+    .await?;
+    Ok(res.rows_affected())
+}
 
+/// the event **`creator`** imported under **`ical_uid`**, uids only identify an event within one user's calendar
+pub async fn get_by_ical_uid(creator: Uuid, ical_uid: &str, conn: impl PgExecutor<'_>) -> Result<Option<Event>, sqlx::Error> {
+    sqlx::query_as!(
+        Event,
+        "SELECT id, title, descr, dt, place, creator, cancelled_at, time_zone, rrule, exdates, sequence, updated_at, ical_uid FROM events
+        WHERE creator = $1 AND ical_uid = $2",
+        creator, ical_uid
+    ).fetch_optional(conn)
+    .await
+}
+
+/// overwrites every field an import can set and bumps the sequence,</br>
+/// a cancelled event is on again, imports cancel through **`cancel`**
+pub async fn replace(
+    id: Uuid,
+    event: &dto::NewEventDto,
+    recurrence_end: Option<DateTime<Utc>>,
+    conn: impl PgExecutor<'_>
+) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE events SET title = $1, descr = $2, dt = $3, place = $4, time_zone = $5, rrule = $6, exdates = $7,
+        recurrence_end = $8, cancelled_at = NULL, sequence = sequence + 1, updated_at = now() WHERE id = $9",
+        event.title, event.descr, event.dt, event.place, event.time_zone, event.rrule, &event.exdates, recurrence_end, id
+    ).execute(conn)
+    .await?;
+    Ok(res.rows_affected())
+}
@@ -0,0 +1,87 @@
+use sqlx::{postgres::PgQueryResult, Acquire, PgExecutor, Postgres};
+use uuid::Uuid;
+
+use crate::{models::EventRoleRow, PGPool};
+
+pub async fn get_all(event_id: Uuid, pool: &PGPool) -> Result<Vec<EventRoleRow>, sqlx::Error> {
+    sqlx::query_as!(
+        EventRoleRow,
+        "SELECT * FROM event_roles WHERE event_id = $1",
+        event_id
+    ).fetch_all(pool)
+    .await
+}
+
+pub async fn get(event_id: Uuid, user_id: Uuid, conn: impl PgExecutor<'_>) -> Result<Option<String>, sqlx::Error> {
+    let res = sqlx::query!(
+        "SELECT role FROM event_roles WHERE event_id = $1 AND user_id = $2",
+        event_id, user_id
+    ).fetch_optional(conn)
+    .await?;
+    Ok(res.map(|row| row.role))
+}
+
+pub async fn set(event_id: Uuid, user_id: Uuid, role: &str, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO event_roles (event_id, user_id, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (event_id, user_id) DO UPDATE SET role = EXCLUDED.role",
+        event_id, user_id, role
+    ).execute(pool)
+    .await
+}
+
+pub async fn delete(event_id: Uuid, user_id: Uuid, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM event_roles WHERE event_id = $1 AND user_id = $2",
+        event_id, user_id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// makes **`new_owner`** the owner and **`creator`** of the event,</br>
+/// the previous owner stays on as **`previous_owner_role`**; the event loses its imported uid</br>
+/// if **`new_owner`** already imported one under it
+pub async fn transfer_ownership(
+    event_id: Uuid, 
+    previous_owner: Uuid, 
+    new_owner: Uuid, 
+    previous_owner_role: &str, 
+    conn: impl Acquire<'_, Database = Postgres>
+) -> Result<(), sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    sqlx::query!(
+        "INSERT INTO event_roles (event_id, user_id, role)
+        VALUES ($1, $2, 'owner')
+        ON CONFLICT (event_id, user_id) DO UPDATE SET role = 'owner'",
+        event_id, new_owner
+    ).execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO event_roles (event_id, user_id, role)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (event_id, user_id) DO UPDATE SET role = EXCLUDED.role",
+        event_id, previous_owner, previous_owner_role
+    ).execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "UPDATE events SET creator = $1,
+        ical_uid = CASE WHEN EXISTS (SELECT 1 FROM events other WHERE other.creator = $1 AND other.ical_uid = events.ical_uid)
+            THEN NULL ELSE ical_uid END
+        WHERE id = $2",
+        new_owner, event_id
+    ).execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// the member holding **`role`** who would take over the event, picked by id so the choice is stable
+pub async fn first_with_role(event_id: Uuid, role: &str, conn: impl PgExecutor<'_>) -> Result<Option<Uuid>, sqlx::Error> {
+    let res = sqlx::query!(
+        "SELECT user_id FROM event_roles WHERE event_id = $1 AND role = $2 ORDER BY user_id LIMIT 1",
+        event_id, role
+    ).fetch_optional(conn)
+    .await?;
+    Ok(res.map(|row| row.user_id))
+}
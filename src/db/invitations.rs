@@ -1,4 +1,5 @@
 use sqlx;
+use uuid::Uuid;
 
 use crate::{PGPool, models::Invitation};
 
@@ -22,10 +23,19 @@ pub async fn create(
    }
 }
 
+pub async fn get_by_user(user_id: Uuid, pool: &PGPool) -> Result<Vec<Invitation>, sqlx::Error> {
+   sqlx::query_as!(
+      Invitation,
+      "SELECT * FROM invitations WHERE user_id = $1",
+      user_id
+   ).fetch_all(pool)
+   .await
+}
+
 pub mod notifications {
-   use crate::{models::Invitation, PGPool};
-   use sqlx::postgres::PgQueryResult;
-use uuid::Uuid;
+   use crate::{models::{Invitation, Notification}, PGPool};
+   use sqlx::{postgres::PgQueryResult, PgExecutor};
+   use uuid::Uuid;
    use chrono::Utc;
    pub async fn create(invitaion: &Invitation, pool: &PGPool) -> Result<Uuid, sqlx::Error> {
       let Invitation{id, event_id, user_id, link} = invitaion;
@@ -66,4 +76,27 @@ use uuid::Uuid;
       .await;
       res
    }
-}
\ No newline at end of file
+   /// stores a pending notification with free-form **`content`**
+   pub async fn create_for(recipient: Uuid, content: &str, conn: impl PgExecutor<'_>) -> Result<Uuid, sqlx::Error> {
+      let notification_id = Uuid::new_v4();
+      sqlx::query!(
+         "INSERT INTO notifications (id, recipient, content, stat, creation_dt)
+         VALUES ($1, $2, $3, $4, $5)",
+         notification_id,
+         recipient,
+         content,
+         0,
+         Utc::now(),
+      ).execute(conn)
+      .await?;
+      Ok(notification_id)
+   }
+   pub async fn get_by_recipient(recipient: Uuid, pool: &PGPool) -> Result<Vec<Notification>, sqlx::Error> {
+      sqlx::query_as!(
+         Notification,
+         "SELECT * FROM notifications WHERE recipient = $1 ORDER BY creation_dt",
+         recipient
+      ).fetch_all(pool)
+      .await
+   }
+}
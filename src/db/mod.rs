@@ -1,15 +1,27 @@
 pub mod user;
 pub mod event;
 pub mod invitations;
-use crate::PGPool;
-use log::{info, warn};
+pub mod session;
+pub mod event_role;
+pub mod api_key;
+pub mod one_time_token;
+pub mod two_factor;
+pub mod throttle;
+pub mod oidc;
+pub mod comment;
+pub mod occurrence;
+pub mod calendar_feed;
+use std::time::Duration;
+use crate::{config::DatabaseConfig, PGPool};
+use log::info;
 use sqlx::postgres::PgPoolOptions;
 
-pub async fn init_db_pool(db_url: &str) -> PGPool {
-    warn!("database url: {}", db_url);
+pub async fn init_db_pool(config: &DatabaseConfig) -> PGPool {
     let pool: PGPool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .connect(&config.url)
         .await
         .unwrap();
     info!("{}", "Connect with postgresql".to_string());
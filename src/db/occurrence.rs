@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::{models::OccurrenceOverride, PGPool};
+
+/// the changed and cancelled occurrences of the given events
+pub async fn get_by_events(event_ids: &[Uuid], pool: &PGPool) -> Result<Vec<OccurrenceOverride>, sqlx::Error> {
+    sqlx::query_as!(
+        OccurrenceOverride,
+        "SELECT * FROM event_occurrences WHERE event_id = ANY($1) ORDER BY event_id, occurrence_dt",
+        event_ids
+    ).fetch_all(pool)
+    .await
+}
+
+pub async fn get(event_id: Uuid, occurrence_dt: DateTime<Utc>, conn: impl PgExecutor<'_>) -> Result<Option<OccurrenceOverride>, sqlx::Error> {
+    sqlx::query_as!(
+        OccurrenceOverride,
+        "SELECT * FROM event_occurrences WHERE event_id = $1 AND occurrence_dt = $2",
+        event_id, occurrence_dt
+    ).fetch_optional(conn)
+    .await
+}
+
+/// stores the changes of one occurrence, replacing earlier ones but keeping a cancellation
+pub async fn upsert(changes: OccurrenceOverride, conn: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "INSERT INTO event_occurrences (event_id, occurrence_dt, title, descr, dt, place)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (event_id, occurrence_dt) DO UPDATE
+        SET title = EXCLUDED.title, descr = EXCLUDED.descr, dt = EXCLUDED.dt, place = EXCLUDED.place",
+        changes.event_id, changes.occurrence_dt, changes.title, changes.descr, changes.dt, changes.place
+    ).execute(conn)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// calls one occurrence off, returns **`0`** if it already was
+pub async fn cancel(event_id: Uuid, occurrence_dt: DateTime<Utc>, conn: impl PgExecutor<'_>) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "INSERT INTO event_occurrences (event_id, occurrence_dt, cancelled_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (event_id, occurrence_dt) DO UPDATE
+        SET cancelled_at = EXCLUDED.cancelled_at WHERE event_occurrences.cancelled_at IS NULL",
+        event_id, occurrence_dt, Utc::now()
+    ).execute(conn)
+    .await?;
+    Ok(res.rows_affected())
+}
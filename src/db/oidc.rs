@@ -0,0 +1,56 @@
+use chrono::Utc;
+use sqlx::postgres::PgQueryResult;
+use uuid::Uuid;
+
+use crate::{models::{OidcState, UserIdentity}, PGPool};
+
+pub async fn create_state(state: OidcState, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO oidc_states (state, provider, pkce_verifier, nonce, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)",
+        state.state, state.provider, state.pkce_verifier, state.nonce, state.created_at, state.expires_at
+    ).execute(pool)
+    .await
+}
+
+/// removes and returns the state if it was issued for **`provider`** and has not expired,</br>
+/// expired states are cleaned up on the way
+pub async fn take_state(state: &str, provider: &str, pool: &PGPool) -> Result<Option<OidcState>, sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query!("DELETE FROM oidc_states WHERE expires_at <= $1", now)
+        .execute(pool)
+        .await?;
+    sqlx::query_as!(
+        OidcState,
+        "DELETE FROM oidc_states WHERE state = $1 AND provider = $2 RETURNING *",
+        state, provider
+    ).fetch_optional(pool)
+    .await
+}
+
+pub async fn get_identity(provider: &str, subject: &str, pool: &PGPool) -> Result<Option<UserIdentity>, sqlx::Error> {
+    sqlx::query_as!(
+        UserIdentity,
+        "SELECT * FROM user_identities WHERE provider = $1 AND subject = $2",
+        provider, subject
+    ).fetch_optional(pool)
+    .await
+}
+
+pub async fn link(provider: &str, subject: &str, user_id: Uuid, email: Option<String>, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO user_identities (provider, subject, user_id, email, created_at)
+        VALUES ($1, $2, $3, $4, $5)",
+        provider, subject, user_id, email, Utc::now()
+    ).execute(pool)
+    .await
+}
+
+pub async fn get_identities(user_id: Uuid, pool: &PGPool) -> Result<Vec<UserIdentity>, sqlx::Error> {
+    sqlx::query_as!(
+        UserIdentity,
+        "SELECT * FROM user_identities WHERE user_id = $1 ORDER BY created_at",
+        user_id
+    ).fetch_all(pool)
+    .await
+}
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgQueryResult;
+use uuid::Uuid;
+
+use crate::{models::OneTimeToken, PGPool};
+
+pub async fn create(token: OneTimeToken, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "INSERT INTO one_time_tokens (jti, user_id, purpose, email, created_at, expires_at, used_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)",
+        token.jti, token.user_id, token.purpose, token.email, token.created_at, token.expires_at, token.used_at
+    ).execute(pool)
+    .await
+}
+
+/// marks the token as used if it is still unused and not expired</br>
+/// returns the token or **`None`** if it cannot be used
+pub async fn consume(jti: Uuid, purpose: &str, pool: &PGPool) -> Result<Option<OneTimeToken>, sqlx::Error> {
+    let now = Utc::now();
+    sqlx::query_as!(
+        OneTimeToken,
+        "UPDATE one_time_tokens SET used_at = $1
+        WHERE jti = $2 AND purpose = $3 AND used_at IS NULL AND expires_at > $1
+        RETURNING *",
+        now, jti, purpose
+    ).fetch_optional(pool)
+    .await
+}
+
+/// uses up every outstanding token of **`purpose`** issued to the user
+pub async fn invalidate(user_id: Uuid, purpose: &str, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE one_time_tokens SET used_at = $1 WHERE user_id = $2 AND purpose = $3 AND used_at IS NULL",
+        Utc::now(), user_id, purpose
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// creation times of the tokens of **`purpose`** issued to the user after **`since`**, newest first
+pub async fn issued_since(user_id: Uuid, purpose: &str, since: DateTime<Utc>, pool: &PGPool) -> Result<Vec<DateTime<Utc>>, sqlx::Error> {
+    let rows = sqlx::query!(
+        "SELECT created_at FROM one_time_tokens
+        WHERE user_id = $1 AND purpose = $2 AND created_at > $3
+        ORDER BY created_at DESC",
+        user_id, purpose, since
+    ).fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|row| row.created_at).collect())
+}
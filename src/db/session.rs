@@ -0,0 +1,71 @@
+use chrono::Utc;
+use sqlx::postgres::PgQueryResult;
+use uuid::Uuid;
+
+use crate::{models::Session, PGPool};
+
+pub async fn create(session: Session, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query_as!(
+        Session,
+        "INSERT INTO sessions (id, user_id, refresh_jti, created_at, last_used_at, revoked_at, user_agent, ip)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        session.id, session.user_id, session.refresh_jti, session.created_at, session.last_used_at, session.revoked_at,
+        session.user_agent, session.ip
+    ).execute(pool)
+    .await
+}
+
+pub async fn get_by_id(id: Uuid, pool: &PGPool) -> Result<Session, sqlx::Error> {
+    sqlx::query_as!(Session, "SELECT * FROM sessions WHERE id = $1", id)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn get_active_by_user(user_id: Uuid, pool: &PGPool) -> Result<Vec<Session>, sqlx::Error> {
+    sqlx::query_as!(
+        Session,
+        "SELECT * FROM sessions WHERE user_id = $1 AND revoked_at IS NULL ORDER BY last_used_at DESC",
+        user_id
+    ).fetch_all(pool)
+    .await
+}
+
+pub async fn touch(id: Uuid, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
+    sqlx::query!(
+        "UPDATE sessions SET last_used_at = $1 WHERE id = $2",
+        Utc::now(), id
+    ).execute(pool)
+    .await
+}
+
+/// replaces **`old_jti`** with **`new_jti`** if **`old_jti`** is still the current</br>
+/// refresh token of an active session, returns the number of rows affected
+pub async fn rotate(id: Uuid, old_jti: Uuid, new_jti: Uuid, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE sessions SET refresh_jti = $1, last_used_at = $2
+        WHERE id = $3 AND refresh_jti = $4 AND revoked_at IS NULL",
+        new_jti, Utc::now(), id, old_jti
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// revokes the whole token family
+pub async fn revoke(id: Uuid, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE sessions SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL",
+        Utc::now(), id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// revokes every active session of the user
+pub async fn revoke_all(user_id: Uuid, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE sessions SET revoked_at = $1 WHERE user_id = $2 AND revoked_at IS NULL",
+        Utc::now(), user_id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
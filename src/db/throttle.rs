@@ -0,0 +1,56 @@
+use chrono::{DateTime, Utc};
+
+use crate::{models::AuthThrottle, PGPool};
+
+pub async fn get(scope: &str, key: &str, pool: &PGPool) -> Result<Option<AuthThrottle>, sqlx::Error> {
+    sqlx::query_as!(
+        AuthThrottle,
+        "SELECT * FROM auth_throttle WHERE scope = $1 AND key = $2",
+        scope, key
+    ).fetch_optional(pool)
+    .await
+}
+
+/// counts a failure, the count restarts if the previous one is older than **`window_start`**</br>
+/// returns the number of failures in the current window
+pub async fn record_failure(scope: &str, key: &str, window_start: DateTime<Utc>, pool: &PGPool) -> Result<i32, sqlx::Error> {
+    let res = sqlx::query!(
+        "INSERT INTO auth_throttle (scope, key, failures, last_failure_at)
+        VALUES ($1, $2, 1, $3)
+        ON CONFLICT (scope, key) DO UPDATE SET
+            failures = CASE WHEN auth_throttle.last_failure_at < $4 THEN 1 ELSE auth_throttle.failures + 1 END,
+            last_failure_at = EXCLUDED.last_failure_at
+        RETURNING failures",
+        scope, key, Utc::now(), window_start
+    ).fetch_one(pool)
+    .await?;
+    Ok(res.failures)
+}
+
+/// extends the lock, an existing longer one is kept
+pub async fn lock_until(scope: &str, key: &str, until: DateTime<Utc>, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE auth_throttle SET locked_until = GREATEST(locked_until, $1) WHERE scope = $2 AND key = $3",
+        until, scope, key
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+pub async fn clear(scope: &str, key: &str, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "DELETE FROM auth_throttle WHERE scope = $1 AND key = $2",
+        scope, key
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+pub async fn get_locked(now: DateTime<Utc>, pool: &PGPool) -> Result<Vec<AuthThrottle>, sqlx::Error> {
+    sqlx::query_as!(
+        AuthThrottle,
+        "SELECT * FROM auth_throttle WHERE locked_until > $1 ORDER BY locked_until DESC",
+        now
+    ).fetch_all(pool)
+    .await
+}
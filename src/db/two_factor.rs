@@ -0,0 +1,87 @@
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{models::UserTotp, PGPool};
+
+pub async fn get(user_id: Uuid, pool: &PGPool) -> Result<Option<UserTotp>, sqlx::Error> {
+    sqlx::query_as!(UserTotp, "SELECT * FROM user_totp WHERE user_id = $1", user_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn is_enabled(user_id: Uuid, pool: &PGPool) -> Result<bool, sqlx::Error> {
+    let res = sqlx::query!(
+        "SELECT EXISTS(SELECT 1 FROM user_totp WHERE user_id = $1 AND confirmed_at IS NOT NULL) AS \"enabled!\"",
+        user_id
+    ).fetch_one(pool)
+    .await?;
+    Ok(res.enabled)
+}
+
+/// stores a new unconfirmed secret, a confirmed one is never replaced</br>
+/// returns the number of rows affected
+pub async fn set_pending(user_id: Uuid, secret: &str, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "INSERT INTO user_totp (user_id, secret, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, created_at = EXCLUDED.created_at, last_step = NULL
+        WHERE user_totp.confirmed_at IS NULL",
+        user_id, secret, Utc::now()
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// records **`step`** as used if it is newer than the last accepted one,</br>
+/// so each code works only once, returns the number of rows affected
+pub async fn use_step(user_id: Uuid, step: i64, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE user_totp SET last_step = $1 WHERE user_id = $2 AND (last_step IS NULL OR last_step < $1)",
+        step, user_id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// enables 2fa and replaces the recovery codes in one transaction
+pub async fn confirm(user_id: Uuid, code_hashes: &[String], pool: &PGPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE user_totp SET confirmed_at = $1 WHERE user_id = $2",
+        Utc::now(), user_id
+    ).execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    for code_hash in code_hashes {
+        sqlx::query!(
+            "INSERT INTO recovery_codes (id, user_id, code_hash) VALUES ($1, $2, $3)",
+            Uuid::new_v4(), user_id, code_hash
+        ).execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await
+}
+
+/// uses up the recovery code, returns the number of rows affected
+pub async fn use_recovery_code(user_id: Uuid, code_hash: &str, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE recovery_codes SET used_at = $1 WHERE user_id = $2 AND code_hash = $3 AND used_at IS NULL",
+        Utc::now(), user_id, code_hash
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// removes the secret and all recovery codes
+pub async fn delete(user_id: Uuid, pool: &PGPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
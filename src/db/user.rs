@@ -1,12 +1,13 @@
+use chrono::Utc;
 use log::info;
-use sqlx::{postgres::PgQueryResult, query};
+use sqlx::{postgres::PgQueryResult, query, Acquire, PgExecutor, Postgres};
 use uuid::Uuid;
 
-use crate::{models::{User, Event}, PGPool, dto};
+use crate::{models::{User, Event, Participation}, PGPool, dto};
 
 pub async fn create(user: User, pool: &PGPool) -> Result<PgQueryResult, sqlx::Error> {
-    let res: Result<PgQueryResult, sqlx::Error> = sqlx::query_as!(User, "INSERT INTO users (id, username, pwd_hash, email, access_token, refresh_token) 
-    VALUES ($1, $2, $3, $4, $5, $6)", user.id, user.username, user.pwd_hash, user.email, user.access_token, user.refresh_token)
+    let res: Result<PgQueryResult, sqlx::Error> = sqlx::query_as!(User, "INSERT INTO users (id, username, pwd_hash, email, role, permissions, email_verified_at, auth_source) 
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)", user.id, user.username, user.pwd_hash, user.email, user.role, user.permissions, user.email_verified_at, user.auth_source)
     .execute(pool)
     .await;
     match res {
@@ -15,9 +16,9 @@ pub async fn create(user: User, pool: &PGPool) -> Result<PgQueryResult, sqlx::Er
     }
 }
 
-pub async fn get_by_id(id: Uuid, pool: &PGPool) -> Result<User, sqlx::Error> {
+pub async fn get_by_id(id: Uuid, conn: impl PgExecutor<'_>) -> Result<User, sqlx::Error> {
     let res = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", id)
-    .fetch_one(pool)
+    .fetch_one(conn)
     .await;
     match res {
         Ok(user) => Ok(user),
@@ -35,37 +36,48 @@ pub async fn get_all(pool: &PGPool) -> Result<Vec<User>, sqlx::Error> {
     }
 }
 
+/// users shown in the directory, plus **`viewer`** whether listed or not
+pub async fn get_listed(viewer: Uuid, pool: &PGPool) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as!(User, "SELECT * FROM users WHERE listed OR id = $1", viewer)
+        .fetch_all(pool)
+        .await
+}
+
 pub async fn exists(username: String, pool: &PGPool) -> bool {
     let res = sqlx::query_as!(User, "SELECT * FROM users WHERE username = $1", username)
         .fetch_one(pool)
         .await;
-    match res {
-        Ok(_) => true,
-        Err(_) => false
-    }
+    res.is_ok()
 }
 
 pub async fn exists_by_id(user_id: Uuid, pool: &PGPool) -> bool {
     let res = sqlx::query_as!(User, "SELECT * FROM users WHERE id = $1", user_id)
         .fetch_one(pool)
         .await;
-    match res {
-        Ok(_) => true,
-        Err(_) => false
-    }
+    res.is_ok()
 }
 
 // /users/{id}/participations
 pub async fn get_user_participations(id: Uuid, pool: &PGPool) -> Result<Vec<Event>, sqlx::Error> {
     let res = sqlx::query_as!(
         Event, 
-        "SELECT * FROM events WHERE id IN (SELECT event_id FROM participations WHERE user_id = $1)", 
+        "SELECT id, title, descr, dt, place, creator, cancelled_at, time_zone, rrule, exdates, sequence, updated_at, ical_uid FROM events WHERE id IN (SELECT event_id FROM participations WHERE user_id = $1)", 
         id
     ).fetch_all(pool)
     .await;
     res
 }
 
+/// every participation of the user, the whole series ones and those in a single occurrence
+pub async fn get_participation_rows(id: Uuid, pool: &PGPool) -> Result<Vec<Participation>, sqlx::Error> {
+    sqlx::query_as!(
+        Participation,
+        "SELECT * FROM participations WHERE user_id = $1",
+        id
+    ).fetch_all(pool)
+    .await
+}
+
 pub async fn get_id_by_username(username: String, pool: &PGPool) -> Result<Uuid, sqlx::Error> {
     let res = sqlx::query_as!(User, "SELECT * FROM users WHERE username = $1", username)
     .fetch_one(pool)
@@ -86,6 +98,18 @@ pub async fn get_by_username(username: String, pool: &PGPool) -> Result<User, sq
     }
 }
 
+pub async fn get_by_email(email: &str, pool: &PGPool) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1", email)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn get_verified_by_email(email: &str, pool: &PGPool) -> Result<Vec<User>, sqlx::Error> {
+    sqlx::query_as!(User, "SELECT * FROM users WHERE email = $1 AND email_verified_at IS NOT NULL", email)
+        .fetch_all(pool)
+        .await
+}
+
 pub async fn get_pwd_hash(id: Uuid, pool: &PGPool) -> Result<String, sqlx::Error> {
     let res = sqlx::query_as!(User, 
         "SELECT * FROM users WHERE id = $1", id)
@@ -97,6 +121,41 @@ pub async fn get_pwd_hash(id: Uuid, pool: &PGPool) -> Result<String, sqlx::Error
     }
 }
 
+pub async fn get_permissions(id: Uuid, pool: &PGPool) -> Result<i32, sqlx::Error> {
+    let res = sqlx::query!("SELECT permissions FROM users WHERE id = $1", id)
+    .fetch_one(pool)
+    .await?;
+    Ok(res.permissions)
+}
+
+pub async fn set_role(id: Uuid, role: &str, permissions: i32, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE users SET role = $1, permissions = $2 WHERE id = $3",
+        role, permissions, id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+pub async fn set_privacy(id: Uuid, privacy: &dto::PrivacySettings, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE users SET show_email = $1, show_participations = $2, listed = $3 WHERE id = $4",
+        privacy.show_email, privacy.show_participations, privacy.listed, id
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// marks **`email`** as verified, does nothing if the user's address has changed since
+pub async fn set_email_verified(id: Uuid, email: &str, pool: &PGPool) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query!(
+        "UPDATE users SET email_verified_at = $1 WHERE id = $2 AND email = $3",
+        Utc::now(), id, email
+    ).execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
 pub async fn set_fields(id: Uuid, user_fields: dto::UpdateUserDto, pool: &PGPool) -> Result<u64, sqlx::Error> {
     let fields = user_fields.get_values();
     if let Some(fields) = fields {
@@ -104,6 +163,10 @@ pub async fn set_fields(id: Uuid, user_fields: dto::UpdateUserDto, pool: &PGPool
         for (i, (key, _)) in fields.iter().enumerate() {
             sql.push_str(&format!("{} = ${}, ", key, i + 1));
         }
+        // a new address has to be verified again
+        if user_fields.email.is_some() {
+            sql.push_str("email_verified_at = NULL, ");
+        }
         sql.truncate(sql.len() - 2);
         sql.push_str(" WHERE id = $");
         sql.push_str(&(fields.len() + 1).to_string());
@@ -120,4 +183,45 @@ pub async fn set_fields(id: Uuid, user_fields: dto::UpdateUserDto, pool: &PGPool
     }
 }
 
-
+/// strips the account of everything personal in one transaction,</br>
+/// the row itself stays so comments and past events keep their author</br>
+/// sessions, keys, tokens, 2fa, linked identities, participations, invitations, event roles, notifications</br>
+/// and the calendar feed are deleted
+pub async fn anonymize(
+    id: Uuid,
+    username: &str,
+    pwd_hash: &str,
+    auth_source: &str,
+    conn: impl Acquire<'_, Database = Postgres>
+) -> Result<u64, sqlx::Error> {
+    let mut tx = conn.begin().await?;
+    let previous = sqlx::query!("SELECT username FROM users WHERE id = $1 FOR UPDATE", id)
+        .fetch_one(&mut *tx)
+        .await?;
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM api_keys WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM one_time_tokens WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM user_totp WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM user_identities WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM participations WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM invitations WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM event_roles WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM notifications WHERE recipient = $1", id).execute(&mut *tx).await?;
+    sqlx::query!("DELETE FROM calendar_feeds WHERE user_id = $1", id).execute(&mut *tx).await?;
+    sqlx::query!(
+        "DELETE FROM auth_throttle WHERE scope = 'account' AND key = $1",
+        previous.username
+    ).execute(&mut *tx)
+    .await?;
+    let res = sqlx::query!(
+        "UPDATE users SET username = $1, pwd_hash = $2, auth_source = $3, email = NULL, email_verified_at = NULL,
+        display_name = NULL, bio = NULL, time_zone = NULL, locale = NULL, role = 'user', permissions = 0,
+        show_email = FALSE, show_participations = FALSE, listed = FALSE
+        WHERE id = $4",
+        username, pwd_hash, auth_source, id
+    ).execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(res.rows_affected())
+}
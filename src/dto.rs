@@ -2,6 +2,11 @@ use serde::{Deserialize, Serialize};
 use chrono::{self, Utc};
 use uuid::Uuid;
 
+use crate::{
+    models::{Comment, Event, Invitation, Notification, UserIdentity},
+    service::rbac::{ApiScope, EventRole, Role},
+};
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct NewUserDto {
     pub username: String,
@@ -16,6 +21,13 @@ pub struct NewEventDto {
     pub descr: String,
     pub dt: chrono::DateTime<chrono::Utc>,
     pub place: Option<String>,
+    /// IANA name the recurrence is expanded in, UTC when unset
+    pub time_zone: Option<String>,
+    /// RFC 5545 recurrence rule, e.g. **`FREQ=WEEKLY;BYDAY=MO,WE;COUNT=10`**
+    pub rrule: Option<String>,
+    /// starts of occurrences left out, needs **`rrule`**
+    #[serde(default)]
+    pub exdates: Vec<chrono::DateTime<Utc>>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -27,48 +39,321 @@ pub struct LoginUserRequest {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AuthUserRespone(pub String);
 
+/// deliberately not **`Debug`**, so the tokens cannot end up in a log line
+#[derive(Serialize, Deserialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+/// response body in cookie mode, the tokens themselves are only set as cookies</br>
+/// **`csrf_token`** has to be sent back in the **`X-CSRF-Token`** header on mutating requests
+#[derive(Debug, Serialize)]
+pub struct CookieSession {
+    pub csrf_token: String,
+    pub expires_in: i64,
+}
+
+/// returned by the first login step when the account has 2fa enabled,</br>
+/// **`challenge_token`** is exchanged for a token pair together with a code
+#[derive(Serialize)]
+pub struct TwoFactorChallenge {
+    pub challenge_token: String,
+    pub expires_in: i64,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Tokens(TokenPair),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+/// **`code`** is a current TOTP code or an unused recovery code
+#[derive(Deserialize)]
+pub struct TwoFactorLoginDto {
+    pub challenge_token: String,
+    pub code: String,
+}
+
+/// **`mode=cookie`** finishes the login in cookie mode
+#[derive(Debug, Deserialize)]
+pub struct OidcLoginQuery {
+    pub mode: Option<String>,
+}
+
+/// query of the provider's redirect back to **`/auth/oidc/{provider}/callback`**
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Deserialize)]
+pub struct TotpCodeDto {
+    pub code: String,
+}
+
+/// body of **`POST /auth/2fa/disable`**, **`pwd`** is required for accounts with a local password
+#[derive(Deserialize)]
+pub struct DisableTwoFactorDto {
+    pub pwd: Option<String>,
+    pub code: String,
+}
+
+/// shown once when 2fa is confirmed, only hashes are stored
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// client details recorded on a session at login
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub last_used_at: chrono::DateTime<Utc>,
+    pub current: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKeyDto {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// returned once on creation, **`key`** cannot be retrieved again
+#[derive(Debug, Serialize)]
+pub struct ApiKeyCreated {
+    pub id: Uuid,
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub expires_at: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub expires_at: Option<chrono::DateTime<Utc>>,
+    pub last_used_at: Option<chrono::DateTime<Utc>>,
+}
+
+/// value of the **`typ`** claim, keeps access, refresh and one-time tokens apart
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenType {
+    Refresh,
+    Access,
+    EmailVerification,
+    PasswordReset,
+    TwoFactorChallenge
+}
+
+impl TokenType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenType::Refresh => "refresh",
+            TokenType::Access => "access",
+            TokenType::EmailVerification => "email_verification",
+            TokenType::PasswordReset => "password_reset",
+            TokenType::TwoFactorChallenge => "two_factor_challenge",
+        }
+    }
+}
+
+/// registered claims follow RFC 7519, timestamps are in seconds
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Claims {
     pub user_id: Uuid,
     pub username: String,
-    pub exp: usize
+    /// session (token family) the token belongs to
+    pub sid: Uuid,
+    pub typ: TokenType,
+    pub jti: Uuid,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub nbf: i64,
+    pub exp: i64
 }
 
-impl Claims {
-    pub fn new(user_id: &Uuid,  username: &String, exp: usize) -> Self {
-        Self {
-            user_id: *user_id,
-            username: username.to_string(),
-            exp
-        }
-    }
+/// claims of single-use tokens sent by mail, **`email`** is the address the token was sent to
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OneTimeClaims {
+    pub sub: Uuid,
+    pub typ: TokenType,
+    pub jti: Uuid,
+    pub email: Option<String>,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub nbf: i64,
+    pub exp: i64
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailDto {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordDto {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordDto {
+    pub token: String,
+    pub pwd: String,
+    pub pwd_confirm: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangePasswordDto {
+    pub current_pwd: String,
+    pub pwd: String,
+    pub pwd_confirm: String,
+}
+
+/// what other users may see of a profile
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PrivacySettings {
+    pub show_email: bool,
+    pub show_participations: bool,
+    pub listed: bool,
+}
+
+/// profile as seen by other users, **`email`** only if the owner shares it
+#[derive(Debug, Serialize)]
+pub struct PublicProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+/// the caller's own profile, served at **`GET /user/me`**
+#[derive(Debug, Serialize)]
+pub struct PrivateProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub email: Option<String>,
+    pub email_verified_at: Option<chrono::DateTime<Utc>>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub time_zone: Option<String>,
+    pub locale: Option<String>,
+    pub role: String,
+    pub auth_source: String,
+    pub privacy: PrivacySettings,
+}
+
+/// **`format=zip`** packs every section of the export into its own file, **`json`** is the default
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+}
+
+/// everything stored about the caller, served at **`GET /user/me/export`**
+#[derive(Debug, Serialize)]
+pub struct AccountExport {
+    pub exported_at: chrono::DateTime<Utc>,
+    pub profile: PrivateProfile,
+    pub events_created: Vec<Event>,
+    pub participations: Vec<Event>,
+    pub invitations: Vec<Invitation>,
+    pub comments: Vec<Comment>,
+    pub notifications: Vec<Notification>,
+    pub sessions: Vec<SessionInfo>,
+    pub api_keys: Vec<ApiKeyInfo>,
+    pub identities: Vec<UserIdentity>,
 }
-#[derive(Clone)]
+
+/// body of **`DELETE /user/me`**, **`pwd`** is required for accounts with a local password
+#[derive(Deserialize)]
+pub struct DeleteAccountDto {
+    pub pwd: Option<String>,
+}
+
+/// what happened to the events the deleted user created
+#[derive(Debug, Serialize)]
+pub struct AccountDeletion {
+    pub transferred_events: Vec<Uuid>,
+    pub cancelled_events: Vec<Uuid>,
+}
+
+/// body of **`PATCH /user/me`**, absent fields are left alone and an empty string clears</br>
+/// **`display_name`**, **`bio`**, **`time_zone`** or **`locale`**</br>
+/// unknown fields are rejected, so passwords and tokens can never be set through it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateProfileDto {
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub time_zone: Option<String>,
+    pub locale: Option<String>,
+}
+
+/// columns written by **`db::user::set_fields`**, **`Some(None)`** sets a column to NULL
+#[derive(Clone, Default)]
 pub struct UpdateUserDto {
     pub pwd_hash: Option<String>,
     pub username: Option<String>,
     pub email: Option<String>,
-    pub access_token: Option<String>,
-    pub refresh_token: Option<String>,
+    pub display_name: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub time_zone: Option<Option<String>>,
+    pub locale: Option<Option<String>>,
 }
 
 impl UpdateUserDto {
-    pub fn get_values(&self) -> Option<Vec<(String, String)>> {
-        let mut fields: Vec<(String, String)> = Vec::new();
+    pub fn get_values(&self) -> Option<Vec<(String, Option<String>)>> {
+        let mut fields: Vec<(String, Option<String>)> = Vec::new();
         if let Some(v) = &self.pwd_hash {
-            fields.push(("pwd_hash".to_string(), v.to_string()));
+            fields.push(("pwd_hash".to_string(), Some(v.to_string())));
         }
         if let Some(v) = &self.username {
-            fields.push(("username".to_string(), v.to_string()));
+            fields.push(("username".to_string(), Some(v.to_string())));
         }
         if let Some(v) = &self.email {
-            fields.push(("email".to_string(), v.to_string()));
+            fields.push(("email".to_string(), Some(v.to_string())));
         }
-        if let Some(v) = &self.access_token {
-            fields.push(("access_token".to_string(), v.to_string()));
+        if let Some(v) = &self.display_name {
+            fields.push(("display_name".to_string(), v.clone()));
         }
-        if let Some(v) = &self.refresh_token {
-            fields.push(("refresh_token".to_string(), v.to_string()));
+        if let Some(v) = &self.bio {
+            fields.push(("bio".to_string(), v.clone()));
+        }
+        if let Some(v) = &self.time_zone {
+            fields.push(("time_zone".to_string(), v.clone()));
+        }
+        if let Some(v) = &self.locale {
+            fields.push(("locale".to_string(), v.clone()));
         }
 
         if fields.is_empty() {
@@ -88,32 +373,183 @@ pub struct UpdateEventDto {
 }
 
 impl UpdateEventDto {
-    pub fn get_values(&self) -> Option<Vec<(String, String)>> {
-        let mut fields: Vec<(String, String)> = Vec::new();
-        if let Some(v) = &self.title {
-            fields.push(("title".to_string(), v.to_string()));
-        }
-        if let Some(v) = &self.descr {
-            fields.push(("descr".to_string(), v.to_string()));
-        }
-        if let Some(v) = &self.dt {
-            fields.push(("dt".to_string(), v.timestamp_micros().to_string()));
-        }
-        if let Some(v) = &self.place {
-            fields.push(("place".to_string(), v.to_string()));
-        }
-
-        if fields.is_empty() {
-            None
-        } else {
-            Some(fields)
-        }
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.descr.is_none() && self.dt.is_none() && self.place.is_none()
     }
 }
 
+/// query of **`GET /event`**, the filters combine, **`sort`** is **`dt`**, **`-dt`**, **`title`** or **`-title`**</br>
+/// **`cursor`** is the **`next_cursor`** of the previous page and only valid with the same **`sort`**</br>
+/// **`expand=true`** lists every occurrence of recurring events between **`from`** and **`to`**, both are required then</br>
+/// and only **`dt`** or **`-dt`** can be the **`sort`**
+#[derive(Debug, Deserialize)]
+pub struct EventQuery {
+    pub from: Option<chrono::DateTime<Utc>>,
+    pub to: Option<chrono::DateTime<Utc>>,
+    pub place: Option<String>,
+    pub creator: Option<Uuid>,
+    pub q: Option<String>,
+    pub sort: Option<String>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub expand: Option<bool>,
+}
+
+/// an event, or one occurrence of a recurring event with its changes applied</br>
+/// **`occurrence`** is the start the recurrence rule gives it and identifies it in later requests
+#[derive(Debug, Serialize)]
+pub struct EventInstance {
+    #[serde(flatten)]
+    pub event: Event,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occurrence: Option<chrono::DateTime<Utc>>,
+}
+
+/// body of **`PUT /event/{id}/recurrence`**, replaces the whole recurrence, no **`rrule`** ends it
+#[derive(Debug, Deserialize)]
+pub struct RecurrenceDto {
+    pub time_zone: Option<String>,
+    pub rrule: Option<String>,
+    #[serde(default)]
+    pub exdates: Vec<chrono::DateTime<Utc>>,
+}
+
+/// body of **`PUT /event/{id}/occurrences/{occurrence}`**, unset fields keep the value of the series
+#[derive(Debug, Deserialize)]
+pub struct OccurrenceDto {
+    pub title: Option<String>,
+    pub descr: Option<String>,
+    pub dt: Option<chrono::DateTime<Utc>>,
+    pub place: Option<String>,
+}
+
+/// query of **`POST /event/{id}/subscribe`**, without **`occurrence`** the whole series is subscribed
+#[derive(Debug, Deserialize)]
+pub struct SubscribeQuery {
+    pub occurrence: Option<chrono::DateTime<Utc>>,
+}
+
+/// the private feed url, clients poll it without any other credentials
+#[derive(Debug, Serialize)]
+pub struct CalendarFeedDto {
+    pub url: String,
+}
+
+/// query of **`POST /event/import`**, a dry run reports what an import would do without writing anything
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    pub dry_run: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Created,
+    Updated,
+    Unchanged,
+    Cancelled,
+    /// cancelled in the file and not known here, so there is nothing to cancel
+    Skipped,
+    Failed,
+}
+
+/// what happened, or in a dry run would happen, to one **`VEVENT`**
+#[derive(Debug, Serialize)]
+pub struct ImportItem {
+    pub uid: Option<String>,
+    pub title: Option<String>,
+    /// set for entries that change one occurrence of a series
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occurrence: Option<chrono::DateTime<Utc>>,
+    pub action: ImportAction,
+    /// absent for events a dry run would create and for failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub cancelled: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub items: Vec<ImportItem>,
+}
+
+/// a page of results, **`next_cursor`** is absent on the last page
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+/// query of **`GET /event/search`**, the last word of **`q`** also matches as a prefix,</br>
+/// **`cursor`** is the **`next_cursor`** of the previous page
+#[derive(Debug, Deserialize)]
+pub struct EventSearchQuery {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+/// an event found by full-text search, **`title_highlight`** and **`snippet`** are html-escaped</br>
+/// with the matched words wrapped in **`<mark>`**
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct EventSearchHit {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub event: Event,
+    pub rank: f32,
+    pub title_highlight: String,
+    pub snippet: String,
+    /// the next occurrences of a recurring event
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub occurrences: Vec<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRoleDto {
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EventRoleDto {
+    pub user_id: Uuid,
+    pub role: EventRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetEventRoleDto {
+    pub role: EventRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransferOwnershipDto {
+    pub user_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InvitationQuery {
+    pub recipient: Uuid,
+}
+
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct ParticipantDto {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Routes {
     pub event: Vec<String>,
     pub user: Vec<String>,
-    pub auth: Vec<String>
+    pub auth: Vec<String>,
+    pub admin: Vec<String>
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use actix_web::{
     error,
-    http::{header::ContentType, StatusCode},
+    http::{header::{ContentType, RETRY_AFTER}, StatusCode},
     HttpResponse,
 };
 use derive_more::{Display, Error};
@@ -26,12 +26,26 @@ pub enum MyError {
     TokenExpirationError,
 
     #[display(fmt = "unauthorized")]
-    Unauthorized
+    Unauthorized,
+
+    #[display(fmt = "forbidden")]
+    Forbidden,
+
+    #[display(fmt = "not found")]
+    NotFound,
+
+    /// **`retry_after`** is sent back in seconds in the **`Retry-After`** header
+    #[display(fmt = "too many requests")]
+    TooManyRequests { retry_after: u64 }
 }
 
 impl error::ResponseError for MyError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
+        let mut response = HttpResponse::build(self.status_code());
+        if let MyError::TooManyRequests { retry_after } = self {
+            response.insert_header((RETRY_AFTER, retry_after.to_string()));
+        }
+        response
             .insert_header(ContentType::html())
             .body(self.to_string())
     }
@@ -44,7 +58,10 @@ impl error::ResponseError for MyError {
             MyError::AuthError => StatusCode::NOT_FOUND,
             MyError::DecodeError => StatusCode::INTERNAL_SERVER_ERROR,
             MyError::TokenExpirationError => StatusCode::UNAUTHORIZED,
-            MyError::Unauthorized => StatusCode::UNAUTHORIZED
+            MyError::Unauthorized => StatusCode::UNAUTHORIZED,
+            MyError::Forbidden => StatusCode::FORBIDDEN,
+            MyError::NotFound => StatusCode::NOT_FOUND,
+            MyError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS
         }
     }
 }
@@ -0,0 +1,92 @@
+use actix_web::{Responder, web, delete, get, put, HttpResponse};
+use log::{error, info};
+use uuid::Uuid;
+
+use crate::{PGPool, dto::SetRoleDto, service::{self, rbac::{Permissions, Require}}};
+
+#[put("/users/{id}/role")]
+pub async fn set_role(
+    _admin: Require<{ Permissions::MANAGE_USERS.bits() }>,
+    id: web::Path<Uuid>,
+    dto: web::Json<SetRoleDto>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let user_id = id.into_inner();
+    let role = dto.into_inner().role;
+    match service::user::set_role(user_id, role, conn).await {
+        Ok(_) => {
+            info!("RESPONSE /ADMIN/USERS/{:?}/ROLE: {:?}", user_id, role);
+            HttpResponse::Ok().json(role)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] SET ROLE ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[get("/lockouts")]
+pub async fn get_lockouts(
+    _admin: Require<{ Permissions::MANAGE_USERS.bits() }>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::throttle::get_locked(conn).await {
+        Ok(lockouts) => {
+            info!("RESPONSE /ADMIN/LOCKOUTS: {:?}", lockouts);
+            HttpResponse::Ok().json(lockouts)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[delete("/users/{id}/lockout")]
+pub async fn clear_user_lockout(
+    _admin: Require<{ Permissions::MANAGE_USERS.bits() }>,
+    id: web::Path<Uuid>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let user_id = id.into_inner();
+    match service::throttle::clear_account(user_id, conn).await {
+        Ok(_) => {
+            info!("RESPONSE /ADMIN/USERS/{:?}/LOCKOUT: cleared", user_id);
+            HttpResponse::Ok().json("Lockout cleared")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] CLEAR LOCKOUT ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[delete("/lockouts/ip/{ip}")]
+pub async fn clear_ip_lockout(
+    _admin: Require<{ Permissions::MANAGE_USERS.bits() }>,
+    ip: web::Path<String>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let ip = ip.into_inner();
+    match service::throttle::clear_ip(&ip, conn).await {
+        Ok(_) => {
+            info!("RESPONSE /ADMIN/LOCKOUTS/IP/{:}: cleared", ip);
+            HttpResponse::Ok().json("Lockout cleared")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] CLEAR LOCKOUT ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(set_role)
+        .service(get_lockouts)
+        .service(clear_user_lockout)
+        .service(clear_ip_lockout);
+}
@@ -1,34 +1,394 @@
-use actix_web::{Responder, web, HttpResponse, HttpRequest};
+use actix_web::{Responder, web, HttpResponse, HttpResponseBuilder, HttpRequest};
 use log::{error, info};
 
-use crate::{PGPool, dto::NewUserDto, service};
+use uuid::Uuid;
 
-pub async fn login(req: HttpRequest, pool_state: web::Data<PGPool>) -> impl Responder {
+use crate::{PGPool, config::Config, dto::{DisableTwoFactorDto, ForgotPasswordDto, LoginResponse, OidcCallbackQuery, OidcLoginQuery, NewApiKeyDto, NewUserDto, LoginUserRequest, ResetPasswordDto, TokenPair, TotpCodeDto, TwoFactorLoginDto, VerifyEmailDto}, errors::MyError, service::{self, auth::UserAuthData, cookies, mail::MailSender}};
+
+/// the token pair as json, or set as cookies if the client uses cookie mode
+pub fn tokens_response(req: &HttpRequest, tokens: TokenPair) -> HttpResponse {
+    if !cookies::wants_cookies(req) {
+        return HttpResponse::Ok().json(tokens);
+    }
+    let (cookies, session) = cookies::session_cookies(tokens);
+    let mut response = HttpResponse::Ok();
+    for cookie in cookies {
+        response.cookie(cookie);
+    }
+    response.json(session)
+}
+
+/// what a login step answered, for the log, without the tokens
+fn login_outcome(response: &LoginResponse) -> &'static str {
+    match response {
+        LoginResponse::Tokens(_) => "token pair issued",
+        LoginResponse::TwoFactorRequired(_) => "2fa challenge issued",
+    }
+}
+
+fn login_response(req: &HttpRequest, response: LoginResponse) -> HttpResponse {
+    match response {
+        LoginResponse::Tokens(tokens) => tokens_response(req, tokens),
+        challenge => HttpResponse::Ok().json(challenge),
+    }
+}
+
+/// expires the auth cookies of clients in cookie mode
+pub fn clearing_cookies(req: &HttpRequest) -> HttpResponseBuilder {
+    let mut response = HttpResponse::Ok();
+    if cookies::wants_cookies(req) {
+        for cookie in cookies::clear_cookies() {
+            response.cookie(cookie);
+        }
+    }
+    response
+}
+
+pub async fn login(req: HttpRequest, dto: web::Json<LoginUserRequest>, pool_state: web::Data<PGPool>) -> impl Responder {
     let conn: &PGPool = pool_state.get_ref();
-    let response = service::auth::jwt::login(conn, req).await;
+    let client = service::auth::jwt::client_info(&req);
+    let response = service::auth::jwt::login(conn, dto.into_inner(), client).await;
     match response {
         Ok(val) => {
-            info!("RESPONSE /USER/LOGIN: {:?}", val);
-            HttpResponse::Ok().json(val)
+            info!("RESPONSE /AUTH/LOGIN: {:}", login_outcome(&val));
+            login_response(&req, val)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] LOGIN ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn login_second_factor(req: HttpRequest, dto: web::Json<TwoFactorLoginDto>, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let client = service::auth::jwt::client_info(&req);
+    match service::auth::jwt::login_second_factor(conn, dto.into_inner(), client).await {
+        Ok(val) => {
+            info!("RESPONSE /AUTH/LOGIN/2FA: token pair issued");
+            tokens_response(&req, val)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] LOGIN ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn oidc_login(
+    provider: web::Path<String>,
+    query: web::Query<OidcLoginQuery>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let provider = provider.into_inner();
+    match service::oidc::authorization_url(&provider, conn).await {
+        Ok(url) => {
+            info!("RESPONSE /AUTH/OIDC/{:}: redirect to provider", provider);
+            let mut response = HttpResponse::Found();
+            // the callback is reached by a browser redirect, which cannot carry the mode header
+            if query.mode.as_deref() == Some("cookie") && cookies::enabled() {
+                response.cookie(cookies::mode_cookie(service::oidc::STATE_TTL));
+            }
+            response
+                .insert_header((actix_web::http::header::LOCATION, url))
+                .finish()
+        },
+        Err(err) => {
+            error!("[{:} : {:}] OIDC LOGIN ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn oidc_callback(
+    req: HttpRequest,
+    provider: web::Path<String>,
+    query: web::Query<OidcCallbackQuery>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let provider = provider.into_inner();
+    let OidcCallbackQuery { code, state, error } = query.into_inner();
+    let Some(code) = code.filter(|_| error.is_none()) else {
+        error!("[{:} : {:}] OIDC PROVIDER ERROR: {:?}", file!(), line!(), error);
+        return HttpResponse::from_error(MyError::Unauthorized);
+    };
+    let client = service::auth::jwt::client_info(&req);
+    match service::oidc::callback(&provider, code, &state, client, conn).await {
+        Ok(val) => {
+            info!("RESPONSE /AUTH/OIDC/{:}/CALLBACK: {:}", provider, login_outcome(&val));
+            login_response(&req, val)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] OIDC CALLBACK ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn refresh(req: HttpRequest, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let response = service::auth::jwt::refresh_pair(conn, req.clone()).await;
+    match response {
+        Ok(val) => {
+            info!("RESPONSE /AUTH/REFRESH: token pair issued");
+            tokens_response(&req, val)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] REFRESH ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn logout(req: HttpRequest, user_auth_data: UserAuthData, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let Some(session_id) = user_auth_data.session_id() else {
+        return HttpResponse::from_error(MyError::BadClientData);
+    };
+    match service::session::revoke(session_id, conn).await {
+        Ok(_) => {
+            info!("RESPONSE /AUTH/LOGOUT: session {:?} revoked", session_id);
+            clearing_cookies(&req).json("Logged out")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn logout_all(req: HttpRequest, user_auth_data: UserAuthData, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::session::revoke_all(user_auth_data.user_id, conn).await {
+        Ok(revoked) => {
+            info!("RESPONSE /AUTH/LOGOUT-ALL: {revoked} sessions revoked");
+            clearing_cookies(&req).json(revoked)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn sessions(user_auth_data: UserAuthData, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let Some(session_id) = user_auth_data.session_id() else {
+        return HttpResponse::from_error(MyError::BadClientData);
+    };
+    let response = service::session::list(
+        user_auth_data.user_id, 
+        session_id, 
+        conn
+    ).await;
+    match response {
+        Ok(sessions) => {
+            info!("RESPONSE /AUTH/SESSIONS: {:?}", sessions);
+            HttpResponse::Ok().json(sessions)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn create_api_key(
+    user_auth_data: UserAuthData,
+    dto: web::Json<NewApiKeyDto>,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.api_keys {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    match service::api_key::create(user_auth_data.user_id, dto.into_inner(), conn).await {
+        Ok(key) => {
+            info!("RESPONSE /AUTH/API-KEYS: created key {:?}", key.id);
+            HttpResponse::Created().json(key)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] CREATE API KEY ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn api_keys(user_auth_data: UserAuthData, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::api_key::list(user_auth_data.user_id, conn).await {
+        Ok(keys) => {
+            info!("RESPONSE /AUTH/API-KEYS: {:?}", keys);
+            HttpResponse::Ok().json(keys)
         },
         Err(err) => {
             error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
-            HttpResponse::InternalServerError().json(err)
+            HttpResponse::from_error(err)
         }
     }
 }
 
-pub async fn register(dto: web::Json<NewUserDto>, pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn revoke_api_key(user_auth_data: UserAuthData, id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
     let conn: &PGPool = pool_state.get_ref();
-    let response = service::user::create(dto.0, conn).await;
+    let id = id.into_inner();
+    match service::api_key::revoke(id, user_auth_data.user_id, conn).await {
+        Ok(_) => {
+            info!("RESPONSE /AUTH/API-KEYS/{:?}: revoked", id);
+            HttpResponse::Ok().json("Api key revoked")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] REVOKE API KEY ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn enroll_two_factor(user_auth_data: UserAuthData, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::two_factor::enroll(&user_auth_data, conn).await {
+        Ok(enrollment) => {
+            info!("RESPONSE /AUTH/2FA/ENROLL: enrollment started for {:?}", user_auth_data.user_id);
+            HttpResponse::Ok().json(enrollment)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] 2FA ENROLL ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn confirm_two_factor(user_auth_data: UserAuthData, dto: web::Json<TotpCodeDto>, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::two_factor::confirm(&user_auth_data, &dto.code, conn).await {
+        Ok(codes) => {
+            info!("RESPONSE /AUTH/2FA/CONFIRM: 2fa enabled for {:?}", user_auth_data.user_id);
+            HttpResponse::Ok().json(codes)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] 2FA CONFIRM ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn disable_two_factor(req: HttpRequest, user_auth_data: UserAuthData, dto: web::Json<DisableTwoFactorDto>, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let client = service::auth::jwt::client_info(&req);
+    match service::two_factor::disable(&user_auth_data, dto.into_inner(), client, conn).await {
+        Ok(_) => {
+            info!("RESPONSE /AUTH/2FA/DISABLE: 2fa disabled for {:?}", user_auth_data.user_id);
+            HttpResponse::Ok().json("Two-factor authentication disabled")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] 2FA DISABLE ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn jwks() -> impl Responder {
+    HttpResponse::Ok().json(service::keys::store().jwks())
+}
+
+pub async fn verify_email(dto: web::Json<VerifyEmailDto>, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::email_verification::verify(&dto.token, conn).await {
+        Ok(_) => {
+            info!("RESPONSE /AUTH/VERIFY-EMAIL: email verified");
+            HttpResponse::Ok().json("Email verified")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] VERIFY EMAIL ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn resend_verification(
+    user_auth_data: UserAuthData,
+    mailer: web::Data<dyn MailSender>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::email_verification::resend(user_auth_data.user_id, mailer.get_ref(), conn).await {
+        Ok(_) => {
+            info!("RESPONSE /AUTH/VERIFY-EMAIL/RESEND: mail sent to user {:?}", user_auth_data.user_id);
+            HttpResponse::Ok().json("Verification mail sent")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] RESEND VERIFICATION ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn forgot_password(
+    dto: web::Json<ForgotPasswordDto>,
+    mailer: web::Data<dyn MailSender>,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.password_reset {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    match service::password_reset::forgot(&dto.email, mailer.get_ref(), conn).await {
+        Ok(_) => {
+            info!("RESPONSE /AUTH/PASSWORD/FORGOT: reset requested");
+            HttpResponse::Ok().json("If the address is registered, a reset token has been sent")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] FORGOT PASSWORD ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn reset_password(dto: web::Json<ResetPasswordDto>, config: web::Data<Config>, pool_state: web::Data<PGPool>) -> impl Responder {
+    if !config.features.password_reset {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    match service::password_reset::reset(dto.into_inner(), conn).await {
+        Ok(_) => {
+            info!("RESPONSE /AUTH/PASSWORD/RESET: password reset");
+            HttpResponse::Ok().json("Password changed")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] RESET PASSWORD ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+pub async fn register(
+    req: HttpRequest,
+    dto: web::Json<NewUserDto>,
+    mailer: web::Data<dyn MailSender>,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.registration {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    let client = service::auth::jwt::client_info(&req);
+    if let Err(err) = service::throttle::check_registration(&client, conn).await {
+        error!("[{:} : {:}] REGISTER ERROR: {:?}", file!(), line!(), err);
+        return HttpResponse::from_error(err);
+    }
+    let response = service::user::create(dto.0, mailer.get_ref(), conn).await;
     match response {
         Ok(val) => {
             info!("RESPONSE /USER/LOGIN: {:?}", val);
             HttpResponse::Ok().json(val)
         },
         Err(err) => {
-            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
-            HttpResponse::InternalServerError().json("Failed to create new user")
+            error!("[{:} : {:}] REGISTER ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
         }
     }
 }
@@ -1,85 +1,145 @@
-use actix_web::{Responder, web, get, post, put, HttpResponse, HttpRequest, HttpMessage};
+use actix_web::{Responder, web, get, post, put, delete, HttpMessage, HttpRequest, HttpResponse, http::header::{ContentDisposition, DispositionParam, DispositionType}};
 use log::{info, error};
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
-use crate::{PGPool, service::{auth::UserAuthData, self}, dto::{NewEventDto, UpdateEventDto}, errors::MyError};
+use crate::{PGPool, errors::MyError, service::{auth::UserAuthData, rbac::{Permissions, Require}, self}, dto::{EventQuery, EventSearchQuery, ImportQuery, NewEventDto, OccurrenceDto, RecurrenceDto, SubscribeQuery, UpdateEventDto, SetEventRoleDto, TransferOwnershipDto, InvitationQuery}};
 
-#[get("/")]
-pub async fn get_all(pool_state: web::Data<PGPool>) -> impl Responder {
+/// **`GET /event`**, registered for the scope path with and without a trailing slash
+pub async fn get_all(user_auth_data: UserAuthData, query: web::Query<EventQuery>, pool_state: web::Data<PGPool>) -> impl Responder {
    let conn: &PGPool = pool_state.get_ref();
-   let res = service::event::get_all(conn)
+   let res = service::event::get_all(&user_auth_data, query.into_inner(), conn)
       .await;
    match res {
-      Ok(events) => {
-         info!("RESPONSE EVENT/: events");
-         HttpResponse::Ok().json(events)
+      Ok(page) => {
+         info!("RESPONSE EVENT/: {} events", page.items.len());
+         HttpResponse::Ok().json(page)
       },
       Err(err) => {
-         error!("INTERNAL SERVER ERROR: {:?}", err);
-         HttpResponse::InternalServerError().json(err)
+         error!("[{:} : {:}] GET EVENTS ERROR: {:?}", file!(), line!(), err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[get("/search")]
+pub async fn search(user_auth_data: UserAuthData, query: web::Query<EventSearchQuery>, pool_state: web::Data<PGPool>) -> impl Responder {
+   let conn: &PGPool = pool_state.get_ref();
+   match service::event::search(&user_auth_data, query.into_inner(), conn).await {
+      Ok(page) => {
+         info!("RESPONSE EVENT/SEARCH: {} events", page.items.len());
+         HttpResponse::Ok().json(page)
+      },
+      Err(err) => {
+         error!("[{:} : {:}] SEARCH EVENTS ERROR: {:?}", file!(), line!(), err);
+         HttpResponse::from_error(err)
       }
    }
 }
 
 #[post("/create")]
-pub async fn create(req: HttpRequest, new_event_dto: web::Json<NewEventDto>, pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn create(
+   Require(user_auth_data): Require<{ Permissions::CREATE_EVENT.bits() }>, 
+   new_event_dto: web::Json<NewEventDto>, 
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
    let conn: &PGPool = pool_state.get_ref();
    let new_event = new_event_dto.into_inner();
-   match req.extensions().get::<UserAuthData>() {
-      Some(user_auth_data) => {
-         let response_result = service::event::create(user_auth_data, new_event, conn)
-         .await;
-         match response_result {
-            Ok(response) => {
-               info!("RESPONSE EVENT/CREATE: {response}");
-               HttpResponse::Ok().json(response)
-            }, 
-            Err(err) => {
-               error!("INTERNAL SERVER ERROR: {:?}", err);
-               HttpResponse::InternalServerError().json(err)
-            }
-         }
+   let response_result = service::event::create(&user_auth_data, new_event, conn)
+      .await;
+   match response_result {
+      Ok(response) => {
+         info!("RESPONSE EVENT/CREATE: {response}");
+         HttpResponse::Ok().json(response)
       }, 
-      None => {
-         error!("INTERNAL SERVER ERROR: {:?}", MyError::Unauthorized);
-         HttpResponse::InternalServerError().json(MyError::Unauthorized)
+      Err(err) => {
+         error!("[{:} : {:}] CREATE EVENT ERROR: {:?}", file!(), line!(), err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+/// **`POST /event/import`**, the body is an iCalendar file sent as **`text/calendar`**</br>
+/// with **`?dry_run=true`** nothing is written and the report tells what the import would do
+#[post("/import")]
+pub async fn import(
+   Require(user_auth_data): Require<{ Permissions::CREATE_EVENT.bits() }>,
+   req: HttpRequest,
+   query: web::Query<ImportQuery>,
+   body: web::Bytes,
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   if !req.content_type().eq_ignore_ascii_case("text/calendar") {
+      error!("[{:} : {:}] IMPORT EVENTS ERROR: content type {:?}", file!(), line!(), req.content_type());
+      return HttpResponse::from_error(MyError::BadClientData);
+   }
+   let conn: &PGPool = pool_state.get_ref();
+   let dry_run = query.dry_run.unwrap_or(false);
+   match service::import::import(&user_auth_data, &body, dry_run, conn).await {
+      Ok(report) => {
+         info!("RESPONSE EVENT/IMPORT: {} entries", report.items.len());
+         HttpResponse::Ok().json(report)
+      },
+      Err(err) => {
+         error!("[{:} : {:}] IMPORT EVENTS ERROR: {:?}", file!(), line!(), err);
+         HttpResponse::from_error(err)
       }
    }
 }
 
 #[post("/{id}/subscribe")]
-pub async fn subscribe(req: HttpRequest, event_id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn subscribe(
+   user_auth_data: UserAuthData,
+   event_id: web::Path<Uuid>,
+   query: web::Query<SubscribeQuery>,
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
    let conn: &PGPool = pool_state.get_ref();
    let id: Uuid = event_id.into_inner();
-   match req.extensions().get::<UserAuthData>() {
-      Some(user_auth_data) => {
-         let res = service::event::subscribe(
-            id.clone(), 
-            user_auth_data.user_id, 
-            conn
-         ).await;
-         match res {
-            Ok(val)  => {
-               info!("RESPONSE EVENT/{:?}/SUBSCRIBE: {val}", id);
-               HttpResponse::Ok().json(val)
-            },
-            Err(err) => {
-               error!("INTERNAL SERVER ERROR: {:?}", err);
-               HttpResponse::InternalServerError().json(err)
-            }
-         }
+   let res = service::event::subscribe(
+      id, 
+      user_auth_data.user_id, 
+      query.into_inner().occurrence,
+      conn
+   ).await;
+   match res {
+      Ok(val)  => {
+         info!("RESPONSE EVENT/{:?}/SUBSCRIBE: {val}", id);
+         HttpResponse::Ok().json(val)
+      },
+      Err(err) => {
+         error!("[{:} : {:}] SUBSCRIBE ERROR: {:?}", file!(), line!(), err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[get("/{id}.ics")]
+pub async fn get_ics(user_auth_data: UserAuthData, id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+   let conn: &PGPool = pool_state.get_ref();
+   let event_id = id.into_inner();
+   match service::event::get_ics(event_id, &user_auth_data, conn).await {
+      Ok(calendar) => {
+         info!("RESPONSE EVENT/{:?}.ICS", event_id);
+         HttpResponse::Ok()
+            .content_type(service::icalendar::CONTENT_TYPE)
+            .insert_header(ContentDisposition {
+               disposition: DispositionType::Attachment,
+               parameters: vec![DispositionParam::Filename(format!("{}.ics", event_id))],
+            })
+            .body(calendar)
       },
-      None => {
-         error!("INTERNAL SERVER ERROR: {:?}", MyError::AuthError);
-         HttpResponse::from_error(MyError::AuthError)
+      Err(err) => {
+         error!("[{:} : {:}] EVENT ICS ERROR: {:?}", file!(), line!(), err);
+         HttpResponse::from_error(err)
       }
    }
 }
 
 #[get("/{id}")]
-pub async fn get_by_id(id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn get_by_id(user_auth_data: UserAuthData, id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
    let conn: &PGPool = pool_state.get_ref();
    let event_id = id.into_inner();
-   let res = service::event::get_by_id(event_id.clone(), conn)
+   let res = service::event::get_by_id(event_id, &user_auth_data, conn)
       .await;
    match res {
       Ok(event) => {
@@ -87,8 +147,8 @@ pub async fn get_by_id(id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> im
          HttpResponse::Ok().json(event)
       }
       Err(err) => {
-         error!("INTERNAL SERVER ERROR: {:?}", err);
-         HttpResponse::InternalServerError().json(err)
+         error!("[{:} : {:}] EVENT ERROR: {:?}", file!(), line!(), err);
+         HttpResponse::from_error(err)
       }
    }
 }
@@ -97,46 +157,43 @@ pub async fn get_by_id(id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> im
 pub async fn update(
    id: web::Path<Uuid>, 
    update_event_dto: web::Json<UpdateEventDto>, 
-   req: HttpRequest,
+   user_auth_data: UserAuthData,
    pool_state: web::Data<PGPool>
 ) -> impl Responder {
    let conn = pool_state.get_ref();
    let event_fields = update_event_dto.into_inner();
    let event_id = id.into_inner();
-   match req.extensions().get::<UserAuthData>() {
-      Some(user_auth_data) => {
-         let update_res = service::event::update(
-            event_id.clone(), 
-            event_fields, 
-            user_auth_data, 
-            conn
-         ).await;
-         match update_res {
-            Ok(_) => {
-               info!("RESPONSE EVENT/UPDATE/{:?}: Update successfull", event_id);
-               HttpResponse::Ok().json("Update successfull")
-            }
-            Err(err) => {
-               error!("INTERNAL SERVER ERROR: {:?}", err);
-               HttpResponse::InternalServerError().json(err)
-            }
-         }
-      }, 
-      None => todo!()
+   let update_res = service::event::update(
+      event_id, 
+      event_fields, 
+      &user_auth_data, 
+      conn
+   ).await;
+   match update_res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/UPDATE/{:?}: Update successfull", event_id);
+         HttpResponse::Ok().json("Update successfull")
+      }
+      Err(err) => {
+         error!("EVENT UPDATE ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
    }
 }
 
 #[post("/{id}/invitation")]
 pub async fn create_invitation(
    event_id: web::Path<Uuid>,
-   recipient: web::Query<Uuid>,
+   query: web::Query<InvitationQuery>,
+   user_auth_data: UserAuthData,
    pool_state: web::Data<PGPool>
 ) -> impl Responder {
    let conn = pool_state.get_ref();
    let id = event_id.into_inner();
    let res = service::event::create_invitation(
-      id.clone(), 
-      recipient.into_inner(),
+      id, 
+      &user_auth_data,
+      query.into_inner().recipient,
       conn
    ).await;
    match res {
@@ -145,48 +202,245 @@ pub async fn create_invitation(
          HttpResponse::Created().json("Invitation created")
       }
       Err(err) => {
-         error!("INTERNAL SERVER ERROR: {:?}", err);
-         HttpResponse::InternalServerError().json(err)
+         error!("EVENT INVITATION ERROR: {:?}", err);
+         HttpResponse::from_error(err)
       }
    }
 }
 
 #[get("/{id}/accept-invitation")]
-pub async fn accept_invitation(req: HttpRequest, event_id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn accept_invitation(user_auth_data: UserAuthData, event_id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
    let conn = pool_state.get_ref();
    let id = event_id.into_inner();
-   match req.extensions().get::<UserAuthData>() {
-      Some(user_auth_data) => {
-         let recipient = user_auth_data.user_id;
-         let res = service::event::subscribe(id.clone(), recipient, conn)
-            .await;
-         match res {
-            Ok(_) => {
-               info!("RESPONSE EVENT/{:?}/ACCEPT-INVITATION: Invitaion accepted", id);
-               HttpResponse::Ok().json("Invitation accepted")
-            }
-            Err(err) => {
-               error!("INTERNAL SERVER ERROR: {:?}", err);
-               HttpResponse::from_error(err)
-            }
-         }
-      },
-      None => {
-         error!("INTERNAL SERVER ERROR: {:?}", MyError::AuthError);
-         HttpResponse::from_error(MyError::AuthError)
+   let recipient = user_auth_data.user_id;
+   let res = service::event::subscribe(id, recipient, None, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/ACCEPT-INVITATION: Invitaion accepted", id);
+         HttpResponse::Ok().json("Invitation accepted")
+      }
+      Err(err) => {
+         error!("INTERNAL SERVER ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[get("/{id}/participants")]
+pub async fn get_participants(user_auth_data: UserAuthData, event_id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let id = event_id.into_inner();
+   let res = service::event::get_participants(id, &user_auth_data, conn)
+      .await;
+   match res {
+      Ok(participants) => {
+         info!("RESPONSE EVENT/{:?}/PARTICIPANTS: {:?}", id, participants);
+         HttpResponse::Ok().json(participants)
+      }
+      Err(err) => {
+         error!("INTERNAL SERVER ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[delete("/{id}/participants/{user_id}")]
+pub async fn remove_participant(
+   path: web::Path<(Uuid, Uuid)>, 
+   user_auth_data: UserAuthData, 
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let (id, user_id) = path.into_inner();
+   let res = service::event::remove_participant(id, &user_auth_data, user_id, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/PARTICIPANTS/{:?}: participant removed", id, user_id);
+         HttpResponse::Ok().json("Participant removed")
+      }
+      Err(err) => {
+         error!("EVENT PARTICIPANT ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[get("/{id}/roles")]
+pub async fn get_roles(event_id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let id = event_id.into_inner();
+   let res = service::event_role::get_all(id, conn)
+      .await;
+   match res {
+      Ok(roles) => {
+         info!("RESPONSE EVENT/{:?}/ROLES: {:?}", id, roles);
+         HttpResponse::Ok().json(roles)
+      }
+      Err(err) => {
+         error!("EVENT ROLES ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[put("/{id}/roles/{user_id}")]
+pub async fn set_role(
+   path: web::Path<(Uuid, Uuid)>, 
+   dto: web::Json<SetEventRoleDto>,
+   user_auth_data: UserAuthData, 
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let (id, user_id) = path.into_inner();
+   let role = dto.into_inner().role;
+   let res = service::event_role::set(id, &user_auth_data, user_id, role, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/ROLES/{:?}: {:?}", id, user_id, role);
+         HttpResponse::Ok().json(role)
+      }
+      Err(err) => {
+         error!("EVENT ROLES ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[delete("/{id}/roles/{user_id}")]
+pub async fn remove_role(
+   path: web::Path<(Uuid, Uuid)>, 
+   user_auth_data: UserAuthData, 
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let (id, user_id) = path.into_inner();
+   let res = service::event_role::remove(id, &user_auth_data, user_id, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/ROLES/{:?}: role removed", id, user_id);
+         HttpResponse::Ok().json("Role removed")
+      }
+      Err(err) => {
+         error!("EVENT ROLES ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
+
+#[post("/{id}/roles/transfer")]
+pub async fn transfer_ownership(
+   event_id: web::Path<Uuid>, 
+   dto: web::Json<TransferOwnershipDto>,
+   user_auth_data: UserAuthData, 
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let id = event_id.into_inner();
+   let new_owner = dto.into_inner().user_id;
+   let res = service::event_role::transfer_ownership(id, &user_auth_data, new_owner, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/ROLES/TRANSFER: new owner {:?}", id, new_owner);
+         HttpResponse::Ok().json("Ownership transferred")
+      }
+      Err(err) => {
+         error!("EVENT ROLES ERROR: {:?}", err);
+         HttpResponse::from_error(err)
       }
    }
+}
 
+#[put("/{id}/recurrence")]
+pub async fn set_recurrence(
+   event_id: web::Path<Uuid>,
+   dto: web::Json<RecurrenceDto>,
+   user_auth_data: UserAuthData,
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let id = event_id.into_inner();
+   let res = service::event::set_recurrence(id, dto.into_inner(), &user_auth_data, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/RECURRENCE: recurrence updated", id);
+         HttpResponse::Ok().json("Recurrence updated")
+      }
+      Err(err) => {
+         error!("EVENT RECURRENCE ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
 }
 
+#[put("/{id}/occurrences/{occurrence}")]
+pub async fn update_occurrence(
+   path: web::Path<(Uuid, DateTime<Utc>)>,
+   dto: web::Json<OccurrenceDto>,
+   user_auth_data: UserAuthData,
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let (id, occurrence) = path.into_inner();
+   let res = service::event::update_occurrence(id, occurrence, dto.into_inner(), &user_auth_data, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/OCCURRENCES/{:?}: occurrence updated", id, occurrence);
+         HttpResponse::Ok().json("Occurrence updated")
+      }
+      Err(err) => {
+         error!("EVENT OCCURRENCE ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
 
+#[delete("/{id}/occurrences/{occurrence}")]
+pub async fn cancel_occurrence(
+   path: web::Path<(Uuid, DateTime<Utc>)>,
+   user_auth_data: UserAuthData,
+   pool_state: web::Data<PGPool>
+) -> impl Responder {
+   let conn = pool_state.get_ref();
+   let (id, occurrence) = path.into_inner();
+   let res = service::event::cancel_occurrence(id, occurrence, &user_auth_data, conn)
+      .await;
+   match res {
+      Ok(_) => {
+         info!("RESPONSE EVENT/{:?}/OCCURRENCES/{:?}: occurrence cancelled", id, occurrence);
+         HttpResponse::Ok().json("Occurrence cancelled")
+      }
+      Err(err) => {
+         error!("EVENT OCCURRENCE ERROR: {:?}", err);
+         HttpResponse::from_error(err)
+      }
+   }
+}
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
    cfg.service(create)
+      .service(import)
+      .service(search)
       .service(update)
+      .service(get_participants)
+      .service(remove_participant)
+      .service(get_roles)
+      .service(transfer_ownership)
+      .service(set_role)
+      .service(remove_role)
+      .service(set_recurrence)
+      .service(update_occurrence)
+      .service(cancel_occurrence)
       .service(subscribe)
       .service(create_invitation)
       .service(accept_invitation)
-      .service(get_all)
+      .service(web::resource(["", "/"]).route(web::get().to(get_all)))
+      .service(get_ics)
       .service(get_by_id);
 }
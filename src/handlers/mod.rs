@@ -1,3 +1,4 @@
 pub mod user;
 pub mod event;
-pub mod auth;
\ No newline at end of file
+pub mod auth;
+pub mod admin;
\ No newline at end of file
@@ -1,47 +1,270 @@
-use actix_web::{Responder, web, get, HttpResponse};
+use actix_web::{Responder, web, delete, get, patch, post, put, HttpResponse, HttpRequest, http::header::{ContentDisposition, DispositionParam, DispositionType}};
+use log::{error, info};
 use uuid::Uuid;
 
-use crate::PGPool;
-use crate::service;
+use crate::{config::Config, errors::MyError, PGPool};
+use crate::dto::{ChangePasswordDto, DeleteAccountDto, ExportQuery, PrivacySettings, UpdateProfileDto};
+use crate::service::{self, auth::UserAuthData, mail::MailSender};
 
 #[get("/")]
-pub async fn get_all(pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn get_all(user_auth_data: UserAuthData, pool_state: web::Data<PGPool>) -> impl Responder {
     let conn: &PGPool = pool_state.get_ref();
-    println!("{:?}", "GET ALL");
-    let response = service::user::get_all(conn).await;
+    let response = service::user::get_all(&user_auth_data, conn).await;
     match response {
         Ok(users) => HttpResponse::Ok().json(users),
-        Err(err) => HttpResponse::InternalServerError().json(err)
+        Err(err) => {
+            error!("[{:} : {:}] GET USERS ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[get("/me")]
+pub async fn get_me(user_auth_data: UserAuthData, pool_state: web::Data<PGPool>) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::user::get_me(&user_auth_data, conn).await {
+        Ok(profile) => HttpResponse::Ok().json(profile),
+        Err(err) => {
+            error!("[{:} : {:}] GET PROFILE ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[patch("/me")]
+pub async fn update_me(
+    user_auth_data: UserAuthData,
+    dto: web::Json<UpdateProfileDto>,
+    mailer: web::Data<dyn MailSender>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::user::update_me(&user_auth_data, dto.into_inner(), mailer.get_ref(), conn).await {
+        Ok(profile) => {
+            info!("RESPONSE /USER/ME: profile of {:?} updated", user_auth_data.user_id);
+            HttpResponse::Ok().json(profile)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] UPDATE PROFILE ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[delete("/me")]
+pub async fn delete_me(
+    req: HttpRequest,
+    user_auth_data: UserAuthData,
+    dto: web::Json<DeleteAccountDto>,
+    mailer: web::Data<dyn MailSender>,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.account_deletion {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    let client = service::auth::jwt::client_info(&req);
+    match service::account::delete(&user_auth_data, dto.into_inner(), client, mailer.get_ref(), conn).await {
+        Ok(deletion) => {
+            info!("RESPONSE /USER/ME: account {:?} deleted", user_auth_data.user_id);
+            super::auth::clearing_cookies(&req).json(deletion)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] DELETE ACCOUNT ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+fn attachment(filename: String) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    }
+}
+
+#[get("/me/export")]
+pub async fn export(
+    user_auth_data: UserAuthData,
+    query: web::Query<ExportQuery>,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.data_export {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    let export = match service::account::export(&user_auth_data, conn).await {
+        Ok(export) => export,
+        Err(err) => {
+            error!("[{:} : {:}] EXPORT ERROR: {:?}", file!(), line!(), err);
+            return HttpResponse::from_error(err);
+        }
+    };
+    let name = format!("export-{}", user_auth_data.user_id.simple());
+    info!("RESPONSE /USER/ME/EXPORT: export of {:?}", user_auth_data.user_id);
+    match query.format.as_deref() {
+        None | Some("json") => HttpResponse::Ok()
+            .insert_header(attachment(format!("{name}.json")))
+            .json(export),
+        Some("zip") => match service::account::to_zip(&export) {
+            Ok(archive) => HttpResponse::Ok()
+                .content_type("application/zip")
+                .insert_header(attachment(format!("{name}.zip")))
+                .body(archive),
+            Err(err) => HttpResponse::from_error(err)
+        },
+        Some(_) => HttpResponse::from_error(MyError::BadClientData)
+    }
+}
+
+#[post("/me/calendar-feed")]
+pub async fn create_calendar_feed(
+    user_auth_data: UserAuthData,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.calendar_feeds {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    match service::calendar_feed::create(user_auth_data.user_id, conn).await {
+        Ok(feed) => {
+            info!("RESPONSE /USER/ME/CALENDAR-FEED: feed of {:?} created", user_auth_data.user_id);
+            HttpResponse::Created().json(feed)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] CALENDAR FEED ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[delete("/me/calendar-feed")]
+pub async fn revoke_calendar_feed(
+    user_auth_data: UserAuthData,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.calendar_feeds {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    match service::calendar_feed::revoke(user_auth_data.user_id, conn).await {
+        Ok(()) => {
+            info!("RESPONSE /USER/ME/CALENDAR-FEED: feed of {:?} revoked", user_auth_data.user_id);
+            HttpResponse::Ok().json("Calendar feed revoked")
+        },
+        Err(err) => {
+            error!("[{:} : {:}] CALENDAR FEED ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+/// **`GET /calendar/{token}.ics`**, outside the **`/user`** scope since the token is the only credential
+pub async fn calendar_feed(
+    token: web::Path<String>,
+    config: web::Data<Config>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    if !config.features.calendar_feeds {
+        return HttpResponse::from_error(MyError::NotFound);
+    }
+    let conn: &PGPool = pool_state.get_ref();
+    match service::calendar_feed::render(&token.into_inner(), conn).await {
+        Ok(calendar) => HttpResponse::Ok()
+            .content_type(service::icalendar::CONTENT_TYPE)
+            .insert_header(("Cache-Control", "private, no-cache"))
+            .body(calendar),
+        Err(err) => {
+            // the path holds the token, so it is not logged
+            error!("[{:} : {:}] CALENDAR FEED ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
+#[put("/me/privacy")]
+pub async fn set_privacy(
+    user_auth_data: UserAuthData,
+    dto: web::Json<PrivacySettings>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    match service::user::set_privacy(&user_auth_data, dto.into_inner(), conn).await {
+        Ok(privacy) => {
+            info!("RESPONSE /USER/ME/PRIVACY: {:?}", privacy);
+            HttpResponse::Ok().json(privacy)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] SET PRIVACY ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
     }
 }
 
 #[get("/{id}")]
-pub async fn get_by_id(id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn get_by_id(user_auth_data: UserAuthData, id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
     let conn: &PGPool = pool_state.get_ref();
     let user_id = id.into_inner();
-    println!("GET BY ID: {:?} ", user_id);
-    let response = service::user::get_by_id(user_id, conn).await;
+    let response = service::user::get_by_id(user_id, &user_auth_data, conn).await;
     match response {
         Ok(user) => HttpResponse::Ok().json(user),
-        Err(err) => HttpResponse::InternalServerError().json(err)
+        Err(err) => {
+            error!("[{:} : {:}] GET USER ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
     }
 }
 
 #[get("/{id}/participations")]
-pub async fn get_user_participations(id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
+pub async fn get_user_participations(user_auth_data: UserAuthData, id: web::Path<Uuid>, pool_state: web::Data<PGPool>) -> impl Responder {
     let conn: &PGPool = pool_state.get_ref();
     let user_id = id.into_inner();
-    println!("GET PARTICIPATIONS BY ID: {:?}", user_id);
-    let response = service::user::get_user_participations(user_id, conn)
+    let response = service::user::get_user_participations(user_id, &user_auth_data, conn)
         .await;
     match response {
         Ok(events) => HttpResponse::Ok().json(events),
-        Err(err) => HttpResponse::InternalServerError().json(err)
+        Err(err) => {
+            error!("[{:} : {:}] GET PARTICIPATIONS ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
     } 
 }
 
+#[put("/me/password")]
+pub async fn change_password(
+    req: HttpRequest,
+    user_auth_data: UserAuthData,
+    dto: web::Json<ChangePasswordDto>,
+    pool_state: web::Data<PGPool>
+) -> impl Responder {
+    let conn: &PGPool = pool_state.get_ref();
+    let client = service::auth::jwt::client_info(&req);
+    match service::user::change_password(&user_auth_data, dto.into_inner(), client, conn).await {
+        Ok(tokens) => {
+            info!("RESPONSE /USER/ME/PASSWORD: password changed for {:?}", user_auth_data.user_id);
+            super::auth::tokens_response(&req, tokens)
+        },
+        Err(err) => {
+            error!("[{:} : {:}] CHANGE PASSWORD ERROR: {:?}", file!(), line!(), err);
+            HttpResponse::from_error(err)
+        }
+    }
+}
+
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
     cfg.service(get_all)
+        .service(get_me)
+        .service(update_me)
+        .service(delete_me)
+        .service(export)
+        .service(create_calendar_feed)
+        .service(revoke_calendar_feed)
+        .service(set_privacy)
+        .service(change_password)
         .service(get_by_id)
         .service(get_user_participations);
 }
\ No newline at end of file
@@ -4,75 +4,202 @@ pub mod service;
 pub mod models;
 pub mod dto;
 pub mod errors;
+pub mod config;
 
 use actix_web::{HttpServer, App, web, HttpResponse};
+use config::Config;
 use db::init_db_pool;
 use dto::Routes;
 use service::{auth::AuthMiddleware, log::LoggerMiddleware};
 use sqlx::{postgres::Postgres, Pool};
-use dotenv::dotenv;
-use std::env;
 
 type PGPool = Pool<Postgres>;
 
-const ACCESS_TOKEN_EXP: usize = 60 * 60 * 1000 * 1000;
-const REFRESH_TOKEN_EXP: usize = 5 * 24 * 60 * 60 * 1000 * 1000;
 
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()>{
-    dotenv().ok();
-    let db_url = env::var("DATABASE_URL")
+    let config = Config::load()
     .unwrap_or_else(|e| {
-        panic!("Failed to get env with name 'DATABASE_URL': {:?}", e);
+        panic!("Invalid configuration: {}", e);
     });
-    let pool: PGPool = init_db_pool(&db_url).await;
+    config::init(config.clone())
+    .unwrap_or_else(|e| {
+        panic!("Failed to initialize configuration: {}", e);
+    });
+    service::log::init_logger(&config.log.level);
+    let pool: PGPool = init_db_pool(&config.database).await;
 
     let info = || async {
         let routes = Routes { 
-            auth: vec!["/login".to_string(), "register".to_string()], 
+            auth: vec![
+                "/login".to_string(), 
+                "/refresh".to_string(), 
+                "/logout".to_string(),
+                "/logout-all".to_string(),
+                "/sessions".to_string(),
+                "/api-keys".to_string(),
+                "/api-keys/{id}".to_string(),
+                "/verify-email".to_string(),
+                "/verify-email/resend".to_string(),
+                "/password/forgot".to_string(),
+                "/password/reset".to_string(),
+                "/login/2fa".to_string(),
+                "/oidc/{provider}".to_string(),
+                "/oidc/{provider}/callback".to_string(),
+                "/2fa/enroll".to_string(),
+                "/2fa/confirm".to_string(),
+                "/2fa/disable".to_string(),
+                "register".to_string()
+            ], 
             event: vec![
                 "/create".to_string(), 
                 "/{id}/subscribe".to_string(),
                 "/".to_string(),
+                "/search".to_string(),
                 "/{id}".to_string(),
                 "/update/{id}".to_string(),
-                "/{id}/invitaion".to_string(),
-                "/{id}/accept-invitation".to_string()
+                "/{id}/invitation".to_string(),
+                "/{id}/accept-invitation".to_string(),
+                "/{id}/participants".to_string(),
+                "/{id}/participants/{user_id}".to_string(),
+                "/{id}/roles".to_string(),
+                "/{id}/roles/{user_id}".to_string(),
+                "/{id}/roles/transfer".to_string(),
+                "/{id}/recurrence".to_string(),
+                "/{id}/occurrences/{occurrence}".to_string(),
+                "/{id}.ics".to_string(),
+                "/import".to_string()
             ], 
             user: vec![
                 "/".to_string(),
                 "/{id}".to_string(),
-                "/{id}/participations".to_string()
+                "/{id}/participations".to_string(),
+                "/me".to_string(),
+                "/me/export".to_string(),
+                "/me/calendar-feed".to_string(),
+                "/me/password".to_string(),
+                "/me/privacy".to_string()
+            ],
+            admin: vec![
+                "/users/{id}/role".to_string(),
+                "/users/{id}/lockout".to_string(),
+                "/lockouts".to_string(),
+                "/lockouts/ip/{ip}".to_string()
             ]
         };
         
         HttpResponse::Ok().json(routes)
     };
-    service::log::init_logger();
-    HttpServer::new(move || {
+    service::keys::init()
+    .unwrap_or_else(|e| {
+        panic!("Failed to load jwt keys: {}", e);
+    });
+    service::credentials::init()
+    .unwrap_or_else(|e| {
+        panic!("Failed to configure credential backends: {}", e);
+    });
+    service::oidc::init()
+    .unwrap_or_else(|e| {
+        panic!("Failed to configure oidc providers: {}", e);
+    });
+    let mailer = service::mail::from_config()
+    .unwrap_or_else(|e| {
+        panic!("Failed to configure mail: {}", e);
+    });
+    let app_config = web::Data::new(config.clone());
+    let accept_api_keys = config.features.api_keys;
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(pool.clone()))
+            .app_data(web::Data::from(mailer.clone()))
+            .app_data(app_config.clone())
             .route("/", web::get().to(info))
+            .route("/.well-known/jwks.json", web::get().to(handlers::auth::jwks))
             .service(
                 web::scope("/user")
+                    .wrap(AuthMiddleware::register(pool.clone()))
                     .wrap(LoggerMiddleware) 
                     .configure(handlers::user::init_routes)  
             )
             .service(
                 web::scope("/event")
-                    .wrap(AuthMiddleware::register(pool.clone()))
+                    .wrap(if accept_api_keys {
+                        AuthMiddleware::with_api_keys(pool.clone())
+                    } else {
+                        AuthMiddleware::register(pool.clone())
+                    })
                     .wrap(LoggerMiddleware)
                     .configure(handlers::event::init_routes)
             )
+            // no LoggerMiddleware, the token in the path is the credential
+            .service(
+                web::scope("/calendar")
+                    .route("/{token}.ics", web::get().to(handlers::user::calendar_feed))
+            )
+            .service(
+                web::scope("/admin")
+                    .wrap(AuthMiddleware::register(pool.clone()))
+                    .wrap(LoggerMiddleware)
+                    .configure(handlers::admin::init_routes)
+            )
             .service(
                 web::scope("/auth")
                 .wrap(LoggerMiddleware)
                     .route("/login", web::post().to(handlers::auth::login))
+                    .route("/login/2fa", web::post().to(handlers::auth::login_second_factor))
+                    .route("/oidc/{provider}", web::get().to(handlers::auth::oidc_login))
+                    .route("/oidc/{provider}/callback", web::get().to(handlers::auth::oidc_callback))
+                    .route("/refresh", web::post().to(handlers::auth::refresh))
+                    .service(
+                        web::resource("/logout")
+                            .wrap(AuthMiddleware::register(pool.clone()))
+                            .route(web::post().to(handlers::auth::logout))
+                    )
+                    .service(
+                        web::resource("/logout-all")
+                            .wrap(AuthMiddleware::register(pool.clone()))
+                            .route(web::post().to(handlers::auth::logout_all))
+                    )
+                    .service(
+                        web::resource("/sessions")
+                            .wrap(AuthMiddleware::register(pool.clone()))
+                            .route(web::get().to(handlers::auth::sessions))
+                    )
+                    .service(
+                        web::resource("/api-keys")
+                            .wrap(AuthMiddleware::register(pool.clone()))
+                            .route(web::post().to(handlers::auth::create_api_key))
+                            .route(web::get().to(handlers::auth::api_keys))
+                    )
+                    .service(
+                        web::resource("/api-keys/{id}")
+                            .wrap(AuthMiddleware::register(pool.clone()))
+                            .route(web::delete().to(handlers::auth::revoke_api_key))
+                    )
+                    .service(
+                        web::scope("/2fa")
+                            .wrap(AuthMiddleware::register(pool.clone()))
+                            .route("/enroll", web::post().to(handlers::auth::enroll_two_factor))
+                            .route("/confirm", web::post().to(handlers::auth::confirm_two_factor))
+                            .route("/disable", web::post().to(handlers::auth::disable_two_factor))
+                    )
+                    .route("/verify-email", web::post().to(handlers::auth::verify_email))
+                    .service(
+                        web::resource("/verify-email/resend")
+                            .wrap(AuthMiddleware::register(pool.clone()))
+                            .route(web::post().to(handlers::auth::resend_verification))
+                    )
+                    .route("/password/forgot", web::post().to(handlers::auth::forgot_password))
+                    .route("/password/reset", web::post().to(handlers::auth::reset_password))
                     .route("register", web::post().to(handlers::auth::register))
             )
-    })
-    .bind(("127.0.0.1", 8080))?
+    });
+    let server = match config.server.workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    server.bind((config.server.host.as_str(), config.server.port))?
     .run()
     .await
 }
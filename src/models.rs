@@ -5,29 +5,69 @@ use uuid::Uuid;
 #[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
 pub struct User {
     pub id: Uuid,
+    #[serde(skip_serializing)]
     pub pwd_hash: String,
     pub username: String,
     pub email: Option<String>,
-    pub access_token: Option<String>,
-    pub refresh_token: Option<String>
+    pub role: String,
+    pub permissions: i32,
+    pub email_verified_at: Option<chrono::DateTime<Utc>>,
+    /// credential backend that owns the account, see **`service::credentials`**
+    pub auth_source: String,
+    pub show_email: bool,
+    pub show_participations: bool,
+    /// whether the user appears in **`GET /user/`**
+    pub listed: bool,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    /// IANA name, e.g. **`Europe/Berlin`**
+    pub time_zone: Option<String>,
+    /// BCP 47 tag, e.g. **`de-DE`**
+    pub locale: Option<String>
 }
 
-#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, FromRow, serde::Serialize, serde::Deserialize)]
 pub struct Event {
     pub id: Uuid,
     pub title: String,
     pub descr: String,
     pub dt: chrono::DateTime<Utc>,
     pub place: Option<String>,
-    pub creator: Uuid
+    pub creator: Uuid,
+    /// set once the event is called off
+    pub cancelled_at: Option<chrono::DateTime<Utc>>,
+    /// IANA name the recurrence is expanded in, UTC when unset
+    pub time_zone: Option<String>,
+    /// RFC 5545 recurrence rule without the **`RRULE:`** prefix, see **`service::recurrence`**
+    pub rrule: Option<String>,
+    /// starts of occurrences left out of the series
+    pub exdates: Vec<chrono::DateTime<Utc>>,
+    /// iCalendar **`SEQUENCE`**, bumped on every change
+    pub sequence: i32,
+    pub updated_at: chrono::DateTime<Utc>,
+    /// **`UID`** of an event imported from another calendar, kept when it is exported again
+    pub ical_uid: Option<String>
+}
+
+/// changes to one occurrence of a recurring event, unset fields keep the value of the series
+#[derive(Debug, Clone, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct OccurrenceOverride {
+    pub event_id: Uuid,
+    /// the start the recurrence rule gives the occurrence
+    pub occurrence_dt: chrono::DateTime<Utc>,
+    pub title: Option<String>,
+    pub descr: Option<String>,
+    pub dt: Option<chrono::DateTime<Utc>>,
+    pub place: Option<String>,
+    pub cancelled_at: Option<chrono::DateTime<Utc>>
 }
 
 #[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
 pub struct Notification {
     pub id: Uuid,
-    pub reipient: Uuid,
+    pub recipient: Uuid,
     pub content: Option<String>,
-    pub stat: u8,
+    pub stat: i32,
     pub creation_dt: chrono::DateTime<Utc>,
     pub sending_dt: Option<chrono::DateTime<Utc>>
 }
@@ -41,7 +81,106 @@ pub struct Invitation {
 }
 
 #[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
-pub struct Participation {
+pub struct Comment {
+    pub id: Uuid,
+    pub comment: String,
     pub event_id: Uuid,
     pub user_id: Uuid
 }
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct Participation {
+    pub event_id: Uuid,
+    pub user_id: Uuid,
+    /// the single occurrence of a recurring event, unset for the whole series
+    pub occurrence_dt: Option<chrono::DateTime<Utc>>
+}
+
+
+/// the private calendar feed of a user, see **`service::calendar_feed`**
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct CalendarFeed {
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub created_at: chrono::DateTime<Utc>
+}
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub refresh_jti: Uuid,
+    pub created_at: chrono::DateTime<Utc>,
+    pub last_used_at: chrono::DateTime<Utc>,
+    pub revoked_at: Option<chrono::DateTime<Utc>>,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>
+}
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct EventRoleRow {
+    pub event_id: Uuid,
+    pub user_id: Uuid,
+    pub role: String
+}
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub key_hash: String,
+    pub scopes: i32,
+    pub created_at: chrono::DateTime<Utc>,
+    pub expires_at: Option<chrono::DateTime<Utc>>,
+    pub last_used_at: Option<chrono::DateTime<Utc>>,
+    pub revoked_at: Option<chrono::DateTime<Utc>>
+}
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct OneTimeToken {
+    pub jti: Uuid,
+    pub user_id: Uuid,
+    pub purpose: String,
+    pub email: Option<String>,
+    pub created_at: chrono::DateTime<Utc>,
+    pub expires_at: chrono::DateTime<Utc>,
+    pub used_at: Option<chrono::DateTime<Utc>>
+}
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct UserTotp {
+    pub user_id: Uuid,
+    pub secret: String,
+    pub created_at: chrono::DateTime<Utc>,
+    pub confirmed_at: Option<chrono::DateTime<Utc>>,
+    pub last_step: Option<i64>
+}
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct AuthThrottle {
+    pub scope: String,
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: chrono::DateTime<Utc>,
+    pub locked_until: Option<chrono::DateTime<Utc>>
+}
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct OidcState {
+    pub state: String,
+    pub provider: String,
+    pub pkce_verifier: String,
+    pub nonce: String,
+    pub created_at: chrono::DateTime<Utc>,
+    pub expires_at: chrono::DateTime<Utc>
+}
+
+#[derive(Debug, FromRow, serde::Serialize, serde::Deserialize)]
+pub struct UserIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub user_id: Uuid,
+    pub email: Option<String>,
+    pub created_at: chrono::DateTime<Utc>
+}
@@ -0,0 +1,204 @@
+use std::io::{Cursor, Write};
+use chrono::Utc;
+use log::{error, info, warn};
+use serde::Serialize;
+use sqlx::PgConnection;
+use uuid::Uuid;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    db::{self, event::Filter},
+    dto::{AccountDeletion, AccountExport, ClientInfo, DeleteAccountDto},
+    errors::MyError,
+    models::Event,
+    PGPool,
+};
+
+use super::{
+    api_key,
+    auth::UserAuthData,
+    credentials,
+    crypto,
+    event::is_upcoming,
+    mail::{Mail, MailSender},
+    password::{self, Verification},
+    rbac::EventRole,
+    session,
+    throttle,
+    user,
+};
+
+/// prefix of anonymized usernames, no one can register or pick a name starting with it
+pub const DELETED_PREFIX: &str = "deleted-";
+/// **`deleted-`** and 16 hex digits of the id fit into **`users.username`**
+const DELETED_ID_LEN: usize = 16;
+
+/// names only account deletion hands out, compared case-insensitively
+pub fn is_reserved_username(username: &str) -> bool {
+    username.get(..DELETED_PREFIX.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(DELETED_PREFIX))
+}
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+/// everything stored about the caller
+pub async fn export(viewer: &UserAuthData, pool: &PGPool) -> Result<AccountExport, MyError> {
+    let id = viewer.user_id;
+    Ok(AccountExport {
+        exported_at: Utc::now(),
+        profile: user::get_me(viewer, pool).await?,
+        events_created: db::event::filter(Filter { creator: Some(id), ..Default::default() }, pool).await.map_err(internal)?,
+        participations: db::user::get_user_participations(id, pool).await.map_err(internal)?,
+        invitations: db::invitations::get_by_user(id, pool).await.map_err(internal)?,
+        comments: db::comment::get_by_user(id, pool).await.map_err(internal)?,
+        notifications: db::invitations::notifications::get_by_recipient(id, pool).await.map_err(internal)?,
+        sessions: session::list(id, viewer.session_id().unwrap_or_default(), pool).await?,
+        api_keys: api_key::list(id, pool).await?,
+        identities: db::oidc::get_identities(id, pool).await.map_err(internal)?,
+    })
+}
+
+fn zip_entry<T: Serialize>(archive: &mut ZipWriter<Cursor<Vec<u8>>>, name: &str, value: &T) -> Result<(), MyError> {
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let json = serde_json::to_vec_pretty(value).map_err(|_| MyError::InternalError)?;
+    archive.start_file(name, options)
+        .map_err(|err| {
+            error!("[{:} : {:}] ZIP ERROR: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })?;
+    archive.write_all(&json).map_err(|_| MyError::InternalError)
+}
+
+/// the export as a zip archive holding one json file per section
+pub fn to_zip(export: &AccountExport) -> Result<Vec<u8>, MyError> {
+    let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+    zip_entry(&mut archive, "export.json", &serde_json::json!({
+        "user_id": export.profile.id,
+        "exported_at": export.exported_at,
+    }))?;
+    zip_entry(&mut archive, "profile.json", &export.profile)?;
+    zip_entry(&mut archive, "events_created.json", &export.events_created)?;
+    zip_entry(&mut archive, "participations.json", &export.participations)?;
+    zip_entry(&mut archive, "invitations.json", &export.invitations)?;
+    zip_entry(&mut archive, "comments.json", &export.comments)?;
+    zip_entry(&mut archive, "notifications.json", &export.notifications)?;
+    zip_entry(&mut archive, "sessions.json", &export.sessions)?;
+    zip_entry(&mut archive, "api_keys.json", &export.api_keys)?;
+    zip_entry(&mut archive, "identities.json", &export.identities)?;
+    archive.finish()
+        .map(Cursor::into_inner)
+        .map_err(|err| {
+            error!("[{:} : {:}] ZIP ERROR: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })
+}
+
+/// calls the event off and stores a notification for every other participant,</br>
+/// returns the mails for those with a verified address, sent once the deletion is committed
+async fn cancel_event(event: &Event, organizer: Uuid, conn: &mut PgConnection) -> Result<Vec<(Uuid, Mail)>, MyError> {
+    db::event::cancel(event.id, &mut *conn).await.map_err(internal)?;
+    let content = format!(
+        "The event \"{}\" (#{}) on {} has been cancelled because its organizer deleted their account.",
+        event.title,
+        event.id,
+        event.dt.format("%Y-%m-%d %H:%M UTC")
+    );
+    let participants = db::event::get_participants(event.id, &mut *conn)
+        .await
+        .map_err(internal)?;
+    let mut mails = Vec::new();
+    for participant in participants.into_iter().filter(|participant| participant.user_id != organizer) {
+        let notification_id = db::invitations::notifications::create_for(participant.user_id, &content, &mut *conn)
+            .await
+            .map_err(internal)?;
+        let recipient = db::user::get_by_id(participant.user_id, &mut *conn)
+            .await
+            .map_err(internal)?;
+        let Some(email) = recipient.email.filter(|_| recipient.email_verified_at.is_some()) else {
+            continue;
+        };
+        mails.push((notification_id, Mail {
+            to: email,
+            subject: format!("Cancelled: {}", event.title),
+            body: content.clone(),
+        }));
+    }
+    Ok(mails)
+}
+
+/// mails the cancellations, a notification stays pending if its mail cannot be delivered
+async fn send_cancellations(mails: Vec<(Uuid, Mail)>, mailer: &dyn MailSender, pool: &PGPool) {
+    for (notification_id, mail) in mails {
+        if let Err(err) = mailer.send(mail).await {
+            warn!("[{:} : {:}] FAILED TO SEND CANCELLATION MAIL: {:?}", file!(), line!(), err);
+            continue;
+        }
+        if let Err(err) = db::invitations::notifications::update_status_send(&notification_id, pool).await {
+            warn!("[{:} : {:}] FAILED TO MARK NOTIFICATION AS SENT: {:?}", file!(), line!(), err);
+        }
+    }
+}
+
+/// deletes the caller's account, accounts with a local password have to confirm it with **`pwd`**,</br>
+/// wrong ones count towards the login throttle</br>
+/// events the user created go to their first co-organizer, upcoming events and running series without one</br>
+/// are cancelled and past ones are kept as they are; the user row itself is anonymized, see **`db::user::anonymize`**</br>
+/// all of it is one transaction, participants of cancelled events are mailed once it is committed
+pub async fn delete(
+    viewer: &UserAuthData,
+    dto: DeleteAccountDto,
+    client: ClientInfo,
+    mailer: &dyn MailSender,
+    pool: &PGPool
+) -> Result<AccountDeletion, MyError> {
+    let user = db::user::get_by_id(viewer.user_id, pool)
+        .await
+        .map_err(internal)?;
+    if user.auth_source == credentials::LOCAL_SOURCE {
+        let pwd = dto.pwd.ok_or(MyError::Forbidden)?;
+        throttle::check_login(&user.username, &client, pool).await?;
+        if password::verify(&pwd, &user.pwd_hash).await? == Verification::Invalid {
+            throttle::login_failed(&user.username, &client, pool).await;
+            return Err(MyError::Forbidden);
+        }
+        throttle::login_succeeded(&user.username, pool).await;
+    }
+    let mut deletion = AccountDeletion {
+        transferred_events: Vec::new(),
+        cancelled_events: Vec::new(),
+    };
+    let username = format!("{DELETED_PREFIX}{}", &user.id.simple().to_string()[..DELETED_ID_LEN]);
+    let pwd_hash = password::hash(&crypto::random_token(32)).await?;
+    let mut mails = Vec::new();
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let events = db::event::filter(Filter { creator: Some(user.id), ..Default::default() }, &mut *tx)
+        .await
+        .map_err(internal)?;
+    let now = Utc::now();
+    for event in events {
+        let successor = db::event_role::first_with_role(event.id, EventRole::CoOrganizer.as_str(), &mut *tx)
+            .await
+            .map_err(internal)?;
+        if let Some(successor) = successor {
+            db::event_role::transfer_ownership(event.id, user.id, successor, EventRole::CoOrganizer.as_str(), &mut *tx)
+                .await
+                .map_err(internal)?;
+            deletion.transferred_events.push(event.id);
+        } else if event.cancelled_at.is_none() && is_upcoming(&event, now) {
+            mails.extend(cancel_event(&event, user.id, &mut tx).await?);
+            deletion.cancelled_events.push(event.id);
+        }
+    }
+    db::user::anonymize(user.id, &username, &pwd_hash, credentials::DELETED_SOURCE, &mut *tx)
+        .await
+        .map_err(internal)?;
+    tx.commit().await.map_err(internal)?;
+    send_cancellations(mails, mailer, pool).await;
+    info!(
+        "ACCOUNT {:?} DELETED, {:} EVENTS TRANSFERRED, {:} CANCELLED",
+        user.id, deletion.transferred_events.len(), deletion.cancelled_events.len()
+    );
+    Ok(deletion)
+}
@@ -0,0 +1,156 @@
+use actix_web::{dev::ServiceRequest, http::Method};
+use chrono::Utc;
+use log::{error, warn};
+use uuid::Uuid;
+
+use crate::{db, dto::{ApiKeyCreated, ApiKeyInfo, NewApiKeyDto}, errors::MyError, models::ApiKey, PGPool};
+
+use super::{crypto, rbac::ApiScopes};
+
+/// keys look like **`eps_<id>_<secret>`**, the prefix tells them apart from jwts
+pub const KEY_PREFIX: &str = "eps_";
+const SECRET_BYTES: usize = 32;
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+fn info(key: ApiKey) -> ApiKeyInfo {
+    ApiKeyInfo {
+        id: key.id,
+        name: key.name,
+        scopes: ApiScopes::from_db(key.scopes).to_list(),
+        created_at: key.created_at,
+        expires_at: key.expires_at,
+        last_used_at: key.last_used_at,
+    }
+}
+
+/// creates a key for **`user_id`**, only its hash is stored
+pub async fn create(user_id: Uuid, dto: NewApiKeyDto, pool: &PGPool) -> Result<ApiKeyCreated, MyError> {
+    let scopes = ApiScopes::from_list(&dto.scopes);
+    let name = dto.name.trim().to_string();
+    if name.is_empty() || scopes.is_empty() {
+        return Err(MyError::BadClientData);
+    }
+    let now = Utc::now();
+    if dto.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err(MyError::BadClientData);
+    }
+    let id = Uuid::new_v4();
+    let secret = crypto::random_token(SECRET_BYTES);
+    let key = ApiKey {
+        id,
+        user_id,
+        name: name.clone(),
+        key_hash: crypto::get_sha3_256_hash(&secret),
+        scopes: scopes.to_db(),
+        created_at: now,
+        expires_at: dto.expires_at,
+        last_used_at: None,
+        revoked_at: None,
+    };
+    db::api_key::create(key, pool)
+        .await
+        .map_err(internal)?;
+    Ok(ApiKeyCreated {
+        id,
+        name,
+        key: format!("{KEY_PREFIX}{}_{secret}", id.simple()),
+        scopes: scopes.to_list(),
+        created_at: now,
+        expires_at: dto.expires_at,
+    })
+}
+
+pub async fn list(user_id: Uuid, pool: &PGPool) -> Result<Vec<ApiKeyInfo>, MyError> {
+    let keys = db::api_key::get_active_by_user(user_id, pool)
+        .await
+        .map_err(internal)?;
+    Ok(keys.into_iter().map(info).collect())
+}
+
+/// returns **`MyError::NotFound`** unless the key is active and belongs to **`user_id`**
+pub async fn revoke(id: Uuid, user_id: Uuid, pool: &PGPool) -> Result<(), MyError> {
+    match db::api_key::revoke(id, user_id, pool).await {
+        Ok(0) => Err(MyError::NotFound),
+        Ok(_) => Ok(()),
+        Err(err) => Err(internal(err)),
+    }
+}
+
+pub fn is_api_key(token: &str) -> bool {
+    token.starts_with(KEY_PREFIX)
+}
+
+/// looks the key up by its id and checks the secret, expiry and revocation</br>
+/// returns **`MyError::Unauthorized`** for any key that cannot be used
+pub async fn authenticate(token: &str, pool: &PGPool) -> Result<ApiKey, MyError> {
+    let (id, secret) = token.strip_prefix(KEY_PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+        .and_then(|(id, secret)| Uuid::try_parse(id).ok().map(|id| (id, secret)))
+        .ok_or(MyError::Unauthorized)?;
+    let key = match db::api_key::get_by_id(id, pool).await {
+        Ok(key) => key,
+        Err(sqlx::Error::RowNotFound) => return Err(MyError::Unauthorized),
+        Err(err) => return Err(internal(err)),
+    };
+    let hash = crypto::get_sha3_256_hash(&secret.to_string());
+    if !crypto::constant_time_eq(key.key_hash.as_bytes(), hash.as_bytes())
+        || key.revoked_at.is_some()
+        || key.expires_at.is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(MyError::Unauthorized);
+    }
+    if let Err(err) = db::api_key::touch(id, pool).await {
+        warn!("[{:} : {:}] FAILED TO UPDATE API KEY LAST USE: {:?}", file!(), line!(), err);
+    }
+    Ok(key)
+}
+
+/// scope an api key needs for the request, invitations need **`INVITE`**,</br>
+/// other reads need **`READ_EVENTS`** and everything else **`WRITE_EVENTS`**</br>
+/// **`None`** when no key may make it, event roles and ownership are only managed in a session
+pub fn required_scope(req: &ServiceRequest) -> Option<ApiScopes> {
+    let path = req.path().trim_end_matches('/');
+    let is_read = req.method() == Method::GET;
+    if !is_read && path.starts_with("/event/") && path.split('/').any(|segment| segment == "roles") {
+        None
+    } else if path.ends_with("/invitation") {
+        Some(ApiScopes::INVITE)
+    } else if is_read && !path.ends_with("/accept-invitation") {
+        Some(ApiScopes::READ_EVENTS)
+    } else {
+        Some(ApiScopes::WRITE_EVENTS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+
+    use super::*;
+
+    fn scope(method: Method, path: &str) -> Option<ApiScopes> {
+        required_scope(&TestRequest::default().method(method).uri(path).to_srv_request())
+    }
+
+    #[test]
+    fn maps_requests_to_scopes() {
+        let id = Uuid::nil();
+        assert_eq!(scope(Method::GET, "/event"), Some(ApiScopes::READ_EVENTS));
+        assert_eq!(scope(Method::GET, &format!("/event/{id}/roles")), Some(ApiScopes::READ_EVENTS));
+        assert_eq!(scope(Method::POST, "/event/create"), Some(ApiScopes::WRITE_EVENTS));
+        assert_eq!(scope(Method::GET, &format!("/event/{id}/accept-invitation")), Some(ApiScopes::WRITE_EVENTS));
+        assert_eq!(scope(Method::POST, &format!("/event/{id}/invitation/")), Some(ApiScopes::INVITE));
+    }
+
+    #[test]
+    fn keeps_keys_away_from_roles() {
+        let id = Uuid::nil();
+        assert_eq!(scope(Method::PUT, &format!("/event/{id}/roles/{id}")), None);
+        assert_eq!(scope(Method::DELETE, &format!("/event/{id}/roles/{id}")), None);
+        assert_eq!(scope(Method::POST, &format!("/event/{id}/roles/transfer")), None);
+    }
+}
@@ -1,31 +1,62 @@
-use std::future::{ready, Ready};
-use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, http::header::HeaderValue, HttpMessage};
+use std::{future::{ready, Ready}, rc::Rc};
+use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, HttpMessage};
 use futures_util::future::LocalBoxFuture;
 use log::error;
-use crate::{PGPool, ACCESS_TOKEN_EXP, db, dto::UpdateUserDto};
+use crate::{PGPool, db, dto::TokenType, errors::MyError, service::{api_key, cookies, rbac::{ApiScopes, Permissions}, session}};
 
-use self::jwt::TokenType;
+/// what the request was authenticated with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Credential {
+    /// access token of a login session
+    Session(uuid::Uuid),
+    ApiKey { id: uuid::Uuid, scopes: ApiScopes },
+}
 
+#[derive(Clone)]
 pub struct UserAuthData{
     pub user_id: uuid::Uuid,
-    pub username: String
+    pub username: String,
+    pub credential: Credential,
+    pub permissions: Permissions
+}
+
+impl UserAuthData {
+    /// login session of the request, **`None`** for api keys
+    pub fn session_id(&self) -> Option<uuid::Uuid> {
+        match self.credential {
+            Credential::Session(id) => Some(id),
+            Credential::ApiKey { .. } => None,
+        }
+    }
 }
 
 pub struct AuthMiddleware {
-    pub db_pool: PGPool
+    pub db_pool: PGPool,
+    pub accept_api_keys: bool
 }
 
 impl AuthMiddleware {
+    /// accepts access tokens only
     pub fn register(pool: PGPool) -> Self {
         AuthMiddleware {
-            db_pool: pool
+            db_pool: pool,
+            accept_api_keys: false
+        }
+    }
+
+    /// also accepts api keys in place of an access token,</br>
+    /// each request is checked against the scopes of the key
+    pub fn with_api_keys(pool: PGPool) -> Self {
+        AuthMiddleware {
+            db_pool: pool,
+            accept_api_keys: true
         }
     }
 }
 
 impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware 
     where
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
     B: 'static
 {
@@ -37,21 +68,73 @@ impl<S, B> Transform<S, ServiceRequest> for AuthMiddleware
 
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(AuthMiddlewareSerive {
-            service,
-            db_pool: self.db_pool.clone() 
+            service: Rc::new(service),
+            db_pool: self.db_pool.clone(),
+            accept_api_keys: self.accept_api_keys
         }))
     }
 }
 
 
 pub struct AuthMiddlewareSerive<S> {
-    service: S,
-    db_pool: PGPool
+    service: Rc<S>,
+    db_pool: PGPool,
+    accept_api_keys: bool
+}
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+/// authenticates the request by the access token from the **`Authorization`** header,</br>
+/// or from the access cookie in cookie mode, where mutating requests also need the csrf header
+async fn authenticate_session(req: &ServiceRequest, pool: &PGPool) -> Result<UserAuthData, MyError> {
+    // the refresh token is only accepted by POST /auth/refresh
+    let claims = match jwt::parse_request(req.request(), "Authorization", "Bearer") {
+        Ok(token) => jwt::validate(TokenType::Access, token)?,
+        Err(_) => {
+            let token = cookies::token(req.request(), cookies::ACCESS_COOKIE).ok_or(MyError::Unauthorized)?;
+            cookies::check_csrf(req.request())?;
+            jwt::validate(TokenType::Access, token)?
+        }
+    };
+    // revoked sessions are rejected here, without waiting for the token to expire
+    session::check_access(claims.sid, pool).await?;
+    // permissions are read on every request so role changes apply immediately
+    let permissions = db::user::get_permissions(claims.user_id, pool)
+        .await
+        .map_err(internal)?;
+    Ok(UserAuthData {
+        user_id: claims.user_id,
+        username: claims.username,
+        credential: Credential::Session(claims.sid),
+        permissions: Permissions::from_db(permissions),
+    })
+}
+
+/// authenticates the request by an api key, the key must cover the scope the request needs
+async fn authenticate_api_key(req: &ServiceRequest, token: &str, pool: &PGPool) -> Result<UserAuthData, MyError> {
+    let key = api_key::authenticate(token, pool).await?;
+    let scopes = ApiScopes::from_db(key.scopes);
+    if !api_key::required_scope(req).is_some_and(|required| scopes.contains(required)) {
+        return Err(MyError::Forbidden);
+    }
+    let user = db::user::get_by_id(key.user_id, pool)
+        .await
+        .map_err(internal)?;
+    Ok(UserAuthData {
+        user_id: user.id,
+        username: user.username,
+        credential: Credential::ApiKey { id: key.id, scopes },
+        // user management is never delegated to a key
+        permissions: Permissions::from_db(user.permissions) - Permissions::MANAGE_USERS,
+    })
 }
 
 impl<S, B> Service<ServiceRequest> for AuthMiddlewareSerive<S>
     where 
-    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -61,240 +144,312 @@ impl<S, B> Service<ServiceRequest> for AuthMiddlewareSerive<S>
 
     forward_ready!(service);
     
-    fn call(&self, mut req: ServiceRequest) -> Self::Future {
-        let access_token_validation_result: Result<String, crate::errors::MyError> = jwt::validate(
-            &req, 
-            TokenType::Access, 
-            "Authorization", 
-            "Bearer"
-        );
-        let refresh_token_validation_result: Result<String, crate::errors::MyError> = jwt::validate(
-            &req, 
-            TokenType::Refresh, 
-            "Refresh",
-            "Bearer"
-        );
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let api_key = jwt::parse_request(req.request(), "Authorization", "Bearer")
+            .ok()
+            .filter(|token| self.accept_api_keys && api_key::is_api_key(token));
         let pool = self.db_pool.clone();
-        match (access_token_validation_result, refresh_token_validation_result) {
-            (Ok(_), Ok(refresh_token)) => {
-                match jwt::decode_claims(&TokenType::Refresh, refresh_token) {
-                    Ok(claims) => {
-                        let user_auth_data = UserAuthData {
-                            user_id: claims.claims.user_id,
-                            username: claims.claims.username,
-                        };
-                        req.extensions_mut().insert(user_auth_data);
-                    },
-                    _ => {}
-                }
-                let fut = self.service.call(req);
-                Box::pin(async move {
-                    let res = fut.await?;
-                    Ok(res)
-                })
-            },
-            (Err(_), Ok(_)) => {
-                match jwt::parse_request(req.request(), "Authorization", "Bearer") {
-                    Ok(token) => {
-                        if let Ok(claims) = jwt::decode_claims(&TokenType::Access, token) {
-                            let user_id = claims.claims.user_id;
-                            let username = claims.claims.username;
-                            let new_token = jwt::create(&TokenType::Access, &user_id, &username, ACCESS_TOKEN_EXP).unwrap();
-                            let user_fields = UpdateUserDto {
-                                pwd_hash: None,
-                                username: None,
-                                email: None,
-                                access_token: Some(new_token.clone()),
-                                refresh_token: None,
-                            };
-
-                            req.headers_mut().insert(
-                                actix_web::http::header::AUTHORIZATION,
-                                HeaderValue::from_str(&format!("Bearer {new_token}")).unwrap()
-                            );
-                            let fut = self.service.call(req);
-                            Box::pin(async move {
-                                let fields_res = db::user::set_fields(user_id, user_fields, &pool)
-                                .await;
-                                match fields_res {
-                                    Ok(_) => {
-                                        Ok(fut.await?)
-                                    },
-                                    Err(err) => {
-                                        error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
-                                        Err(actix_web::error::ErrorInternalServerError(""))
-                                    }
-                                }    
-                            })
-                        } else {
-                            Box::pin(async move {
-                                error!("[{:} : {:}] INTERNAL SERVER ERROR: error decoding jwt", file!(), line!());
-                                Err(actix_web::error::ErrorInternalServerError("login again"))
-                            })
-                        }
-                    }, 
-                    Err(_) => {
-                        Box::pin(async move {
-                            error!("[{:} : {:}] INTERNAL SERVER ERROR: invalid request", file!(), line!());
-                            Err(actix_web::error::ErrorBadRequest("invalid request"))
-                        })                        
-                    }
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let auth_result = match api_key {
+                Some(token) => authenticate_api_key(&req, &token, &pool).await,
+                None => authenticate_session(&req, &pool).await,
+            };
+            match auth_result {
+                Ok(user_auth_data) => {
+                    req.extensions_mut().insert(user_auth_data);
+                    service.call(req).await
+                },
+                Err(err) => {
+                    error!("[{:} : {:}] AUTHENTICATION ERROR: {:?}", file!(), line!(), err);
+                    Err(err.into())
                 }
-            },
-            (_, Err(_)) => {
-                Box::pin(async move {
-                    error!("[{:} : {:}] INTERNAL SERVER ERROR: refresh your refresh jwt", file!(), line!());
-                    Err(actix_web::error::ErrorBadRequest("login again"))
-                })  
             }
-        }
+        })
     }
 }
 
 
 pub mod jwt {
-    use std::env::{self, VarError};
-    use actix_web::{dev::ServiceRequest, HttpRequest};
+    use std::net::{IpAddr, SocketAddr};
+    use actix_web::{http::header::{HeaderName, FORWARDED}, HttpRequest};
     use chrono::Utc;
-    use dotenv::dotenv;
-    use jsonwebtoken::{Header, Algorithm, EncodingKey, encode, decode, errors::Error, DecodingKey, Validation, TokenData};
-    use log::{info, warn};
-    use crate::{dto::{Claims, UpdateUserDto}, errors::MyError, PGPool, db, ACCESS_TOKEN_EXP, REFRESH_TOKEN_EXP};
-
-    pub enum TokenType {
-        Refresh,
-        Access
+    use jsonwebtoken::{Header, encode, decode, decode_header, errors::{Error, ErrorKind}, Validation, TokenData};
+    use log::warn;
+    use serde::{de::DeserializeOwned, Serialize};
+    use crate::{
+        dto::{Claims, ClientInfo, LoginResponse, LoginUserRequest, TokenPair, TwoFactorChallenge, TwoFactorLoginDto},
+        errors::MyError, models::User, PGPool, db,
+        service::{cookies, credentials, keys, one_time_token, session, throttle, two_factor},
+        config
+    };
+
+    pub use crate::dto::TokenType;
+
+    /// **`iss`** claim, see **`config::AuthConfig`**
+    pub fn issuer() -> &'static str {
+        &config::get().auth.issuer
     }
 
-    pub fn get_secret(token_type: &TokenType) -> Result<String, VarError> {
-        dotenv().ok();
-        let env_key: String;
-        match token_type {
-            TokenType::Refresh => env_key = "JWT_REFRESH_SECRET".to_string(),
-            TokenType::Access => env_key ="JWT_ACCESS_SECRET".to_string()
-        }
-        let secret = env::var(env_key);
-        secret
+    /// **`aud`** claim, defaults to the issuer
+    pub fn audience() -> &'static str {
+        config::get().auth.audience()
+    }
+
+    /// clock skew in seconds tolerated on **`exp`** and **`nbf`**
+    pub fn leeway() -> u64 {
+        config::get().auth.leeway_secs
+    }
+
+    /// verifies signature with the key named by the **`kid`** header,</br>
+    /// **`exp`**, **`nbf`**, **`iss`** and **`aud`**
+    pub fn verify<T: DeserializeOwned>(token: &str) -> Result<TokenData<T>, Error> {
+        let header = decode_header(token)?;
+        let key = header.kid
+            .as_deref()
+            .and_then(|kid| keys::store().get(kid))
+            .ok_or(ErrorKind::InvalidToken)?;
+        let mut validation = Validation::new(key.algorithm);
+        validation.leeway = leeway();
+        validation.validate_nbf = true;
+        validation.set_issuer(&[issuer()]);
+        validation.set_audience(&[audience()]);
+        validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+        decode::<T>(token, &key.decoding_key, &validation)
+    }
+
+    /// signs **`claims`** with the current signing key and names it in the **`kid`** header
+    pub fn sign<T: Serialize>(claims: &T) -> Result<String, Error> {
+        let key = keys::store().signing_key();
+        let mut header: Header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        encode(&header, claims, &key.encoding_key)
     }
 
+    /// verifies the token and that **`typ`** matches **`token_type`**
     pub fn decode_claims(token_type: &TokenType, token: String) -> Result<TokenData<Claims>, Error> {
-        let secret = get_secret(token_type).expect("Jwt token secret must be set");
-        warn!("secret: {:?}", secret);
-        let decoding_key = DecodingKey::from_secret(secret.as_ref());
-        let validation = Validation::new(Algorithm::HS256);
-        let claims = decode::<Claims>(&token, &decoding_key, &validation);
-        warn!("decoded claims: {:?}", claims);
-        claims
+        let claims = verify::<Claims>(&token)?;
+        if claims.claims.typ != *token_type {
+            return Err(ErrorKind::InvalidToken.into());
+        }
+        Ok(claims)
     }
 
-    pub fn create(token_type: &TokenType, user_id: &uuid::Uuid, username: &String, exp: usize) -> Result<String, Error> {
-        let exp_timestamp = Utc::now().timestamp_micros() as usize + exp;
-        let secret = get_secret(token_type).expect("Jwt token secret must be set");
-        let header: Header = Header::new(Algorithm::HS256);
-        let claims: Claims = Claims::new(user_id, username, exp_timestamp);
-        let key: EncodingKey = EncodingKey::from_secret(secret.as_ref());
-        let token_res = encode(&header, &claims, &key);
-        match token_res {
-            Ok(token) => {
-                Ok(token)
-            },
-            Err(err) => {
-                Err(err)
+    /// creates a token of **`token_type`** valid for **`ttl`** seconds
+    pub fn create(
+        token_type: &TokenType, 
+        user_id: &uuid::Uuid, 
+        username: &String, 
+        session_id: &uuid::Uuid, 
+        jti: &uuid::Uuid, 
+        ttl: i64
+    ) -> Result<String, Error> {
+        let now = Utc::now().timestamp();
+        let claims: Claims = Claims {
+            user_id: *user_id,
+            username: username.to_string(),
+            sid: *session_id,
+            typ: *token_type,
+            jti: *jti,
+            iss: issuer().to_string(),
+            aud: audience().to_string(),
+            iat: now,
+            nbf: now,
+            exp: now + ttl,
+        };
+        sign(&claims)
+    } 
+    /// signs an **`access`**/**`refresh`** token pair for the session,</br>
+    /// **`refresh_jti`** must be the current refresh token id of the session
+    fn sign_pair(user_id: &uuid::Uuid, username: &String, session_id: &uuid::Uuid, refresh_jti: &uuid::Uuid) -> Result<TokenPair, MyError> {
+        let ttl = &config::get().auth;
+        let access_token = create(&TokenType::Access, user_id, username, session_id, &uuid::Uuid::new_v4(), ttl.access_token_ttl)
+            .map_err(|_| MyError::InternalError)?;
+        let refresh_token = create(&TokenType::Refresh, user_id, username, session_id, refresh_jti, ttl.refresh_token_ttl)
+            .map_err(|_| MyError::InternalError)?;
+        Ok(TokenPair { access_token, refresh_token })
+    }
+
+    /// starts a new session for the user and issues its first</br>
+    /// **`access`**/**`refresh`** token pair
+    pub async fn issue_pair(user_id: &uuid::Uuid, username: &String, client: ClientInfo, pool: &PGPool) -> Result<TokenPair, MyError> {
+        let (session_id, refresh_jti) = session::start(*user_id, client, pool).await?;
+        sign_pair(user_id, username, &session_id, &refresh_jti)
+    }
+
+    /// checks **`username`** and **`pwd`** with the configured credential backends</br>
+    /// and issues a fresh **`access`** and **`refresh`** token pair,</br>
+    /// or a challenge token for **`login_second_factor`** if the user has 2fa enabled</br>
+    /// returns **`MyError::Unauthorized`** for an unknown user or a wrong password</br>
+    /// and **`MyError::TooManyRequests`** while the account or the client address is locked
+    pub async fn login(pool: &PGPool, dto: LoginUserRequest, client: ClientInfo) -> Result<LoginResponse, MyError> {
+        let LoginUserRequest { username, pwd } = dto;
+        throttle::check_login(&username, &client, pool).await?;
+        let user = match credentials::authenticate(&username, &pwd, pool).await? {
+            Some(user) => user,
+            None => {
+                throttle::login_failed(&username, &client, pool).await;
+                return Err(MyError::Unauthorized)
             }
+        };
+        complete_login(&user, client, pool).await
+    }
+
+    /// last step of every login once the user's credentials are checked:</br>
+    /// a challenge token for **`login_second_factor`** if the user has 2fa enabled,
+    /// a fresh token pair otherwise
+    pub async fn complete_login(user: &User, client: ClientInfo, pool: &PGPool) -> Result<LoginResponse, MyError> {
+        if two_factor::is_enabled(user.id, pool).await? {
+            let ttl = config::get().auth.two_factor_challenge_ttl;
+            let challenge_token = one_time_token::issue(
+                TokenType::TwoFactorChallenge,
+                user.id,
+                None,
+                ttl,
+                pool
+            ).await?;
+            return Ok(LoginResponse::TwoFactorRequired(TwoFactorChallenge {
+                challenge_token,
+                expires_in: ttl,
+            }));
         }
-    } 
+        throttle::login_succeeded(&user.username, pool).await;
+        issue_pair(&user.id, &user.username, client, pool)
+            .await
+            .map(LoginResponse::Tokens)
+    }
+
+    /// exchanges a challenge token from **`login`** and a TOTP or recovery code for a token pair</br>
+    /// the challenge is used up by the first attempt, a wrong code means starting over</br>
+    /// returns **`MyError::Unauthorized`** for a bad challenge or code
+    pub async fn login_second_factor(pool: &PGPool, dto: TwoFactorLoginDto, client: ClientInfo) -> Result<TokenPair, MyError> {
+        let challenge = one_time_token::consume(TokenType::TwoFactorChallenge, &dto.challenge_token, pool)
+            .await
+            .map_err(|err| match err {
+                MyError::BadClientData => MyError::Unauthorized,
+                err => err
+            })?;
+        let user = db::user::get_by_id(challenge.user_id, pool)
+            .await
+            .map_err(|_| MyError::Unauthorized)?;
+        throttle::check_login(&user.username, &client, pool).await?;
+        if let Err(err) = two_factor::verify(user.id, &user.username, &dto.code, pool).await {
+            throttle::login_failed(&user.username, &client, pool).await;
+            return Err(err);
+        }
+        throttle::login_succeeded(&user.username, pool).await;
+        issue_pair(&user.id, &user.username, client, pool).await
+    }
 
-    /// refreshes given **`token`**
-    pub async fn refresh(token: String, token_type: &TokenType, pool: & PGPool, new_exp: usize) -> Result<String, MyError> {
-        let claims_result: Result<TokenData<Claims>, Error> = decode_claims(&token_type, token);
-        let user_id: uuid::Uuid;
-        let username: String;
-        let updated_user_fields: UpdateUserDto;
-
-        return if let Ok(expired_token_claims) = claims_result {
-            user_id = expired_token_claims.claims.user_id;
-            username = expired_token_claims.claims.username;
-            let new_token_result = create(&token_type, &user_id, &username, new_exp);
-            if let Ok(new_token) = new_token_result {
-                updated_user_fields = UpdateUserDto {
-                    pwd_hash: None,
-                    username: None,
-                    email: None,
-                    access_token: Some(new_token.clone()),
-                    refresh_token: None,
-                };
-                let res = db::user::set_fields(user_id, updated_user_fields, pool)
-                    .await;
-                if let Ok(_) = res {
-                    Ok(new_token)
-                } else {
-                    Err(MyError::InternalError)
+    ///rotates the **`refresh`** token from the **`Refresh`** header, or from the refresh cookie</br>
+    ///in cookie mode, and issues a new token pair in the same session</br>
+    ///presenting an already rotated token revokes the whole session
+    pub async fn refresh_pair(pool: &PGPool, req: HttpRequest) -> Result<TokenPair, MyError> {
+        let current_refresh = match parse_request(&req, "Refresh", "Bearer") {
+            Ok(token) => token,
+            Err(err) => {
+                let token = cookies::token(&req, cookies::REFRESH_COOKIE).ok_or(err)?;
+                cookies::check_csrf(&req)?;
+                token
+            }
+        };
+        let claims = decode_claims(&TokenType::Refresh, current_refresh)
+            .map_err(|_| MyError::Unauthorized)?
+            .claims;
+        let refresh_jti = session::rotate(claims.sid, claims.jti, pool).await?;
+        // the username may have changed since the session started
+        let user = db::user::get_by_id(claims.user_id, pool)
+            .await
+            .map_err(|_| MyError::Unauthorized)?;
+        sign_pair(&claims.user_id, &user.username, &claims.sid, &refresh_jti)
+    }
+
+    /// validates **`token`** as **`token_type`**</br>
+    /// returns **`MyError::TokenExpirationError`** if the token is expired</br>
+    /// and **`MyError::Unauthorized`** if it is invalid in any other way
+    pub fn validate(token_type: TokenType, token: String) -> Result<Claims, MyError> {
+        match decode_claims(&token_type, token) {
+            Ok(token_data) => Ok(token_data.claims),
+            Err(err) => match err.kind() {
+                ErrorKind::ExpiredSignature => Err(MyError::TokenExpirationError),
+                _ => {
+                    warn!("[{:} : {:}] INVALID TOKEN: {:?}", file!(), line!(), err);
+                    Err(MyError::Unauthorized)
                 }
-            } else {
-                Err(MyError::InternalError)
             }
-        } else {
-            Err(MyError::DecodeError)
         }
     }
 
-    ///refreshes **`refresg`** and **`access`** tokens if they are expired</br>
-    ///otherwise creates new tokens
-    pub async fn login(pool: &PGPool, req: HttpRequest) -> Result<(String, String), MyError> {
-        let current_access_token_result = parse_request(&req, "Authorization", "Bearer");
-        let current_refresh_token_result = parse_request(&req, "RefreshToken", "Bearer");
-        match (current_access_token_result, current_refresh_token_result) {
-            (Ok(current_access), Ok(current_refresh)) => {
-                let access_token_refresh = refresh(
-                    current_access, 
-                    &TokenType::Access, 
-                    pool, 
-                    ACCESS_TOKEN_EXP
-                ).await?;
-                let refresh_token_refresh = refresh(
-                    current_refresh, 
-                    &TokenType::Refresh, 
-                    pool, 
-                    REFRESH_TOKEN_EXP
-                ).await?;
-                Ok((access_token_refresh, refresh_token_refresh))
-            },
-            (_, _) => Err(MyError::DecodeError)
+    /// reads **`User-Agent`** and the client address for session bookkeeping
+    pub fn client_info(req: &HttpRequest) -> ClientInfo {
+        ClientInfo {
+            user_agent: req.headers()
+                .get(actix_web::http::header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string()),
+            ip: client_ip(req).map(|ip| ip.to_string()),
         }
     }
 
-    /// check if JW token is expired or not </br>
-    /// returns **`MyError::AuthError`** if token is expired </br>
-    /// else returns **`token`** itself</br>
-    /// throws an **`MyError::DecodeError`** if the jwt decoding fails
-    pub fn validate(req: &ServiceRequest, token_type: TokenType, header_key: &str, prefix: &str) -> Result<String, MyError> {
-        if let Ok(token) = parse_request(req.request(), header_key, prefix) {
-            let claims: Result<TokenData<Claims>, Error> = decode_claims(&token_type, format!("{token}"));
-            return match claims {
-                Ok(c) => {
-                    if (Utc::now().timestamp_micros() as usize) > c.claims.exp {
-                        Ok(token)
-                    } else {
-                        Err(MyError::AuthError)
-                    }
-                },
-                Err(_) => {
-                    Err(MyError::DecodeError)
-                }
+    /// an address from **`X-Forwarded-For`** or a **`for=`** of **`Forwarded`**,</br>
+    /// optionally quoted, bracketed or with a port
+    fn parse_forwarded_ip(value: &str) -> Option<IpAddr> {
+        let value = value.trim().trim_matches('"');
+        value.parse::<IpAddr>().ok()
+            .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+            .or_else(|| value.strip_prefix('[')?.split(']').next()?.parse().ok())
+    }
+
+    /// the addresses the forwarding headers list, the nearest hop last
+    fn forwarded_chain(req: &HttpRequest) -> Vec<Option<IpAddr>> {
+        let headers = req.headers();
+        let forwarded = headers.get_all(FORWARDED)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|element| element.split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, value)| parse_forwarded_ip(value)))
+            .collect::<Vec<_>>();
+        if !forwarded.is_empty() {
+            return forwarded;
+        }
+        headers.get_all(HeaderName::from_static("x-forwarded-for"))
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(parse_forwarded_ip)
+            .collect()
+    }
+
+    /// the connecting address, unless it is one of **`server.trusted_proxies`**: then the forwarding</br>
+    /// headers are walked back from the nearest hop to the first address that is not a trusted proxy,</br>
+    /// so a client cannot choose the address its sessions and throttling are keyed by
+    pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        let trusted = &config::get().server.trusted_proxies;
+        if !trusted.contains(&peer) {
+            return Some(peer);
+        }
+        let mut client = peer;
+        for hop in forwarded_chain(req).into_iter().rev() {
+            // an unreadable entry cannot be attributed, stop at the last proxy that vouched for it
+            let Some(hop) = hop else { break };
+            client = hop;
+            if !trusted.contains(&hop) {
+                break;
             }
         }
-        Err(MyError::AuthError)
+        Some(client)
     }
 
     pub fn parse_request(req: &HttpRequest, header_key: &str, prefix: &str) -> Result<String, MyError> {
         if let Some(auth_header) = req.headers().get(header_key) {
             if let Ok(auth_value) = auth_header.to_str() {
                 if let Some(token) = auth_value.strip_prefix(prefix) {
-                    info!("PARSE REQUEST TOKEN: {:}", token);
-                    return Ok(token.to_string());
+                    return Ok(token.trim().to_string());
                 }
             }
         }
         Err(MyError::AuthError)
     }
-}
\ No newline at end of file
+}
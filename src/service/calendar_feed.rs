@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use uuid::Uuid;
+
+use crate::{config, db, dto::CalendarFeedDto, errors::MyError, PGPool};
+
+use super::{crypto, icalendar::Calendar};
+
+const TOKEN_BYTES: usize = 32;
+const FEED_NAME: &str = "Event planning";
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+fn feed_url(token: &str) -> String {
+    format!("{}/calendar/{token}.ics", config::get().public_url())
+}
+
+/// gives the user a new feed url, the previous one stops working</br>
+/// the token is only shown here, just its hash is stored
+pub async fn create(user_id: Uuid, pool: &PGPool) -> Result<CalendarFeedDto, MyError> {
+    let token = crypto::random_token(TOKEN_BYTES);
+    db::calendar_feed::replace(user_id, &crypto::get_sha3_256_hash(&token), pool)
+        .await
+        .map_err(internal)?;
+    info!("CALENDAR FEED CREATED FOR {:?}", user_id);
+    Ok(CalendarFeedDto { url: feed_url(&token) })
+}
+
+/// returns **`MyError::NotFound`** if the user has no feed
+pub async fn revoke(user_id: Uuid, pool: &PGPool) -> Result<(), MyError> {
+    match db::calendar_feed::delete(user_id, pool).await.map_err(internal)? {
+        0 => Err(MyError::NotFound),
+        _ => Ok(()),
+    }
+}
+
+/// the events the owner of **`token`** takes part in; whole series with their changed occurrences,</br>
+/// or only the occurrences they subscribed to; cancelled ones stay in with **`STATUS:CANCELLED`**</br>
+/// so clients drop them; returns **`MyError::NotFound`** for an unknown token
+pub async fn render(token: &str, pool: &PGPool) -> Result<String, MyError> {
+    let feed = db::calendar_feed::get_by_token_hash(&crypto::get_sha3_256_hash(&token.to_string()), pool)
+        .await
+        .map_err(internal)?
+        .ok_or(MyError::NotFound)?;
+    let events = db::user::get_user_participations(feed.user_id, pool)
+        .await
+        .map_err(internal)?;
+    let participations = db::user::get_participation_rows(feed.user_id, pool)
+        .await
+        .map_err(internal)?;
+    let ids: Vec<Uuid> = events.iter().map(|event| event.id).collect();
+    let changes = db::occurrence::get_by_events(&ids, pool)
+        .await
+        .map_err(internal)?;
+    let mut calendar = Calendar::new(Some(FEED_NAME));
+    for event in &events {
+        let subscribed: Vec<Option<DateTime<Utc>>> = participations.iter()
+            .filter(|participation| participation.event_id == event.id)
+            .map(|participation| participation.occurrence_dt)
+            .collect();
+        if event.rrule.is_none() || subscribed.contains(&None) {
+            calendar.add_event(event, &changes);
+            continue;
+        }
+        for occurrence in subscribed.into_iter().flatten() {
+            let occurrence_changes = changes.iter()
+                .find(|changes| changes.event_id == event.id && changes.occurrence_dt == occurrence);
+            calendar.add_occurrence(event, occurrence, occurrence_changes);
+        }
+    }
+    Ok(calendar.render())
+}
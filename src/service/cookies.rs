@@ -0,0 +1,123 @@
+use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite},
+    http::Method,
+    HttpRequest,
+};
+
+use crate::{config::{self as app_config, CookieConfig}, dto::{CookieSession, TokenPair}, errors::MyError};
+
+use super::crypto;
+
+pub const ACCESS_COOKIE: &str = "eps_access";
+pub const REFRESH_COOKIE: &str = "eps_refresh";
+/// readable by scripts, its value has to be echoed in **`CSRF_HEADER`**
+pub const CSRF_COOKIE: &str = "eps_csrf";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+/// remembers the choice of cookie mode across a redirect through an oidc provider
+pub const MODE_COOKIE: &str = "eps_auth_mode";
+/// clients opt in with **`X-Auth-Mode: cookie`**
+pub const MODE_HEADER: &str = "X-Auth-Mode";
+const COOKIE_MODE: &str = "cookie";
+/// the refresh token is only ever sent to the endpoints that use it
+const REFRESH_PATH: &str = "/auth";
+const CSRF_TOKEN_LEN: usize = 32;
+
+fn config() -> &'static CookieConfig {
+    &app_config::get().cookies
+}
+
+pub fn enabled() -> bool {
+    config().enabled
+}
+
+/// whether tokens go into cookies for this request: the client asked for it,</br>
+/// already holds auth cookies or started an oidc login in cookie mode
+pub fn wants_cookies(req: &HttpRequest) -> bool {
+    if !enabled() {
+        return false;
+    }
+    let asked = req.headers()
+        .get(MODE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case(COOKIE_MODE));
+    asked || [ACCESS_COOKIE, REFRESH_COOKIE, MODE_COOKIE].iter().any(|name| req.cookie(name).is_some())
+}
+
+fn build(name: &'static str, value: String, path: &'static str, http_only: bool, max_age: Duration) -> Cookie<'static> {
+    let config = config();
+    let mut cookie = Cookie::build(name, value)
+        .path(path)
+        .http_only(http_only)
+        .secure(config.secure)
+        .same_site(match config.same_site {
+            app_config::SameSite::Strict => SameSite::Strict,
+            app_config::SameSite::Lax => SameSite::Lax,
+            app_config::SameSite::None => SameSite::None,
+        })
+        .max_age(max_age)
+        .finish();
+    if let Some(domain) = &config.domain {
+        cookie.set_domain(domain.clone());
+    }
+    cookie
+}
+
+/// cookies carrying a token pair, the csrf token is returned for the response body
+pub fn session_cookies(tokens: TokenPair) -> (Vec<Cookie<'static>>, CookieSession) {
+    let ttl = &app_config::get().auth;
+    let csrf_token = crypto::random_token(CSRF_TOKEN_LEN);
+    let cookies = vec![
+        build(ACCESS_COOKIE, tokens.access_token, "/", true, Duration::seconds(ttl.access_token_ttl)),
+        build(REFRESH_COOKIE, tokens.refresh_token, REFRESH_PATH, true, Duration::seconds(ttl.refresh_token_ttl)),
+        build(CSRF_COOKIE, csrf_token.clone(), "/", false, Duration::seconds(ttl.refresh_token_ttl)),
+        removal(MODE_COOKIE, REFRESH_PATH),
+    ];
+    (cookies, CookieSession { csrf_token, expires_in: ttl.access_token_ttl })
+}
+
+/// short lived marker set when an oidc login is started in cookie mode
+pub fn mode_cookie(max_age: i64) -> Cookie<'static> {
+    build(MODE_COOKIE, COOKIE_MODE.to_string(), REFRESH_PATH, true, Duration::seconds(max_age))
+}
+
+fn removal(name: &'static str, path: &'static str) -> Cookie<'static> {
+    let mut cookie = build(name, String::new(), path, true, Duration::ZERO);
+    cookie.make_removal();
+    cookie
+}
+
+/// expires every auth cookie, sent on logout
+pub fn clear_cookies() -> Vec<Cookie<'static>> {
+    vec![
+        removal(ACCESS_COOKIE, "/"),
+        removal(REFRESH_COOKIE, REFRESH_PATH),
+        removal(CSRF_COOKIE, "/"),
+        removal(MODE_COOKIE, REFRESH_PATH),
+    ]
+}
+
+/// reads a token cookie, **`None`** when cookie mode is off
+pub fn token(req: &HttpRequest, name: &str) -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+    req.cookie(name).map(|cookie| cookie.value().to_string())
+}
+
+/// double-submit check for requests authenticated by cookie:</br>
+/// anything but **`GET`**, **`HEAD`** and **`OPTIONS`** has to repeat the csrf cookie in **`CSRF_HEADER`**</br>
+/// returns **`MyError::Forbidden`** otherwise
+pub fn check_csrf(req: &HttpRequest) -> Result<(), MyError> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(());
+    }
+    let cookie = req.cookie(CSRF_COOKIE).ok_or(MyError::Forbidden)?;
+    let header = req.headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .ok_or(MyError::Forbidden)?;
+    if cookie.value().is_empty() || !crypto::constant_time_eq(cookie.value().as_bytes(), header.as_bytes()) {
+        return Err(MyError::Forbidden);
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::sync::OnceLock;
+use futures::future::BoxFuture;
+use log::{error, info, warn};
+
+use crate::{config, db, dto::UpdateUserDto, errors::MyError, models::User, PGPool};
+
+use super::{ldap::LdapBackend, password::{self, Verification}};
+
+/// values of **`users.auth_source`**
+pub const LOCAL_SOURCE: &str = "local";
+pub const LDAP_SOURCE: &str = "ldap";
+pub const OIDC_SOURCE: &str = "oidc";
+/// the account was deleted and anonymized, nothing may log in to it
+pub const DELETED_SOURCE: &str = "deleted";
+
+static BACKENDS: OnceLock<Vec<Box<dyn CredentialBackend>>> = OnceLock::new();
+
+/// checks a username and password on login
+pub trait CredentialBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// returns the local user if the credentials are valid,</br>
+    /// **`None`** if they are not or the account belongs to another backend
+    fn authenticate<'a>(&'a self, username: &'a str, pwd: &'a str, pool: &'a PGPool) -> BoxFuture<'a, Result<Option<User>, MyError>>;
+}
+
+/// checks the password against **`users.pwd_hash`**
+pub struct DbBackend;
+
+impl CredentialBackend for DbBackend {
+    fn name(&self) -> &'static str {
+        "db"
+    }
+
+    fn authenticate<'a>(&'a self, username: &'a str, pwd: &'a str, pool: &'a PGPool) -> BoxFuture<'a, Result<Option<User>, MyError>> {
+        Box::pin(async move {
+            let user = match db::user::get_by_username(username.to_string(), pool).await {
+                Ok(user) => user,
+                Err(sqlx::Error::RowNotFound) => {
+                    password::verify_dummy(pwd).await;
+                    return Ok(None)
+                },
+                Err(err) => {
+                    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+                    return Err(MyError::InternalError)
+                }
+            };
+            // the directory owns these passwords and deleted accounts have none, a local hash must never let them in
+            if user.auth_source == LDAP_SOURCE || user.auth_source == DELETED_SOURCE {
+                password::verify_dummy(pwd).await;
+                return Ok(None);
+            }
+            match password::verify(pwd, &user.pwd_hash).await? {
+                Verification::Invalid => return Ok(None),
+                Verification::Valid { needs_rehash: true } => {
+                    let user_fields = UpdateUserDto {
+                        pwd_hash: Some(password::hash(pwd).await?),
+                        ..Default::default()
+                    };
+                    if let Err(err) = db::user::set_fields(user.id, user_fields, pool).await {
+                        warn!("[{:} : {:}] FAILED TO UPGRADE PASSWORD HASH: {:?}", file!(), line!(), err);
+                    }
+                },
+                Verification::Valid { needs_rehash: false } => {}
+            }
+            Ok(Some(user))
+        })
+    }
+}
+
+/// sets up the backends named in **`auth.backends`**, tried in order</br>
+/// must be called once at startup
+pub fn init() -> Result<(), String> {
+    let config = config::get();
+    let mut backends: Vec<Box<dyn CredentialBackend>> = Vec::new();
+    for name in &config.auth.backends {
+        let backend: Box<dyn CredentialBackend> = match name.as_str() {
+            "db" => Box::new(DbBackend),
+            "ldap" => Box::new(LdapBackend::new(&config.ldap)),
+            name => return Err(format!("unknown credential backend {name:?}")),
+        };
+        info!("using credential backend {:?}", backend.name());
+        backends.push(backend);
+    }
+    if backends.is_empty() {
+        return Err("auth.backends has no backends".to_string());
+    }
+    BACKENDS.set(backends).map_err(|_| "credential backends are already initialized".to_string())
+}
+
+/// asks every backend in turn, the first one to accept the credentials wins</br>
+/// a failing backend is skipped, its error is only returned if no other backend accepts
+pub async fn authenticate(username: &str, pwd: &str, pool: &PGPool) -> Result<Option<User>, MyError> {
+    let backends = BACKENDS.get().expect("credential backends must be initialized at startup");
+    let mut failure = None;
+    for backend in backends {
+        match backend.authenticate(username, pwd, pool).await {
+            Ok(Some(user)) => return Ok(Some(user)),
+            Ok(None) => {},
+            Err(err) => {
+                warn!("[{:} : {:}] CREDENTIAL BACKEND {:} FAILED: {:?}", file!(), line!(), backend.name(), err);
+                failure.get_or_insert(err);
+            }
+        }
+    }
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(None)
+    }
+}
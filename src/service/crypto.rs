@@ -1,7 +1,28 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::{constant_time, rand::{SecureRandom, SystemRandom}};
 use sha3::{Sha3_256, Digest};
 
 pub fn get_sha3_256_hash(data: &String) -> String {
    let mut hasher = Sha3_256::default();
    hasher.update(data);
    format!("{:X}", hasher.finalize())
-}
\ No newline at end of file
+}
+
+/// **`len`** bytes from the system CSPRNG
+pub fn random_bytes(len: usize) -> Vec<u8> {
+   let mut bytes = vec![0u8; len];
+   SystemRandom::new()
+      .fill(&mut bytes)
+      .expect("system random number generator is unavailable");
+   bytes
+}
+
+/// **`len`** random bytes encoded as unpadded url-safe base64
+pub fn random_token(len: usize) -> String {
+   URL_SAFE_NO_PAD.encode(random_bytes(len))
+}
+
+/// compares secrets without leaking how many leading bytes match
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+   constant_time::verify_slices_are_equal(a, b).is_ok()
+}
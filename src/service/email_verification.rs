@@ -0,0 +1,63 @@
+use log::error;
+use uuid::Uuid;
+
+use crate::{config, db, dto::TokenType, errors::MyError, PGPool};
+
+use super::{mail::{Mail, MailSender}, one_time_token};
+
+/// seconds between two verification mails
+const RESEND_INTERVAL: i64 = 60;
+const RESEND_PER_HOUR: usize = 5;
+
+/// mails a fresh verification token for **`email`**, earlier tokens stop working
+pub async fn send(user_id: Uuid, email: &str, mailer: &dyn MailSender, pool: &PGPool) -> Result<(), MyError> {
+    let ttl = config::get().auth.email_verification_ttl;
+    let token = one_time_token::issue(
+        TokenType::EmailVerification,
+        user_id,
+        Some(email.to_string()),
+        ttl,
+        pool
+    ).await?;
+    mailer.send(Mail {
+        to: email.to_string(),
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Use this token to confirm your email address, it is valid for {} hours:\n\n{}\n",
+            ttl / 3600,
+            token
+        ),
+    }).await
+}
+
+/// sends another verification mail, at most once a minute and five times an hour</br>
+/// returns **`MyError::BadClientData`** if the user has no address or it is already verified
+pub async fn resend(user_id: Uuid, mailer: &dyn MailSender, pool: &PGPool) -> Result<(), MyError> {
+    let user = db::user::get_by_id(user_id, pool)
+        .await
+        .map_err(|err| {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })?;
+    let email = match user.email {
+        Some(email) if user.email_verified_at.is_none() => email,
+        _ => return Err(MyError::BadClientData),
+    };
+    one_time_token::throttle(TokenType::EmailVerification, user_id, RESEND_INTERVAL, RESEND_PER_HOUR, pool).await?;
+    send(user_id, &email, mailer, pool).await
+}
+
+/// marks the address the token was sent to as verified</br>
+/// tokens for an address the user has since replaced are rejected
+pub async fn verify(token: &str, pool: &PGPool) -> Result<(), MyError> {
+    let token = one_time_token::consume(TokenType::EmailVerification, token, pool).await?;
+    let email = token.email.ok_or(MyError::BadClientData)?;
+    match db::user::set_email_verified(token.user_id, &email, pool).await {
+        Ok(0) => Err(MyError::BadClientData),
+        Ok(_) => Ok(()),
+        Err(err) => {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            Err(MyError::InternalError)
+        }
+    }
+}
@@ -1,10 +1,91 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::{error, warn};
+use sqlx::{Acquire, Postgres};
 use uuid::Uuid;
 
-use crate::{dto::{NewEventDto, UpdateEventDto}, PGPool, models::{Event, Invitation}, errors::MyError, db};
+use crate::{config, dto::{EventInstance, EventQuery, EventSearchHit, EventSearchQuery, NewEventDto, OccurrenceDto, Page, RecurrenceDto, UpdateEventDto, ParticipantDto}, PGPool, models::{Event, Invitation, OccurrenceOverride}, errors::MyError, db::{self, event::{Cursor, Filter, Sort}}};
 
-use super::auth::UserAuthData;
+use super::{auth::UserAuthData, event_role, icalendar::Calendar, rbac::{EventPermissions, Permissions}, recurrence::RRule};
 
-pub async fn create(user_auth_data: &UserAuthData, dto: NewEventDto, pool: &PGPool) -> Result<u64, MyError> {
+/// longest window **`expand=true`** lists occurrences for
+const MAX_EXPANSION_DAYS: i64 = 366;
+/// upcoming occurrences listed with a recurring search hit
+const SEARCH_OCCURRENCES: usize = 5;
+/// series **`expand=true`** expands for one page, a window that reaches more has to be narrowed by the other filters
+const MAX_EXPANDED_SERIES: i64 = 1000;
+
+/// validates the recurrence fields, returns the time zone and the normalized rule</br>
+/// returns **`MyError::BadClientData`** for an unknown time zone, an invalid rule or exdates without a rule
+pub fn check_recurrence(
+   time_zone: Option<String>,
+   rrule: Option<String>,
+   exdates: &[DateTime<Utc>]
+) -> Result<(Option<String>, Option<String>), MyError> {
+   if time_zone.as_deref().is_some_and(|time_zone| time_zone.parse::<Tz>().is_err()) {
+      return Err(MyError::BadClientData);
+   }
+   let rrule = rrule
+      .filter(|rrule| !rrule.trim().is_empty())
+      .map(|rrule| rrule.parse::<RRule>())
+      .transpose()
+      .map_err(|err| {
+         warn!("[{:} : {:}] INVALID RRULE: {:}", file!(), line!(), err);
+         MyError::BadClientData
+      })?;
+   if rrule.is_none() && !exdates.is_empty() {
+      return Err(MyError::BadClientData);
+   }
+   Ok((time_zone, rrule.map(|rrule| rrule.to_string())))
+}
+
+/// the rule and time zone of a recurring event, **`None`** for single events
+fn recurrence_of(event: &Event) -> Option<(RRule, Tz)> {
+   parse_recurrence(event.time_zone.as_deref(), event.rrule.as_deref())
+}
+
+fn parse_recurrence(time_zone: Option<&str>, rrule: Option<&str>) -> Option<(RRule, Tz)> {
+   let rule = rrule?.parse().ok()?;
+   let tz = time_zone.and_then(|time_zone| time_zone.parse().ok()).unwrap_or(Tz::UTC);
+   Some((rule, tz))
+}
+
+/// start of the last occurrence of a series starting at **`dt`**, what **`events.recurrence_end`** holds,</br>
+/// **`None`** for single events and series without end</br>
+/// returns **`MyError::BadClientData`** for a **`COUNT`** too far off to ever be expanded
+pub fn recurrence_end(dt: DateTime<Utc>, time_zone: Option<&str>, rrule: Option<&str>) -> Result<Option<DateTime<Utc>>, MyError> {
+   let Some((rule, tz)) = parse_recurrence(time_zone, rrule) else {
+      return Ok(None);
+   };
+   rule.end(dt, tz).map_err(|err| {
+      warn!("[{:} : {:}] RRULE WITHOUT REACHABLE END: {:}", file!(), line!(), err);
+      MyError::BadClientData
+   })
+}
+
+/// whether the event is still to come, for a series whether its last occurrence is
+pub fn is_upcoming(event: &Event, now: DateTime<Utc>) -> bool {
+   if event.dt > now {
+      return true;
+   }
+   match recurrence_of(event) {
+      // a series without a reachable end goes on
+      Some((rule, tz)) => rule.end(event.dt, tz).map_or(true, |end| end.is_none_or(|end| end > now)),
+      None => false,
+   }
+}
+
+/// stores a new event owned by the caller and returns its id,</br>
+/// **`ical_uid`** is the uid of an event imported from another calendar
+pub async fn insert(
+   user_auth_data: &UserAuthData,
+   dto: NewEventDto,
+   ical_uid: Option<String>,
+   conn: impl Acquire<'_, Database = Postgres>
+) -> Result<Uuid, MyError> {
+   let (time_zone, rrule) = check_recurrence(dto.time_zone, dto.rrule, &dto.exdates)?;
+   let end = recurrence_end(dto.dt, time_zone.as_deref(), rrule.as_deref())?;
    let event = Event {
     id: uuid::Uuid::new_v4(),
     title: dto.title,
@@ -12,65 +93,356 @@ pub async fn create(user_auth_data: &UserAuthData, dto: NewEventDto, pool: &PGPo
     dt: dto.dt,
     place: dto.place,
     creator: user_auth_data.user_id,
+    cancelled_at: None,
+    time_zone,
+    rrule,
+    exdates: dto.exdates,
+    sequence: 0,
+    updated_at: Utc::now(),
+    ical_uid,
    };
-   let res = db::event::create(event, pool)
+   let event_id = event.id;
+   let res = db::event::create(event, end, conn)
       .await;
    match res {
-      Ok(pg_query_result) => {
-         Ok(pg_query_result.rows_affected())
-      },
+      Ok(_) => Ok(event_id),
       Err(_) => {
          Err(MyError::InternalError)
       }
    }
 }
 
-pub async fn get_all(pool: &PGPool) -> Result<Vec<Event>, MyError> {
-   let res = db::event::get_all(pool)
-      .await;
-   match res {
-      Ok(events) => {
-         Ok(events)
+/// returns the number of events created
+pub async fn create(user_auth_data: &UserAuthData, dto: NewEventDto, pool: &PGPool) -> Result<u64, MyError> {
+   insert(user_auth_data, dto, None, pool).await.map(|_| 1)
+}
+
+/// page size of **`GET /event`** when no **`limit`** is given
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 100;
+
+fn parse_sort(sort: Option<&str>) -> Result<Sort, MyError> {
+   match sort {
+      None | Some("dt") => Ok(Sort::DtAsc),
+      Some("-dt") => Ok(Sort::DtDesc),
+      Some("title") => Ok(Sort::TitleAsc),
+      Some("-title") => Ok(Sort::TitleDesc),
+      Some(_) => Err(MyError::BadClientData)
+   }
+}
+
+/// opaque to clients: the sort key of the last event and its id, as url-safe base64
+fn encode_cursor(cursor: &Cursor) -> String {
+   let raw = match cursor {
+      Cursor::Dt(dt, id) => format!("d:{}:{}", id, dt.timestamp_micros()),
+      Cursor::Title(title, id) => format!("t:{}:{}", id, title),
+   };
+   URL_SAFE_NO_PAD.encode(raw)
+}
+
+/// returns **`MyError::BadClientData`** for a malformed cursor or one from a different **`sort`**
+fn decode_cursor(cursor: &str, sort: Sort) -> Result<Cursor, MyError> {
+   let raw = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| MyError::BadClientData)?;
+   let raw = String::from_utf8(raw).map_err(|_| MyError::BadClientData)?;
+   let mut parts = raw.splitn(3, ':');
+   let (kind, id, key) = match (parts.next(), parts.next(), parts.next()) {
+      (Some(kind), Some(id), Some(key)) => (kind, id, key),
+      _ => return Err(MyError::BadClientData)
+   };
+   let id = Uuid::parse_str(id).map_err(|_| MyError::BadClientData)?;
+   match (kind, sort) {
+      ("d", Sort::DtAsc | Sort::DtDesc) => {
+         let micros = key.parse().map_err(|_| MyError::BadClientData)?;
+         let dt = Utc.timestamp_micros(micros).single().ok_or(MyError::BadClientData)?;
+         Ok(Cursor::Dt(dt, id))
       },
+      ("t", Sort::TitleAsc | Sort::TitleDesc) => Ok(Cursor::Title(key.to_string(), id)),
+      _ => Err(MyError::BadClientData)
+   }
+}
+
+/// cancelled events stay visible to the people involved, global moderators see everything
+fn visible_to(viewer: &UserAuthData) -> Option<Uuid> {
+   if viewer.permissions.contains(Permissions::MODERATE) {
+      None
+   } else {
+      Some(viewer.user_id)
+   }
+}
+
+/// **`visible_to`** for a single event: a cancelled event only shows to its organizers, members and participants</br>
+/// returns **`MyError::NotFound`** for anyone else, as if it did not exist
+async fn check_visible(event: &Event, viewer: &UserAuthData, pool: &PGPool) -> Result<(), MyError> {
+   let Some(viewer_id) = visible_to(viewer) else {
+      return Ok(());
+   };
+   if event.cancelled_at.is_none() || event.creator == viewer_id {
+      return Ok(());
+   }
+   if db::event::is_participant(viewer_id, event.id, pool).await {
+      return Ok(());
+   }
+   match db::event_role::get(event.id, viewer_id, pool).await {
+      Ok(Some(_)) => Ok(()),
+      Ok(None) => Err(MyError::NotFound),
       Err(_) => Err(MyError::InternalError)
    }
 }
 
+fn page_size(limit: Option<i64>) -> Result<i64, MyError> {
+   let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+   if (1..=MAX_PAGE_SIZE).contains(&limit) {
+      Ok(limit)
+   } else {
+      Err(MyError::BadClientData)
+   }
+}
+
+/// the occurrences of a recurring event the rule puts into **`[from, to)`**, with their changes applied</br>
+/// excluded ones are left out, cancelled ones are kept with **`cancelled_at`** set
+fn expand(
+   event: &Event,
+   changes: &[OccurrenceOverride],
+   from: DateTime<Utc>,
+   to: DateTime<Utc>
+) -> Result<Vec<EventInstance>, MyError> {
+   let Some((rule, tz)) = recurrence_of(event) else {
+      return Ok(Vec::new());
+   };
+   let occurrences = rule.occurrences(event.dt, tz, from, to).map_err(|err| {
+      error!("[{:} : {:}] EXPANDING EVENT {:} ERROR: {:}", file!(), line!(), event.id, err);
+      MyError::InternalError
+   })?;
+   let instances = occurrences
+      .into_iter()
+      .filter(|occurrence| !event.exdates.contains(occurrence))
+      .map(|occurrence| {
+         let mut instance = Event { dt: occurrence, ..event.clone() };
+         if let Some(changes) = changes.iter().find(|c| c.event_id == event.id && c.occurrence_dt == occurrence) {
+            instance.title = changes.title.clone().unwrap_or(instance.title);
+            instance.descr = changes.descr.clone().unwrap_or(instance.descr);
+            instance.dt = changes.dt.unwrap_or(instance.dt);
+            instance.place = changes.place.clone().or(instance.place);
+            instance.cancelled_at = instance.cancelled_at.or(changes.cancelled_at);
+         }
+         EventInstance { event: instance, occurrence: Some(occurrence) }
+      })
+      .collect();
+   Ok(instances)
+}
+
+/// single events of the window come from the database a page at a time, recurring ones are expanded here</br>
+/// and merged in, both ordered by start and id so the cursor works for either
+async fn get_expanded(
+   filter: Filter,
+   sort: Sort,
+   after: Option<Cursor>,
+   limit: i64,
+   pool: &PGPool
+) -> Result<Vec<EventInstance>, MyError> {
+   let (Some(from), Some(to)) = (filter.from, filter.to) else {
+      return Err(MyError::BadClientData);
+   };
+   if to <= from || to - from > Duration::days(MAX_EXPANSION_DAYS) || !matches!(sort, Sort::DtAsc | Sort::DtDesc) {
+      return Err(MyError::BadClientData);
+   }
+   // a series that started before the window can still have occurrences in it, unless it ended before
+   let series_filter = Filter { from: None, recurring: Some(true), ends_after: Some(from), ..filter.clone() };
+   let singles = db::event::page(&Filter { recurring: Some(false), ..filter }, sort, after.clone(), limit, pool)
+      .await
+      .map_err(|_| MyError::InternalError)?;
+   let series = db::event::page(&series_filter, Sort::DtAsc, None, MAX_EXPANDED_SERIES + 1, pool)
+      .await
+      .map_err(|_| MyError::InternalError)?;
+   if series.len() as i64 > MAX_EXPANDED_SERIES {
+      warn!("[{:} : {:}] MORE THAN {:} SERIES TO EXPAND", file!(), line!(), MAX_EXPANDED_SERIES);
+      return Err(MyError::BadClientData);
+   }
+   let ids: Vec<Uuid> = series.iter().map(|event| event.id).collect();
+   let changes = db::occurrence::get_by_events(&ids, pool)
+      .await
+      .map_err(|_| MyError::InternalError)?;
+   let descending = sort == Sort::DtDesc;
+   let mut occurrences = Vec::new();
+   for event in &series {
+      occurrences.extend(expand(event, &changes, from, to)?);
+   }
+   let occurrences = occurrences.into_iter()
+      .filter(|instance| match &after {
+         Some(Cursor::Dt(dt, id)) if descending => (instance.event.dt, instance.event.id) < (*dt, *id),
+         Some(Cursor::Dt(dt, id)) => (instance.event.dt, instance.event.id) > (*dt, *id),
+         _ => true,
+      });
+   let mut items: Vec<EventInstance> = singles.into_iter()
+      .map(|event| EventInstance { event, occurrence: None })
+      .chain(occurrences)
+      .collect();
+   items.sort_by_key(|instance| (instance.event.dt, instance.event.id));
+   if descending {
+      items.reverse();
+   }
+   items.truncate(limit as usize);
+   Ok(items)
+}
+
+/// events matching every filter of **`query`**, one page at a time</br>
+/// **`limit`** is capped at **`MAX_PAGE_SIZE`**, see **`EventQuery`** for expanding recurring events
+pub async fn get_all(viewer: &UserAuthData, query: EventQuery, pool: &PGPool) -> Result<Page<EventInstance>, MyError> {
+   let sort = parse_sort(query.sort.as_deref())?;
+   let limit = page_size(query.limit)?;
+   let after = query.cursor
+      .as_deref()
+      .map(|cursor| decode_cursor(cursor, sort))
+      .transpose()?;
+   let filter = Filter {
+      from: query.from,
+      to: query.to,
+      place: query.place,
+      creator: query.creator,
+      q: query.q.map(|q| q.trim().to_string()).filter(|q| !q.is_empty()),
+      recurring: None,
+      ends_after: None,
+      visible_to: visible_to(viewer),
+   };
+   // one extra row tells whether there is another page
+   let mut items = if query.expand.unwrap_or(false) {
+      get_expanded(filter, sort, after, limit + 1, pool).await?
+   } else {
+      db::event::page(&filter, sort, after, limit + 1, pool)
+         .await
+         .map_err(|_| MyError::InternalError)?
+         .into_iter()
+         .map(|event| EventInstance { event, occurrence: None })
+         .collect()
+   };
+   let next_cursor = if items.len() as i64 > limit {
+      items.truncate(limit as usize);
+      items.last().map(|last| match sort {
+         Sort::DtAsc | Sort::DtDesc => Cursor::Dt(last.event.dt, last.event.id),
+         Sort::TitleAsc | Sort::TitleDesc => Cursor::Title(last.event.title.clone(), last.event.id),
+      })
+   } else {
+      None
+   };
+   Ok(Page {
+      items,
+      next_cursor: next_cursor.as_ref().map(encode_cursor),
+   })
+}
+
+/// words of **`q`** as a tsquery that needs all of them, the last one may be incomplete</br>
+/// anything but letters and digits separates words, so users cannot inject tsquery operators
+fn to_tsquery(q: &str) -> Option<String> {
+   let words: Vec<&str> = q.split(|c: char| !c.is_alphanumeric())
+      .filter(|word| !word.is_empty())
+      .collect();
+   let (last, rest) = words.split_last()?;
+   let mut terms: Vec<String> = rest.iter().map(|word| word.to_string()).collect();
+   terms.push(format!("{last}:*"));
+   Some(terms.join(" & "))
+}
+
+/// ranked pages can shift while a user scrolls, so the cursor is simply the offset of the next page
+fn decode_offset(cursor: &str) -> Result<i64, MyError> {
+   let raw = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| MyError::BadClientData)?;
+   String::from_utf8(raw)
+      .ok()
+      .and_then(|raw| raw.strip_prefix("o:").and_then(|offset| offset.parse().ok()))
+      .filter(|offset: &i64| *offset >= 0)
+      .ok_or(MyError::BadClientData)
+}
+
+/// full-text search over **`title`** and **`descr`**, best matches first</br>
+/// returns **`MyError::BadClientData`** if **`q`** has no words
+pub async fn search(viewer: &UserAuthData, query: EventSearchQuery, pool: &PGPool) -> Result<Page<EventSearchHit>, MyError> {
+   let tsquery = to_tsquery(&query.q).ok_or(MyError::BadClientData)?;
+   let limit = page_size(query.limit)?;
+   let offset = query.cursor.as_deref().map(decode_offset).transpose()?.unwrap_or(0);
+   let filter = Filter {
+      visible_to: visible_to(viewer),
+      ..Default::default()
+   };
+   let mut items = db::event::search(&filter, &tsquery, limit + 1, offset, pool)
+      .await
+      .map_err(|_| MyError::InternalError)?;
+   let next_cursor = if items.len() as i64 > limit {
+      items.truncate(limit as usize);
+      Some(URL_SAFE_NO_PAD.encode(format!("o:{}", offset + limit)))
+   } else {
+      None
+   };
+   let recurring: Vec<Uuid> = items.iter()
+      .filter(|hit| hit.event.rrule.is_some())
+      .map(|hit| hit.event.id)
+      .collect();
+   if !recurring.is_empty() {
+      let changes = db::occurrence::get_by_events(&recurring, pool)
+         .await
+         .map_err(|_| MyError::InternalError)?;
+      let now = Utc::now();
+      for hit in items.iter_mut() {
+         hit.occurrences = expand(&hit.event, &changes, now, now + Duration::days(MAX_EXPANSION_DAYS))?
+            .into_iter()
+            .filter(|instance| instance.event.cancelled_at.is_none())
+            .filter_map(|instance| instance.occurrence)
+            .take(SEARCH_OCCURRENCES)
+            .collect();
+      }
+   }
+   Ok(Page { items, next_cursor })
+}
+
 pub async fn update(
    id: Uuid, 
    event_fields: UpdateEventDto, 
    user_auth_data: &UserAuthData, 
    pool: &PGPool
 ) -> Result<u64, MyError> {
-   let event_res = db::event::get_by_id(id, pool)
-      .await;
-   match event_res {
-      Ok(event) => {
-         if user_auth_data.user_id == event.creator {
-            let update_res = db::event::set_fields(
-               id, 
-               event_fields, 
-               pool
-            ).await;
-            match update_res {
-               Ok(rows_affected) => Ok(rows_affected),
-               Err(_) => Err(MyError::InternalError)
-            }
-         } else {
-            Err(MyError::Unauthorized)
-         }
-      }
-      Err(_) => Err(MyError::InternalError),
+   let event = event_role::require(id, user_auth_data, EventPermissions::EDIT, pool).await?;
+   // the last occurrence of a series moves with its start
+   let end = event_fields.dt
+      .map(|dt| recurrence_end(dt, event.time_zone.as_deref(), event.rrule.as_deref()))
+      .transpose()?;
+   let mut tx = pool.begin().await.map_err(|_| MyError::InternalError)?;
+   let update_res = db::event::set_fields(
+      id, 
+      event_fields, 
+      &mut *tx
+   ).await;
+   let rows_affected = match update_res {
+      Ok(rows_affected) => rows_affected,
+      Err(_) => return Err(MyError::InternalError)
+   };
+   if let Some(end) = end {
+      db::event::set_recurrence_end(id, end, &mut *tx)
+         .await
+         .map_err(|_| MyError::InternalError)?;
    }
+   tx.commit().await.map_err(|_| MyError::InternalError)?;
+   Ok(rows_affected)
 }
 
-pub async fn get_by_id(id: Uuid, pool: &PGPool) -> Result<Event, MyError> {
-   let res = db::event::get_by_id(id, pool)
-      .await;
-   match res {
-      Ok(event) => Ok(event),
-      Err(_) => Err(MyError::InternalError),
-   }      
+/// cancelled events are only served to those who would see them in **`GET /event`**
+pub async fn get_by_id(id: Uuid, viewer: &UserAuthData, pool: &PGPool) -> Result<Event, MyError> {
+   let event = match db::event::get_by_id(id, pool).await {
+      Ok(event) => event,
+      Err(sqlx::Error::RowNotFound) => return Err(MyError::NotFound),
+      Err(_) => return Err(MyError::InternalError)
+   };
+   check_visible(&event, viewer, pool).await?;
+   Ok(event)
+}
+
+/// the event as an iCalendar file, with its time zone and every changed or cancelled occurrence</br>
+/// visible to the same viewers as **`get_by_id`**
+pub async fn get_ics(id: Uuid, viewer: &UserAuthData, pool: &PGPool) -> Result<String, MyError> {
+   let event = get_by_id(id, viewer, pool).await?;
+   let changes = db::occurrence::get_by_events(&[id], pool)
+      .await
+      .map_err(|_| MyError::InternalError)?;
+   let mut calendar = Calendar::new(None);
+   calendar.add_event(&event, &changes);
+   Ok(calendar.render())
 }
 
 pub async fn is_participant(user_id: Uuid, event_id: Uuid, pool: &PGPool) -> bool {
@@ -78,8 +450,8 @@ pub async fn is_participant(user_id: Uuid, event_id: Uuid, pool: &PGPool) -> boo
 }
 
 async fn _create_invitation(event_id: Uuid, recipient: Uuid, pool: &PGPool) -> Result<u64, MyError> {
-   let user_exists = db::user::exists_by_id(recipient.clone(), pool).await;
-   let event_exists = db::event::exists(event_id.clone(), pool).await;
+   let user_exists = db::user::exists_by_id(recipient, pool).await;
+   let event_exists = db::event::exists(event_id, pool).await;
    if user_exists && event_exists {
       let invitation_id = Uuid::new_v4();
       let invitation_link = create_invitation_link(&event_id);
@@ -100,23 +472,300 @@ async fn _create_invitation(event_id: Uuid, recipient: Uuid, pool: &PGPool) -> R
    }
 }
 
-pub async fn create_invitation(event_id: Uuid, recipient: Uuid, pool: &PGPool) -> Result<u64, MyError> {
-   if db::event::is_participant(recipient.clone(), event_id.clone(), pool).await {
-      _create_invitation(event_id, recipient, pool).await
+pub async fn create_invitation(
+   event_id: Uuid, 
+   user_auth_data: &UserAuthData, 
+   recipient: Uuid, 
+   pool: &PGPool
+) -> Result<u64, MyError> {
+   event_role::require(event_id, user_auth_data, EventPermissions::INVITE, pool).await?;
+   if db::event::is_participant(recipient, event_id, pool).await {
+      Err(MyError::BadClientData)
    } else {
-      Err(MyError::AuthError)
+      _create_invitation(event_id, recipient, pool).await
+   }
+}
+
+/// the participants of an event its viewer may see, see **`get_by_id`**
+pub async fn get_participants(event_id: Uuid, viewer: &UserAuthData, pool: &PGPool) -> Result<Vec<ParticipantDto>, MyError> {
+   get_by_id(event_id, viewer, pool).await?;
+   let res = db::event::get_participants(event_id, pool)
+      .await;
+   match res {
+      Ok(participants) => Ok(participants),
+      Err(_) => Err(MyError::InternalError)
+   }
+}
+
+/// participants may leave on their own, removing others needs **`MANAGE_PARTICIPANTS`**
+pub async fn remove_participant(
+   event_id: Uuid, 
+   user_auth_data: &UserAuthData, 
+   user_id: Uuid, 
+   pool: &PGPool
+) -> Result<u64, MyError> {
+   if user_auth_data.user_id != user_id {
+      event_role::require(event_id, user_auth_data, EventPermissions::MANAGE_PARTICIPANTS, pool).await?;
+   }
+   let res = db::event::remove_participant(event_id, user_id, pool)
+      .await;
+   match res {
+      Ok(0) => Err(MyError::NotFound),
+      Ok(rows_affected) => Ok(rows_affected),
+      Err(_) => Err(MyError::InternalError)
    }
 }
 
-pub async fn subscribe(event_id: Uuid, user_id: Uuid, pool: &PGPool) -> Result<u64, MyError> {
-   let res = db::event::subscribe(event_id, user_id, pool)
+/// **`occurrence`** picks a single occurrence of a recurring event, **`None`** subscribes to the whole series</br>
+/// returns **`MyError::BadClientData`** for cancelled events and occurrences and for repeated subscriptions
+pub async fn subscribe(event_id: Uuid, user_id: Uuid, occurrence: Option<DateTime<Utc>>, pool: &PGPool) -> Result<u64, MyError> {
+   let event = match db::event::get_by_id(event_id, pool).await {
+      Ok(event) if event.cancelled_at.is_some() => return Err(MyError::BadClientData),
+      Ok(event) => event,
+      Err(sqlx::Error::RowNotFound) => return Err(MyError::NotFound),
+      Err(_) => return Err(MyError::InternalError)
+   };
+   if let Some(occurrence) = occurrence {
+      check_occurrence(&event, occurrence)?;
+      let changes = db::occurrence::get(event_id, occurrence, pool)
+         .await
+         .map_err(|_| MyError::InternalError)?;
+      if changes.is_some_and(|changes| changes.cancelled_at.is_some()) {
+         return Err(MyError::BadClientData);
+      }
+   }
+   let res = db::event::subscribe(event_id, user_id, occurrence, pool)
    .await;
    match res {
       Ok(rows_affected) => Ok(rows_affected.rows_affected()),
+      Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Err(MyError::BadClientData),
+      Err(_) => Err(MyError::InternalError)
+   }
+}
+
+/// returns **`MyError::BadClientData`** for single events and **`MyError::NotFound`**</br>
+/// if the rule gives the series no occurrence at **`occurrence`** or it is excluded
+pub fn check_occurrence(event: &Event, occurrence: DateTime<Utc>) -> Result<(), MyError> {
+   let (rule, tz) = recurrence_of(event).ok_or(MyError::BadClientData)?;
+   let is_occurrence = rule.is_occurrence(event.dt, tz, occurrence).map_err(|err| {
+      error!("[{:} : {:}] EXPANDING EVENT {:} ERROR: {:}", file!(), line!(), event.id, err);
+      MyError::InternalError
+   })?;
+   if event.exdates.contains(&occurrence) || !is_occurrence {
+      return Err(MyError::NotFound);
+   }
+   Ok(())
+}
+
+/// replaces rule, time zone and excluded dates of the event, needs **`EDIT`**</br>
+/// changes made to single occurrences stay attached to their original start
+pub async fn set_recurrence(
+   id: Uuid,
+   dto: RecurrenceDto,
+   user_auth_data: &UserAuthData,
+   pool: &PGPool
+) -> Result<u64, MyError> {
+   let event = event_role::require(id, user_auth_data, EventPermissions::EDIT, pool).await?;
+   let (time_zone, rrule) = check_recurrence(dto.time_zone, dto.rrule, &dto.exdates)?;
+   let end = recurrence_end(event.dt, time_zone.as_deref(), rrule.as_deref())?;
+   db::event::set_recurrence(id, time_zone, rrule, &dto.exdates, end, pool)
+      .await
+      .map_err(|_| MyError::InternalError)
+}
+
+/// changes a single occurrence of a recurring event, replacing earlier changes to it, needs **`EDIT`**
+pub async fn update_occurrence(
+   id: Uuid,
+   occurrence: DateTime<Utc>,
+   dto: OccurrenceDto,
+   user_auth_data: &UserAuthData,
+   pool: &PGPool
+) -> Result<u64, MyError> {
+   let event = event_role::require(id, user_auth_data, EventPermissions::EDIT, pool).await?;
+   check_occurrence(&event, occurrence)?;
+   let rows_affected = db::occurrence::upsert(OccurrenceOverride {
+      event_id: id,
+      occurrence_dt: occurrence,
+      title: dto.title,
+      descr: dto.descr,
+      dt: dto.dt,
+      place: dto.place,
+      cancelled_at: None,
+   }, pool)
+   .await
+   .map_err(|_| MyError::InternalError)?;
+   db::event::touch(id, pool).await.map_err(|_| MyError::InternalError)?;
+   Ok(rows_affected)
+}
+
+/// calls a single occurrence off, the rest of the series goes on, needs **`EDIT`**</br>
+/// returns **`MyError::BadClientData`** if it already is cancelled
+pub async fn cancel_occurrence(
+   id: Uuid,
+   occurrence: DateTime<Utc>,
+   user_auth_data: &UserAuthData,
+   pool: &PGPool
+) -> Result<u64, MyError> {
+   let event = event_role::require(id, user_auth_data, EventPermissions::EDIT, pool).await?;
+   check_occurrence(&event, occurrence)?;
+   match db::occurrence::cancel(id, occurrence, pool).await {
+      Ok(0) => Err(MyError::BadClientData),
+      Ok(rows_affected) => {
+         db::event::touch(id, pool).await.map_err(|_| MyError::InternalError)?;
+         Ok(rows_affected)
+      },
       Err(_) => Err(MyError::InternalError)
    }
 }
 
 pub fn create_invitation_link(event_id: &Uuid) -> String {
-   format!("http://127.0.0.1:8080/accept-invitaion/{:?}", *event_id)
+   format!("{}/event/{}/accept-invitation", config::get().public_url(), event_id)
+}
+
+#[cfg(test)]
+mod tests {
+   use crate::service::auth::Credential;
+
+   use super::*;
+
+   fn utc(value: &str) -> DateTime<Utc> {
+      value.parse().unwrap()
+   }
+
+   fn series(rrule: &str, exdates: &[&str]) -> Event {
+      Event {
+         id: Uuid::new_v4(),
+         title: "standup".to_string(),
+         descr: "daily sync".to_string(),
+         dt: utc("2024-01-01T09:00:00Z"),
+         place: Some("room 1".to_string()),
+         creator: Uuid::new_v4(),
+         cancelled_at: None,
+         time_zone: None,
+         rrule: Some(rrule.to_string()),
+         exdates: exdates.iter().map(|value| utc(value)).collect(),
+         sequence: 0,
+         updated_at: utc("2024-01-01T00:00:00Z"),
+         ical_uid: None,
+      }
+   }
+
+   fn change(event: &Event, occurrence: &str) -> OccurrenceOverride {
+      OccurrenceOverride {
+         event_id: event.id,
+         occurrence_dt: utc(occurrence),
+         title: None,
+         descr: None,
+         dt: None,
+         place: None,
+         cancelled_at: None,
+      }
+   }
+
+   #[test]
+   fn expand_leaves_out_exdates() {
+      let event = series("FREQ=DAILY;COUNT=4", &["2024-01-02T09:00:00Z"]);
+      let instances = expand(&event, &[], utc("2024-01-01T00:00:00Z"), utc("2024-02-01T00:00:00Z")).unwrap();
+      let occurrences: Vec<_> = instances.iter().filter_map(|instance| instance.occurrence).collect();
+      assert_eq!(occurrences, vec![utc("2024-01-01T09:00:00Z"), utc("2024-01-03T09:00:00Z"), utc("2024-01-04T09:00:00Z")]);
+   }
+
+   #[test]
+   fn expand_applies_changed_occurrences() {
+      let event = series("FREQ=DAILY;COUNT=3", &[]);
+      let moved = OccurrenceOverride {
+         title: Some("planning".to_string()),
+         dt: Some(utc("2024-01-02T14:00:00Z")),
+         ..change(&event, "2024-01-02T09:00:00Z")
+      };
+      let cancelled = OccurrenceOverride {
+         cancelled_at: Some(utc("2023-12-31T00:00:00Z")),
+         ..change(&event, "2024-01-03T09:00:00Z")
+      };
+      let other = Event { id: Uuid::new_v4(), ..event.clone() };
+      let unrelated = OccurrenceOverride { title: Some("other".to_string()), ..change(&other, "2024-01-01T09:00:00Z") };
+      let instances = expand(&event, &[moved, cancelled, unrelated], utc("2024-01-01T00:00:00Z"), utc("2024-02-01T00:00:00Z")).unwrap();
+      assert_eq!(instances.len(), 3);
+
+      assert_eq!(instances[0].event.title, "standup");
+      assert_eq!(instances[0].event.dt, utc("2024-01-01T09:00:00Z"));
+
+      assert_eq!(instances[1].occurrence, Some(utc("2024-01-02T09:00:00Z")));
+      assert_eq!(instances[1].event.title, "planning");
+      assert_eq!(instances[1].event.descr, "daily sync");
+      assert_eq!(instances[1].event.dt, utc("2024-01-02T14:00:00Z"));
+      assert_eq!(instances[1].event.place.as_deref(), Some("room 1"));
+      assert!(instances[1].event.cancelled_at.is_none());
+
+      assert_eq!(instances[2].event.cancelled_at, Some(utc("2023-12-31T00:00:00Z")));
+   }
+
+   #[test]
+   fn single_events_have_no_occurrences() {
+      let event = Event { rrule: None, ..series("FREQ=DAILY", &[]) };
+      assert!(expand(&event, &[], utc("2024-01-01T00:00:00Z"), utc("2024-02-01T00:00:00Z")).unwrap().is_empty());
+      assert!(recurrence_end(event.dt, None, None).unwrap().is_none());
+      assert!(matches!(check_occurrence(&event, event.dt), Err(MyError::BadClientData)));
+   }
+
+   #[test]
+   fn running_series_are_upcoming() {
+      let now = utc("2024-06-01T00:00:00Z");
+      let single = Event { rrule: None, ..series("FREQ=DAILY", &[]) };
+      assert!(!is_upcoming(&single, now));
+      assert!(is_upcoming(&Event { dt: utc("2024-07-01T09:00:00Z"), ..single }, now));
+      assert!(is_upcoming(&series("FREQ=WEEKLY", &[]), now));
+      assert!(is_upcoming(&series("FREQ=DAILY;UNTIL=20240701", &[]), now));
+      assert!(!is_upcoming(&series("FREQ=DAILY;COUNT=10", &[]), now));
+   }
+
+   #[test]
+   fn check_occurrence_rejects_exdates_and_other_times() {
+      let event = series("FREQ=WEEKLY", &["2024-01-08T09:00:00Z"]);
+      assert!(check_occurrence(&event, utc("2024-01-15T09:00:00Z")).is_ok());
+      assert!(matches!(check_occurrence(&event, utc("2024-01-08T09:00:00Z")), Err(MyError::NotFound)));
+      assert!(matches!(check_occurrence(&event, utc("2024-01-16T09:00:00Z")), Err(MyError::NotFound)));
+   }
+
+   #[sqlx::test(migrations = "./migrations")]
+   async fn moving_a_series_moves_its_end(pool: PGPool) {
+      let user_id = Uuid::new_v4();
+      sqlx::query("INSERT INTO users (id, username, pwd_hash) VALUES ($1, 'organizer', '')")
+         .bind(user_id)
+         .execute(&pool)
+         .await
+         .unwrap();
+      let viewer = UserAuthData {
+         user_id,
+         username: "organizer".to_string(),
+         credential: Credential::Session(Uuid::new_v4()),
+         permissions: Permissions::empty(),
+      };
+      let dto = NewEventDto {
+         title: "standup".to_string(),
+         descr: "daily sync".to_string(),
+         dt: utc("2024-01-01T09:00:00Z"),
+         place: None,
+         time_zone: Some("Europe/Berlin".to_string()),
+         rrule: Some("FREQ=DAILY;COUNT=3".to_string()),
+         exdates: Vec::new(),
+      };
+      let id = insert(&viewer, dto, None, &pool).await.unwrap();
+      let moved = UpdateEventDto { title: None, descr: None, dt: Some(utc("2024-03-01T09:00:00Z")), place: None };
+      assert_eq!(update(id, moved, &viewer, &pool).await.unwrap(), 1);
+      let (dt, end): (DateTime<Utc>, Option<DateTime<Utc>>) = sqlx::query_as("SELECT dt, recurrence_end FROM events WHERE id = $1")
+         .bind(id)
+         .fetch_one(&pool)
+         .await
+         .unwrap();
+      assert_eq!(dt, utc("2024-03-01T09:00:00Z"));
+      assert_eq!(end, Some(utc("2024-03-03T09:00:00Z")));
+   }
+
+   #[test]
+   fn recurrence_end_rejects_unreachable_counts() {
+      let start = utc("2024-01-01T09:00:00Z");
+      assert_eq!(recurrence_end(start, Some("Europe/Berlin"), Some("FREQ=DAILY;COUNT=2")).unwrap(), Some(utc("2024-01-02T09:00:00Z")));
+      assert!(matches!(recurrence_end(start, None, Some("FREQ=DAILY;COUNT=1000000")), Err(MyError::BadClientData)));
+   }
 }
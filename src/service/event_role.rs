@@ -0,0 +1,140 @@
+use log::error;
+use sqlx::{Acquire, PgExecutor, Postgres};
+use uuid::Uuid;
+
+use crate::{db, dto::EventRoleDto, errors::MyError, models::Event, PGPool};
+
+use super::{auth::UserAuthData, rbac::{EventPermissions, EventRole, Permissions}};
+
+fn internal(err: sqlx::Error) -> MyError {
+   error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+   MyError::InternalError
+}
+
+async fn get_event(event_id: Uuid, conn: impl PgExecutor<'_>) -> Result<Event, MyError> {
+   match db::event::get_by_id(event_id, conn).await {
+      Ok(event) => Ok(event),
+      Err(sqlx::Error::RowNotFound) => Err(MyError::NotFound),
+      Err(err) => Err(internal(err)),
+   }
+}
+
+/// role of **`user_id`** in the event, the creator is always the owner
+async fn role_of(event: &Event, user_id: Uuid, conn: impl PgExecutor<'_>) -> Result<Option<EventRole>, MyError> {
+   if event.creator == user_id {
+      return Ok(Some(EventRole::Owner));
+   }
+   let role = db::event_role::get(event.id, user_id, conn)
+      .await
+      .map_err(internal)?;
+   Ok(role.as_deref().and_then(EventRole::from_db))
+}
+
+/// event-level permissions of the user, global moderators may edit and manage any event
+pub async fn permissions(event: &Event, user_auth_data: &UserAuthData, conn: impl PgExecutor<'_>) -> Result<EventPermissions, MyError> {
+   let mut permissions = role_of(event, user_auth_data.user_id, conn)
+      .await?
+      .map_or(EventPermissions::empty(), |role| role.permissions());
+   if user_auth_data.permissions.contains(Permissions::MODERATE) {
+      permissions |= EventPermissions::EDIT | EventPermissions::INVITE | EventPermissions::MANAGE_PARTICIPANTS;
+   }
+   Ok(permissions)
+}
+
+/// loads the event and checks that the user holds **`required`** in it</br>
+/// returns **`MyError::Forbidden`** otherwise
+pub async fn require(
+   event_id: Uuid,
+   user_auth_data: &UserAuthData,
+   required: EventPermissions,
+   conn: impl Acquire<'_, Database = Postgres>
+) -> Result<Event, MyError> {
+   let mut conn = conn.acquire().await.map_err(internal)?;
+   let event = get_event(event_id, &mut *conn).await?;
+   if permissions(&event, user_auth_data, &mut *conn).await?.contains(required) {
+      Ok(event)
+   } else {
+      Err(MyError::Forbidden)
+   }
+}
+
+pub async fn get_all(event_id: Uuid, pool: &PGPool) -> Result<Vec<EventRoleDto>, MyError> {
+   let event = get_event(event_id, pool).await?;
+   let rows = db::event_role::get_all(event_id, pool)
+      .await
+      .map_err(internal)?;
+   let mut roles: Vec<EventRoleDto> = rows.into_iter()
+      .filter(|row| row.user_id != event.creator)
+      .filter_map(|row| EventRole::from_db(&row.role).map(|role| EventRoleDto { user_id: row.user_id, role }))
+      .collect();
+   roles.insert(0, EventRoleDto { user_id: event.creator, role: EventRole::Owner });
+   Ok(roles)
+}
+
+/// a member may only hand out roles below their own and only change members ranked below them
+async fn check_rank(event: &Event, caller: &UserAuthData, target: Uuid, pool: &PGPool) -> Result<EventRole, MyError> {
+   if !permissions(event, caller, pool).await?.contains(EventPermissions::MANAGE_ROLES) {
+      return Err(MyError::Forbidden);
+   }
+   let caller_role = role_of(event, caller.user_id, pool)
+      .await?
+      .ok_or(MyError::Forbidden)?;
+   if let Some(target_role) = role_of(event, target, pool).await? {
+      if target_role >= caller_role {
+         return Err(MyError::Forbidden);
+      }
+   }
+   Ok(caller_role)
+}
+
+pub async fn set(
+   event_id: Uuid,
+   caller: &UserAuthData,
+   target: Uuid,
+   role: EventRole,
+   pool: &PGPool
+) -> Result<u64, MyError> {
+   // ownership only changes hands through transfer
+   if role == EventRole::Owner {
+      return Err(MyError::BadClientData);
+   }
+   let event = get_event(event_id, pool).await?;
+   let caller_role = check_rank(&event, caller, target, pool).await?;
+   if role >= caller_role {
+      return Err(MyError::Forbidden);
+   }
+   if !db::user::exists_by_id(target, pool).await {
+      return Err(MyError::NotFound);
+   }
+   db::event_role::set(event_id, target, role.as_str(), pool)
+      .await
+      .map(|res| res.rows_affected())
+      .map_err(internal)
+}
+
+pub async fn remove(event_id: Uuid, caller: &UserAuthData, target: Uuid, pool: &PGPool) -> Result<u64, MyError> {
+   let event = get_event(event_id, pool).await?;
+   check_rank(&event, caller, target, pool).await?;
+   db::event_role::delete(event_id, target, pool)
+      .await
+      .map_err(internal)
+}
+
+/// hands the event over to **`new_owner`**, the previous owner becomes a co-organizer
+pub async fn transfer_ownership(event_id: Uuid, caller: &UserAuthData, new_owner: Uuid, pool: &PGPool) -> Result<(), MyError> {
+   let event = require(event_id, caller, EventPermissions::TRANSFER_OWNERSHIP, pool).await?;
+   if new_owner == event.creator {
+      return Err(MyError::BadClientData);
+   }
+   if !db::user::exists_by_id(new_owner, pool).await {
+      return Err(MyError::NotFound);
+   }
+   db::event_role::transfer_ownership(
+      event_id,
+      event.creator,
+      new_owner,
+      EventRole::CoOrganizer.as_str(),
+      pool
+   ).await
+   .map_err(internal)
+}
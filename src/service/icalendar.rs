@@ -0,0 +1,526 @@
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::{OffsetComponents, OffsetName, Tz};
+use ical::{parser::ical::component::IcalEvent, property::Property, IcalParser};
+use uuid::Uuid;
+
+use crate::{config, dto::NewEventDto, models::{Event, OccurrenceOverride}};
+
+use super::recurrence;
+
+pub const CONTENT_TYPE: &str = "text/calendar; charset=utf-8";
+const PRODID: &str = "-//event-planning-service//EN";
+/// right part of every **`UID`**, fixed so uids survive a change of **`server.public_url`**
+const UID_DOMAIN: &str = "event-planning-service";
+/// longest line in octets before it is folded, RFC 5545 section 3.1
+const LINE_LIMIT: usize = 75;
+/// how far ahead of now **`VTIMEZONE`** transitions are listed, for series without an end
+const TIME_ZONE_HORIZON_DAYS: i64 = 2 * 366;
+/// how far back they are listed at most, older events fall back to the earliest observance listed;</br>
+/// together with the horizon this bounds the transition scan, however old the events are
+const TIME_ZONE_HISTORY_DAYS: i64 = 5 * 366;
+
+/// stable across updates, so calendar clients replace their copy instead of adding another;</br>
+/// imported events keep the uid they came with
+pub fn uid(event: &Event) -> String {
+    event.ical_uid.clone().unwrap_or_else(|| format!("{}@{UID_DOMAIN}", event.id))
+}
+
+/// the id of an event this service exported, **`None`** for uids of other calendars
+pub fn event_id_of_uid(uid: &str) -> Option<Uuid> {
+    uid.strip_suffix(UID_DOMAIN)
+        .and_then(|id| id.strip_suffix('@'))
+        .and_then(|id| Uuid::parse_str(id).ok())
+}
+
+/// escapes a **`TEXT`** value
+fn text(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\n', '\r'], "\\n")
+}
+
+/// splits lines longer than **`LINE_LIMIT`** octets, continuation lines start with a space
+fn fold(line: &str, out: &mut String) {
+    let mut limit = LINE_LIMIT;
+    let mut rest = line;
+    while rest.len() > limit {
+        let mut split = limit;
+        while !rest.is_char_boundary(split) {
+            split -= 1;
+        }
+        out.push_str(&rest[..split]);
+        out.push_str("\r\n ");
+        rest = &rest[split..];
+        // the leading space counts towards the limit
+        limit = LINE_LIMIT - 1;
+    }
+    out.push_str(rest);
+    out.push_str("\r\n");
+}
+
+fn utc_stamp(dt: DateTime<Utc>) -> String {
+    dt.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// **`+0100`**, seconds are dropped
+fn utc_offset(seconds: i32) -> String {
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{sign}{:02}{:02}", minutes / 60, minutes % 60)
+}
+
+/// the time zone times of the event are written in, **`None`** for UTC
+fn time_zone_of(event: &Event) -> Option<Tz> {
+    event.time_zone.as_deref()
+        .and_then(|time_zone| time_zone.parse::<Tz>().ok())
+        .filter(|tz| *tz != Tz::UTC)
+}
+
+/// a property holding a date-time, local with **`TZID`** if the event has a time zone, UTC otherwise
+fn date_time_property(name: &str, dts: &[DateTime<Utc>], tz: Option<Tz>) -> String {
+    match tz {
+        Some(tz) => {
+            let values: Vec<String> = dts.iter()
+                .map(|dt| dt.with_timezone(&tz).format("%Y%m%dT%H%M%S").to_string())
+                .collect();
+            format!("{name};TZID={}:{}", tz.name(), values.join(","))
+        },
+        None => {
+            let values: Vec<String> = dts.iter().map(|dt| utc_stamp(*dt)).collect();
+            format!("{name}:{}", values.join(","))
+        },
+    }
+}
+
+/// the state of **`tz`** at an instant: offset from UTC in seconds, whether it is daylight saving time and its abbreviation
+fn zone_state(tz: Tz, at: DateTime<Utc>) -> (i32, bool, String) {
+    let offset = tz.offset_from_utc_datetime(&at.naive_utc());
+    let daylight = !offset.dst_offset().is_zero();
+    let name = offset.abbreviation().to_string();
+    (offset.fix().local_minus_utc(), daylight, name)
+}
+
+/// a **`VTIMEZONE`** listing every transition of **`tz`** between **`from`** and **`to`**,</br>
+/// each one spelled out since chrono-tz has no rules to turn into **`RRULE`**s
+fn vtimezone(tz: Tz, from: DateTime<Utc>, to: DateTime<Utc>, lines: &mut Vec<String>) {
+    let component = |lines: &mut Vec<String>, at: DateTime<Utc>, before: &(i32, bool, String), after: &(i32, bool, String)| {
+        let kind = if after.1 { "DAYLIGHT" } else { "STANDARD" };
+        // the onset is written in the local time that was in effect until then
+        let onset = at.naive_utc() + Duration::seconds(before.0.into());
+        lines.push(format!("BEGIN:{kind}"));
+        lines.push(format!("DTSTART:{}", onset.format("%Y%m%dT%H%M%S")));
+        lines.push(format!("TZOFFSETFROM:{}", utc_offset(before.0)));
+        lines.push(format!("TZOFFSETTO:{}", utc_offset(after.0)));
+        lines.push(format!("TZNAME:{}", text(&after.2)));
+        lines.push(format!("END:{kind}"));
+    };
+    lines.push("BEGIN:VTIMEZONE".to_string());
+    lines.push(format!("TZID:{}", tz.name()));
+    let mut state = zone_state(tz, from);
+    component(lines, from, &state, &state);
+    let mut day = from;
+    while day < to {
+        let next_day = day + Duration::days(1);
+        let next_state = zone_state(tz, next_day);
+        if next_state != state {
+            // the first second of the new state
+            let (mut low, mut high) = (day, next_day);
+            while high - low > Duration::seconds(1) {
+                let middle = low + (high - low) / 2;
+                if zone_state(tz, middle) == state {
+                    low = middle;
+                } else {
+                    high = middle;
+                }
+            }
+            component(lines, high, &state, &next_state);
+            state = next_state;
+        }
+        day = next_day;
+    }
+    lines.push("END:VTIMEZONE".to_string());
+}
+
+/// one **`VCALENDAR`**, events are added one by one and written out by **`render`**
+pub struct Calendar {
+    name: Option<String>,
+    events: Vec<String>,
+    time_zones: Vec<Tz>,
+    earliest: Option<DateTime<Utc>>,
+}
+
+impl Calendar {
+    /// **`name`** is shown by clients subscribing to a feed
+    pub fn new(name: Option<&str>) -> Self {
+        Self {
+            name: name.map(str::to_string),
+            events: Vec::new(),
+            time_zones: Vec::new(),
+            earliest: None,
+        }
+    }
+
+    fn track(&mut self, dt: DateTime<Utc>, tz: Option<Tz>) {
+        if let Some(tz) = tz.filter(|tz| !self.time_zones.contains(tz)) {
+            self.time_zones.push(tz);
+        }
+        self.earliest = Some(self.earliest.map_or(dt, |earliest| earliest.min(dt)));
+    }
+
+    /// a **`VEVENT`**, the whole series when **`recurrence_id`** is **`None`**, else the occurrence starting then
+    fn vevent(&mut self, event: &Event, recurrence_id: Option<DateTime<Utc>>, changes: Option<&OccurrenceOverride>) {
+        let tz = time_zone_of(event);
+        let start = changes.and_then(|changes| changes.dt).or(recurrence_id).unwrap_or(event.dt);
+        let title = changes.and_then(|changes| changes.title.as_deref()).unwrap_or(&event.title);
+        let descr = changes.and_then(|changes| changes.descr.as_deref()).unwrap_or(&event.descr);
+        let place = changes.and_then(|changes| changes.place.as_deref()).or(event.place.as_deref());
+        let cancelled = event.cancelled_at.is_some() || changes.is_some_and(|changes| changes.cancelled_at.is_some());
+        self.track(start, tz);
+        let mut lines = vec![
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}", uid(event)),
+            format!("DTSTAMP:{}", utc_stamp(event.updated_at)),
+            format!("LAST-MODIFIED:{}", utc_stamp(event.updated_at)),
+            format!("SEQUENCE:{}", event.sequence),
+            date_time_property("DTSTART", &[start], tz),
+        ];
+        match recurrence_id {
+            Some(recurrence_id) => lines.push(date_time_property("RECURRENCE-ID", &[recurrence_id], tz)),
+            None => {
+                if let Some(rrule) = &event.rrule {
+                    lines.push(format!("RRULE:{rrule}"));
+                    if !event.exdates.is_empty() {
+                        lines.push(date_time_property("EXDATE", &event.exdates, tz));
+                    }
+                }
+            },
+        }
+        lines.push(format!("SUMMARY:{}", text(title)));
+        if !descr.is_empty() {
+            lines.push(format!("DESCRIPTION:{}", text(descr)));
+        }
+        if let Some(place) = place {
+            lines.push(format!("LOCATION:{}", text(place)));
+        }
+        lines.push(format!("STATUS:{}", if cancelled { "CANCELLED" } else { "CONFIRMED" }));
+        lines.push(format!("URL:{}/event/{}", config::get().public_url(), event.id));
+        lines.push("END:VEVENT".to_string());
+        self.events.extend(lines);
+    }
+
+    /// the event with its recurrence and every changed or cancelled occurrence in **`changes`**
+    pub fn add_event(&mut self, event: &Event, changes: &[OccurrenceOverride]) {
+        self.vevent(event, None, None);
+        if event.rrule.is_some() {
+            for changes in changes.iter().filter(|changes| changes.event_id == event.id) {
+                self.vevent(event, Some(changes.occurrence_dt), Some(changes));
+            }
+        }
+    }
+
+    /// only one occurrence of a recurring event, under the uid of the series
+    pub fn add_occurrence(&mut self, event: &Event, occurrence: DateTime<Utc>, changes: Option<&OccurrenceOverride>) {
+        self.vevent(event, Some(occurrence), changes);
+    }
+
+    /// the calendar with CRLF line endings and long lines folded
+    pub fn render(&self) -> String {
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            format!("PRODID:{PRODID}"),
+            "CALSCALE:GREGORIAN".to_string(),
+            "METHOD:PUBLISH".to_string(),
+        ];
+        if let Some(name) = &self.name {
+            lines.push(format!("X-WR-CALNAME:{}", text(name)));
+        }
+        if let Some(earliest) = self.earliest {
+            let now = Utc::now();
+            let from = (earliest - Duration::days(1)).max(now - Duration::days(TIME_ZONE_HISTORY_DAYS));
+            let to = now + Duration::days(TIME_ZONE_HORIZON_DAYS);
+            for tz in &self.time_zones {
+                vtimezone(*tz, from, to, &mut lines);
+            }
+        }
+        lines.extend(self.events.iter().cloned());
+        lines.push("END:VCALENDAR".to_string());
+        let mut out = String::new();
+        for line in &lines {
+            fold(line, &mut out);
+        }
+        out
+    }
+}
+
+/// one **`VEVENT`** of an uploaded calendar
+#[derive(Debug)]
+pub struct ParsedEvent {
+    pub uid: String,
+    /// set on entries that change one occurrence of the series with the same uid
+    pub recurrence_id: Option<DateTime<Utc>>,
+    pub cancelled: bool,
+    pub event: NewEventDto,
+}
+
+/// a **`VEVENT`** that could not be read, with what little identifies it
+#[derive(Debug)]
+pub struct ParseFailure {
+    pub uid: Option<String>,
+    pub title: Option<String>,
+    pub reason: String,
+}
+
+fn property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a Property> {
+    event.properties.iter().find(|property| property.name.eq_ignore_ascii_case(name))
+}
+
+fn param<'a>(property: &'a Property, name: &str) -> Option<&'a str> {
+    property.params.as_ref()?
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+/// reverses the escaping of a **`TEXT`** value
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => out.push('\n'),
+            Some(escaped) => out.push(escaped),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn text_value(event: &IcalEvent, name: &str) -> Option<String> {
+    property(event, name)
+        .and_then(|property| property.value.as_deref())
+        .map(unescape)
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// the values of a **`DTSTART`**, **`RECURRENCE-ID`** or **`EXDATE`** and the **`TZID`** they are in;</br>
+/// dates count as midnight, times without a zone as UTC; only IANA zone names are understood,</br>
+/// the **`VTIMEZONE`**s of the file are not read
+fn date_times(property: &Property) -> Result<(Vec<DateTime<Utc>>, Option<Tz>), String> {
+    let value = property.value.as_deref().ok_or_else(|| format!("{} has no value", property.name))?;
+    let tz = param(property, "TZID")
+        .map(|name| name.trim_start_matches('/').parse::<Tz>().map_err(|_| format!("unknown time zone {name:?}")))
+        .transpose()?;
+    let invalid = |raw: &str| format!("{} {raw:?} is not a date", property.name);
+    let values = value.split(',')
+        .map(str::trim)
+        .map(|raw| {
+            if raw.len() == 8 {
+                let date = NaiveDate::parse_from_str(raw, "%Y%m%d").map_err(|_| invalid(raw))?;
+                return Ok(Utc.from_utc_datetime(&date.and_time(Default::default())));
+            }
+            if let Some(utc) = raw.strip_suffix('Z') {
+                let naive = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| invalid(raw))?;
+                return Ok(Utc.from_utc_datetime(&naive));
+            }
+            let naive = NaiveDateTime::parse_from_str(raw, "%Y%m%dT%H%M%S").map_err(|_| invalid(raw))?;
+            match tz {
+                Some(tz) => recurrence::to_utc(tz, naive).ok_or_else(|| invalid(raw)),
+                None => Ok(Utc.from_utc_datetime(&naive)),
+            }
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok((values, tz))
+}
+
+fn read_event(event: &IcalEvent) -> Result<ParsedEvent, String> {
+    let uid = text_value(event, "UID").ok_or("UID is missing")?;
+    let title = text_value(event, "SUMMARY").ok_or("SUMMARY is missing")?;
+    let (starts, tz) = date_times(property(event, "DTSTART").ok_or("DTSTART is missing")?)?;
+    let dt = starts.first().copied().ok_or("DTSTART is missing")?;
+    let recurrence_id = property(event, "RECURRENCE-ID")
+        .map(date_times)
+        .transpose()?
+        .and_then(|(values, _)| values.first().copied());
+    let mut exdates = Vec::new();
+    for exdate in event.properties.iter().filter(|property| property.name.eq_ignore_ascii_case("EXDATE")) {
+        exdates.extend(date_times(exdate)?.0);
+    }
+    let cancelled = property(event, "STATUS")
+        .and_then(|status| status.value.as_deref())
+        .is_some_and(|status| status.eq_ignore_ascii_case("CANCELLED"));
+    Ok(ParsedEvent {
+        uid,
+        recurrence_id,
+        cancelled,
+        event: NewEventDto {
+            title,
+            descr: text_value(event, "DESCRIPTION").unwrap_or_default(),
+            dt,
+            place: text_value(event, "LOCATION"),
+            time_zone: tz.map(|tz| tz.name().to_string()),
+            rrule: property(event, "RRULE").and_then(|rrule| rrule.value.clone()),
+            exdates,
+        },
+    })
+}
+
+/// joins folded lines, a line break followed by a space or tab goes away with both;</br>
+/// the parser trims every line it reads and would drop a space that ended up right before a fold
+fn unfold(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        let rest = &data[i..];
+        let line_break = if rest.starts_with(b"\r\n") { 2 } else if rest.starts_with(b"\n") { 1 } else { 0 };
+        if line_break > 0 && matches!(rest.get(line_break), Some(b' ' | b'\t')) {
+            i += line_break + 1;
+            continue;
+        }
+        out.push(data[i]);
+        i += 1;
+    }
+    out
+}
+
+/// every **`VEVENT`** of every **`VCALENDAR`** in **`data`**, each one read on its own</br>
+/// returns an error if **`data`** is not iCalendar at all
+pub fn parse(data: &[u8]) -> Result<Vec<Result<ParsedEvent, ParseFailure>>, String> {
+    let mut entries = Vec::new();
+    let mut calendars = 0;
+    let data = unfold(data);
+    for calendar in IcalParser::new(data.as_slice()) {
+        let calendar = calendar.map_err(|err| err.to_string())?;
+        calendars += 1;
+        for event in &calendar.events {
+            entries.push(read_event(event).map_err(|reason| ParseFailure {
+                uid: text_value(event, "UID"),
+                title: text_value(event, "SUMMARY"),
+                reason,
+            }));
+        }
+    }
+    if calendars == 0 {
+        return Err("no VCALENDAR found".to_string());
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calendar(events: &[&str]) -> String {
+        let mut data = "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//test//EN\r\n".to_string();
+        for event in events {
+            data.push_str("BEGIN:VEVENT\r\n");
+            data.push_str(event);
+            data.push_str("END:VEVENT\r\n");
+        }
+        data.push_str("END:VCALENDAR\r\n");
+        data
+    }
+
+    fn parse_one(event: &str) -> Result<ParsedEvent, ParseFailure> {
+        let mut entries = parse(calendar(&[event]).as_bytes()).unwrap();
+        assert_eq!(entries.len(), 1);
+        entries.remove(0)
+    }
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn reads_a_series() {
+        let parsed = parse_one(
+            "UID:weekly@example.com\r\n\
+            SUMMARY:Team\\, weekly\\; sync\r\n\
+            DESCRIPTION:first line\\nsecond \\\\ line\r\n\
+            LOCATION:Room 1\r\n\
+            DTSTART;TZID=Europe/Berlin:20240325T090000\r\n\
+            RRULE:FREQ=WEEKLY;BYDAY=MO\r\n\
+            EXDATE;TZID=Europe/Berlin:20240401T090000,20240408T090000\r\n\
+            STATUS:CONFIRMED\r\n"
+        ).unwrap();
+        assert_eq!(parsed.uid, "weekly@example.com");
+        assert_eq!(parsed.recurrence_id, None);
+        assert!(!parsed.cancelled);
+        assert_eq!(parsed.event.title, "Team, weekly; sync");
+        assert_eq!(parsed.event.descr, "first line\nsecond \\ line");
+        assert_eq!(parsed.event.place.as_deref(), Some("Room 1"));
+        assert_eq!(parsed.event.dt, utc("2024-03-25T08:00:00Z"));
+        assert_eq!(parsed.event.time_zone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(parsed.event.rrule.as_deref(), Some("FREQ=WEEKLY;BYDAY=MO"));
+        assert_eq!(parsed.event.exdates, vec![utc("2024-04-01T07:00:00Z"), utc("2024-04-08T07:00:00Z")]);
+    }
+
+    #[test]
+    fn reads_dates_occurrences_and_cancellations() {
+        let parsed = parse_one(
+            "UID:weekly@example.com\r\n\
+            SUMMARY:Off\r\n\
+            DTSTART;VALUE=DATE:20240501\r\n\
+            RECURRENCE-ID:20240508T090000Z\r\n\
+            STATUS:CANCELLED\r\n"
+        ).unwrap();
+        assert_eq!(parsed.event.dt, utc("2024-05-01T00:00:00Z"));
+        assert_eq!(parsed.event.time_zone, None);
+        assert_eq!(parsed.event.descr, "");
+        assert_eq!(parsed.recurrence_id, Some(utc("2024-05-08T09:00:00Z")));
+        assert!(parsed.cancelled);
+    }
+
+    #[test]
+    fn unfolds_long_lines() {
+        let parsed = parse_one("UID:folded@example.com\r\nSUMMARY:Plan\r\n ning\r\nDTSTART:20240101T100000Z\r\n").unwrap();
+        assert_eq!(parsed.event.title, "Planning");
+
+        let descr = "Überblick über das Quartal, Budget; Ziele\nund nächste Schritte. ".repeat(8);
+        let mut folded = String::new();
+        fold(&format!("DESCRIPTION:{}", text(&descr)), &mut folded);
+        assert!(folded.lines().count() > 1);
+        assert!(folded.split("\r\n").all(|line| line.len() <= LINE_LIMIT));
+        let parsed = parse_one(&format!("UID:long@example.com\r\nSUMMARY:Review\r\nDTSTART:20240101T100000Z\r\n{folded}")).unwrap();
+        assert_eq!(parsed.event.descr, descr.trim_end());
+    }
+
+    #[test]
+    fn reports_broken_entries_on_their_own() {
+        let data = calendar(&[
+            "SUMMARY:No uid\r\nDTSTART:20240101T100000Z\r\n",
+            "UID:zone@example.com\r\nSUMMARY:Bad zone\r\nDTSTART;TZID=Mars/Olympus:20240101T100000\r\n",
+            "UID:fine@example.com\r\nSUMMARY:Fine\r\nDTSTART:20240101T100000Z\r\n",
+        ]);
+        let entries = parse(data.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 3);
+        let missing = entries[0].as_ref().unwrap_err();
+        assert_eq!((missing.uid.as_deref(), missing.title.as_deref()), (None, Some("No uid")));
+        assert_eq!(missing.reason, "UID is missing");
+        let zone = entries[1].as_ref().unwrap_err();
+        assert_eq!(zone.uid.as_deref(), Some("zone@example.com"));
+        assert!(zone.reason.contains("Mars/Olympus"));
+        assert_eq!(entries[2].as_ref().unwrap().uid, "fine@example.com");
+    }
+
+    #[test]
+    fn rejects_anything_but_icalendar() {
+        assert!(parse(b"").is_err());
+        assert!(parse(b"title,dt\nstandup,2024-01-01\n").is_err());
+    }
+
+    #[test]
+    fn recognizes_exported_uids() {
+        let id = Uuid::new_v4();
+        assert_eq!(event_id_of_uid(&format!("{id}@{UID_DOMAIN}")), Some(id));
+        assert_eq!(event_id_of_uid(&format!("{id}@example.com")), None);
+        assert_eq!(event_id_of_uid("weekly@event-planning-service"), None);
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::HashSet;
+use log::{error, info, warn};
+use sqlx::PgConnection;
+use uuid::Uuid;
+
+use crate::{
+    db,
+    dto::{ImportAction, ImportItem, ImportReport, NewEventDto, OccurrenceDto},
+    errors::MyError,
+    models::{Event, OccurrenceOverride},
+    PGPool,
+};
+
+use super::{
+    auth::UserAuthData,
+    event,
+    event_role,
+    icalendar::{self, ParsedEvent},
+    rbac::EventPermissions,
+};
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+fn item(parsed: &ParsedEvent, action: ImportAction) -> ImportItem {
+    ImportItem {
+        uid: Some(parsed.uid.clone()),
+        title: Some(parsed.event.title.clone()),
+        occurrence: parsed.recurrence_id,
+        action,
+        event_id: None,
+        reason: None,
+    }
+}
+
+fn failed(parsed: &ParsedEvent, reason: &str) -> ImportItem {
+    ImportItem {
+        reason: Some(reason.to_string()),
+        ..item(parsed, ImportAction::Failed)
+    }
+}
+
+/// the event **`uid`** stands for in the caller's calendar: the one this service exported under it</br>
+/// if the caller may edit it, else the one the caller imported under it
+async fn find(uid: &str, viewer: &UserAuthData, conn: &mut PgConnection) -> Result<Option<Event>, MyError> {
+    if let Some(id) = icalendar::event_id_of_uid(uid) {
+        match db::event::get_by_id(id, &mut *conn).await {
+            Ok(event) => {
+                if may_edit(event.id, viewer, &mut *conn).await? {
+                    return Ok(Some(event));
+                }
+            },
+            Err(sqlx::Error::RowNotFound) => {},
+            Err(err) => return Err(internal(err)),
+        }
+    }
+    db::event::get_by_ical_uid(viewer.user_id, uid, conn).await.map_err(internal)
+}
+
+/// whether importing **`dto`**, which is not cancelled, would leave the event as it is
+fn unchanged(event: &Event, dto: &NewEventDto) -> bool {
+    let mut stored = event.exdates.clone();
+    let mut imported = dto.exdates.clone();
+    stored.sort();
+    imported.sort();
+    event.title == dto.title
+        && event.descr == dto.descr
+        && event.dt == dto.dt
+        && event.place == dto.place
+        && event.time_zone == dto.time_zone
+        && event.rrule == dto.rrule
+        && stored == imported
+        && event.cancelled_at.is_none()
+}
+
+/// what importing **`parsed`** does to **`existing`**, the event found under its uid
+fn event_action(existing: Option<&Event>, parsed: &ParsedEvent) -> ImportAction {
+    match existing {
+        None if parsed.cancelled => ImportAction::Skipped,
+        None => ImportAction::Created,
+        Some(event) if parsed.cancelled => {
+            if event.cancelled_at.is_some() { ImportAction::Unchanged } else { ImportAction::Cancelled }
+        },
+        Some(event) if unchanged(event, &parsed.event) => ImportAction::Unchanged,
+        Some(_) => ImportAction::Updated,
+    }
+}
+
+/// what importing **`changes`** does to an occurrence, **`existing`** are the changes stored for it
+fn occurrence_action(existing: Option<&OccurrenceOverride>, changes: &OccurrenceDto, cancelled: bool) -> ImportAction {
+    match existing {
+        Some(existing) if cancelled && existing.cancelled_at.is_some() => ImportAction::Unchanged,
+        _ if cancelled => ImportAction::Cancelled,
+        Some(existing) if existing.title == changes.title
+            && existing.descr == changes.descr
+            && existing.dt == changes.dt
+            && existing.place == changes.place => ImportAction::Unchanged,
+        None if changes.title.is_none()
+            && changes.descr.is_none()
+            && changes.dt.is_none()
+            && changes.place.is_none() => ImportAction::Unchanged,
+        _ => ImportAction::Updated,
+    }
+}
+
+/// returns **`false`** if the caller may not edit the event
+async fn may_edit(event_id: Uuid, viewer: &UserAuthData, conn: &mut PgConnection) -> Result<bool, MyError> {
+    match event_role::require(event_id, viewer, EventPermissions::EDIT, conn).await {
+        Ok(_) => Ok(true),
+        Err(MyError::Forbidden) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// a single event or a whole series, created or updated to match the file
+async fn import_event(viewer: &UserAuthData, mut parsed: ParsedEvent, dry_run: bool, conn: &mut PgConnection) -> Result<ImportItem, MyError> {
+    let dto = &mut parsed.event;
+    let recurrence = event::check_recurrence(dto.time_zone.take(), dto.rrule.take(), &dto.exdates)
+        .and_then(|(time_zone, rrule)| {
+            let end = event::recurrence_end(dto.dt, time_zone.as_deref(), rrule.as_deref())?;
+            Ok((time_zone, rrule, end))
+        });
+    let recurrence_end = match recurrence {
+        Ok((time_zone, rrule, end)) => {
+            dto.time_zone = time_zone;
+            dto.rrule = rrule;
+            end
+        },
+        Err(_) => return Ok(failed(&parsed, "invalid recurrence rule")),
+    };
+    let existing = find(&parsed.uid, viewer, &mut *conn).await?;
+    let action = event_action(existing.as_ref(), &parsed);
+    let mut imported = item(&parsed, action);
+    imported.event_id = existing.as_ref().map(|existing| existing.id);
+    if dry_run {
+        return Ok(imported);
+    }
+    match (action, existing) {
+        (ImportAction::Created, _) => {
+            imported.event_id = Some(event::insert(viewer, parsed.event, Some(parsed.uid), conn).await?);
+        },
+        (ImportAction::Cancelled, Some(existing)) => {
+            db::event::cancel(existing.id, conn).await.map_err(internal)?;
+        },
+        (ImportAction::Updated, Some(existing)) => {
+            db::event::replace(existing.id, &parsed.event, recurrence_end, conn).await.map_err(internal)?;
+        },
+        _ => {},
+    }
+    Ok(imported)
+}
+
+/// an entry with **`RECURRENCE-ID`**, applied as a change to that occurrence of its series,</br>
+/// **`planned`** holds the uids a dry run would create, their occurrences cannot be checked yet
+async fn import_occurrence(
+    viewer: &UserAuthData,
+    parsed: ParsedEvent,
+    planned: &HashSet<String>,
+    dry_run: bool,
+    conn: &mut PgConnection
+) -> Result<ImportItem, MyError> {
+    let Some(occurrence) = parsed.recurrence_id else {
+        return Ok(failed(&parsed, "RECURRENCE-ID is missing"));
+    };
+    let Some(series) = find(&parsed.uid, viewer, &mut *conn).await? else {
+        if planned.contains(&parsed.uid) {
+            let action = if parsed.cancelled { ImportAction::Cancelled } else { ImportAction::Updated };
+            return Ok(item(&parsed, action));
+        }
+        return Ok(failed(&parsed, "no recurring event with this UID"));
+    };
+    if event::check_occurrence(&series, occurrence).is_err() {
+        return Ok(failed(&parsed, "RECURRENCE-ID is not an occurrence of the series"));
+    }
+    let existing = db::occurrence::get(series.id, occurrence, &mut *conn)
+        .await
+        .map_err(internal)?;
+    let dto = &parsed.event;
+    // only what differs from the series is stored as a change
+    let changes = OccurrenceDto {
+        title: Some(dto.title.clone()).filter(|title| *title != series.title),
+        descr: Some(dto.descr.clone()).filter(|descr| *descr != series.descr),
+        dt: Some(dto.dt).filter(|dt| *dt != occurrence),
+        place: dto.place.clone().filter(|place| series.place.as_ref() != Some(place)),
+    };
+    let action = occurrence_action(existing.as_ref(), &changes, parsed.cancelled);
+    if !dry_run && matches!(action, ImportAction::Cancelled | ImportAction::Updated) {
+        // find only returns series the caller may edit, these are the writes of update_occurrence and cancel_occurrence
+        if action == ImportAction::Cancelled {
+            db::occurrence::cancel(series.id, occurrence, &mut *conn).await.map_err(internal)?;
+        } else {
+            db::occurrence::upsert(OccurrenceOverride {
+                event_id: series.id,
+                occurrence_dt: occurrence,
+                title: changes.title,
+                descr: changes.descr,
+                dt: changes.dt,
+                place: changes.place,
+                cancelled_at: None,
+            }, &mut *conn)
+            .await
+            .map_err(internal)?;
+        }
+        db::event::touch(series.id, conn).await.map_err(internal)?;
+    }
+    Ok(ImportItem {
+        event_id: Some(series.id),
+        ..item(&parsed, action)
+    })
+}
+
+/// imports every **`VEVENT`** of an iCalendar upload, events are matched by **`UID`** so importing</br>
+/// the same file again changes nothing; events exported by this service are matched by their id</br>
+/// entries with a **`RECURRENCE-ID`** are applied after all series, whatever their order in the file</br>
+/// everything is written in one transaction, an error on the way leaves nothing of the import behind</br>
+/// returns **`MyError::BadClientData`** if **`data`** is not iCalendar, problems with single entries are reported per item
+pub async fn import(viewer: &UserAuthData, data: &[u8], dry_run: bool, pool: &PGPool) -> Result<ImportReport, MyError> {
+    let entries = icalendar::parse(data).map_err(|err| {
+        warn!("[{:} : {:}] INVALID ICALENDAR UPLOAD: {:}", file!(), line!(), err);
+        MyError::BadClientData
+    })?;
+    // a dry run reads through it as well and simply never commits
+    let mut tx = pool.begin().await.map_err(internal)?;
+    let mut items = Vec::new();
+    let mut seen = HashSet::new();
+    let mut planned = HashSet::new();
+    let mut occurrences = Vec::new();
+    for entry in entries {
+        let parsed = match entry {
+            Ok(parsed) => parsed,
+            Err(failure) => {
+                items.push(ImportItem {
+                    uid: failure.uid,
+                    title: failure.title,
+                    occurrence: None,
+                    action: ImportAction::Failed,
+                    event_id: None,
+                    reason: Some(failure.reason),
+                });
+                continue;
+            },
+        };
+        if parsed.recurrence_id.is_some() {
+            occurrences.push(parsed);
+            continue;
+        }
+        if !seen.insert(parsed.uid.clone()) {
+            items.push(failed(&parsed, "duplicate UID"));
+            continue;
+        }
+        let uid = parsed.uid.clone();
+        let imported = import_event(viewer, parsed, dry_run, &mut tx).await?;
+        if imported.action == ImportAction::Created {
+            planned.insert(uid);
+        }
+        items.push(imported);
+    }
+    for parsed in occurrences {
+        items.push(import_occurrence(viewer, parsed, &planned, dry_run, &mut tx).await?);
+    }
+    if !dry_run {
+        tx.commit().await.map_err(internal)?;
+    }
+    let count = |action: ImportAction| items.iter().filter(|item| item.action == action).count();
+    let report = ImportReport {
+        dry_run,
+        created: count(ImportAction::Created),
+        updated: count(ImportAction::Updated),
+        unchanged: count(ImportAction::Unchanged),
+        cancelled: count(ImportAction::Cancelled),
+        skipped: count(ImportAction::Skipped),
+        failed: count(ImportAction::Failed),
+        items,
+    };
+    info!(
+        "ICALENDAR IMPORT BY {:?}{}: {:} CREATED, {:} UPDATED, {:} UNCHANGED, {:} FAILED",
+        viewer.user_id, if dry_run { " (DRY RUN)" } else { "" },
+        report.created, report.updated, report.unchanged, report.failed
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    fn parsed(cancelled: bool) -> ParsedEvent {
+        ParsedEvent {
+            uid: "weekly@example.com".to_string(),
+            recurrence_id: None,
+            cancelled,
+            event: NewEventDto {
+                title: "Sync".to_string(),
+                descr: "weekly".to_string(),
+                dt: utc("2024-01-01T09:00:00Z"),
+                place: None,
+                time_zone: Some("Europe/Berlin".to_string()),
+                rrule: Some("FREQ=WEEKLY".to_string()),
+                exdates: vec![utc("2024-01-15T09:00:00Z"), utc("2024-01-08T09:00:00Z")],
+            },
+        }
+    }
+
+    fn stored(parsed: &ParsedEvent) -> Event {
+        let dto = &parsed.event;
+        Event {
+            id: Uuid::new_v4(),
+            title: dto.title.clone(),
+            descr: dto.descr.clone(),
+            dt: dto.dt,
+            place: dto.place.clone(),
+            creator: Uuid::new_v4(),
+            cancelled_at: None,
+            time_zone: dto.time_zone.clone(),
+            rrule: dto.rrule.clone(),
+            exdates: vec![utc("2024-01-08T09:00:00Z"), utc("2024-01-15T09:00:00Z")],
+            sequence: 3,
+            updated_at: utc("2024-01-01T00:00:00Z"),
+            ical_uid: Some(parsed.uid.clone()),
+        }
+    }
+
+    #[test]
+    fn creates_new_uids_unless_cancelled() {
+        assert_eq!(event_action(None, &parsed(false)), ImportAction::Created);
+        assert_eq!(event_action(None, &parsed(true)), ImportAction::Skipped);
+    }
+
+    #[test]
+    fn updates_only_what_changed() {
+        let imported = parsed(false);
+        let event = stored(&imported);
+        assert_eq!(event_action(Some(&event), &imported), ImportAction::Unchanged);
+        let moved = Event { dt: utc("2024-01-02T09:00:00Z"), ..event.clone() };
+        assert_eq!(event_action(Some(&moved), &imported), ImportAction::Updated);
+        let fewer_exdates = Event { exdates: Vec::new(), ..event.clone() };
+        assert_eq!(event_action(Some(&fewer_exdates), &imported), ImportAction::Updated);
+        let other_zone = Event { time_zone: None, ..event };
+        assert_eq!(event_action(Some(&other_zone), &imported), ImportAction::Updated);
+    }
+
+    #[test]
+    fn follows_the_status_of_the_file() {
+        let event = stored(&parsed(false));
+        let cancelled = Event { cancelled_at: Some(utc("2024-01-01T00:00:00Z")), ..event.clone() };
+        assert_eq!(event_action(Some(&event), &parsed(true)), ImportAction::Cancelled);
+        assert_eq!(event_action(Some(&cancelled), &parsed(true)), ImportAction::Unchanged);
+        // no longer cancelled in the file, replace calls it on again
+        assert_eq!(event_action(Some(&cancelled), &parsed(false)), ImportAction::Updated);
+    }
+
+    #[test]
+    fn stores_only_real_changes_of_occurrences() {
+        let none = OccurrenceDto { title: None, descr: None, dt: None, place: None };
+        let renamed = OccurrenceDto { title: Some("Retro".to_string()), descr: None, dt: None, place: None };
+        let stored = OccurrenceOverride {
+            event_id: Uuid::new_v4(),
+            occurrence_dt: utc("2024-01-08T09:00:00Z"),
+            title: Some("Retro".to_string()),
+            descr: None,
+            dt: None,
+            place: None,
+            cancelled_at: None,
+        };
+        let cancelled = OccurrenceOverride { cancelled_at: Some(utc("2024-01-01T00:00:00Z")), ..stored.clone() };
+        assert_eq!(occurrence_action(None, &none, false), ImportAction::Unchanged);
+        assert_eq!(occurrence_action(None, &renamed, false), ImportAction::Updated);
+        assert_eq!(occurrence_action(Some(&stored), &renamed, false), ImportAction::Unchanged);
+        assert_eq!(occurrence_action(Some(&stored), &none, false), ImportAction::Updated);
+        assert_eq!(occurrence_action(None, &none, true), ImportAction::Cancelled);
+        assert_eq!(occurrence_action(Some(&stored), &renamed, true), ImportAction::Cancelled);
+        assert_eq!(occurrence_action(Some(&cancelled), &renamed, true), ImportAction::Unchanged);
+    }
+}
@@ -0,0 +1,119 @@
+use std::{collections::HashMap, fs, sync::OnceLock};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    jwk::{AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm, OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType},
+    Algorithm, DecodingKey, EncodingKey,
+};
+use log::info;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rsa::{pkcs1::DecodeRsaPrivateKey, pkcs8::DecodePrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+
+use crate::config::{self, AuthConfig};
+
+static KEY_STORE: OnceLock<KeyStore> = OnceLock::new();
+
+/// private key loaded from a PEM file, RSA keys sign with RS256 and Ed25519 keys with EdDSA
+pub struct SigningKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding_key: EncodingKey,
+    pub decoding_key: DecodingKey,
+    pub jwk: Jwk,
+}
+
+/// every key listed here verifies tokens, only the one named by **`signing_kid`** signs new ones
+pub struct KeyStore {
+    keys: HashMap<String, SigningKey>,
+    signing_kid: String,
+}
+
+fn jwk(kid: &str, key_algorithm: KeyAlgorithm, algorithm: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm,
+    }
+}
+
+impl SigningKey {
+    pub fn from_pem(kid: &str, bytes: &[u8]) -> Result<Self, String> {
+        let parsed = pem::parse(bytes).map_err(|err| format!("key {kid}: {err}"))?;
+        let der = parsed.contents();
+        if parsed.tag() == "PRIVATE KEY" {
+            if let Ok(pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(der) {
+                let x = URL_SAFE_NO_PAD.encode(pair.public_key().as_ref());
+                let jwk = jwk(kid, KeyAlgorithm::EdDSA, AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }));
+                return Self::new(kid, Algorithm::EdDSA, EncodingKey::from_ed_der(der), jwk);
+            }
+        }
+        let rsa_key = match parsed.tag() {
+            "PRIVATE KEY" => RsaPrivateKey::from_pkcs8_der(der).map_err(|err| format!("key {kid}: {err}"))?,
+            "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_der(der).map_err(|err| format!("key {kid}: {err}"))?,
+            tag => return Err(format!("key {kid}: unsupported PEM block {tag}")),
+        };
+        let encoding_key = EncodingKey::from_rsa_pem(bytes).map_err(|err| format!("key {kid}: {err}"))?;
+        let jwk = jwk(kid, KeyAlgorithm::RS256, AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n: URL_SAFE_NO_PAD.encode(rsa_key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(rsa_key.e().to_bytes_be()),
+        }));
+        Self::new(kid, Algorithm::RS256, encoding_key, jwk)
+    }
+
+    fn new(kid: &str, algorithm: Algorithm, encoding_key: EncodingKey, jwk: Jwk) -> Result<Self, String> {
+        let decoding_key = DecodingKey::from_jwk(&jwk).map_err(|err| format!("key {kid}: {err}"))?;
+        Ok(Self { kid: kid.to_string(), algorithm, encoding_key, decoding_key, jwk })
+    }
+}
+
+impl KeyStore {
+    /// loads **`auth.keys`**, **`auth.signing_kid`** picks the key for new tokens</br>
+    /// and defaults to the first one
+    pub fn from_config(config: &AuthConfig) -> Result<Self, String> {
+        let mut keys = HashMap::new();
+        for entry in &config.keys {
+            let bytes = fs::read(&entry.path).map_err(|err| format!("key {}: {}: {err}", entry.kid, entry.path.display()))?;
+            let key = SigningKey::from_pem(&entry.kid, &bytes)?;
+            info!("loaded {:?} jwt key {:?}", key.algorithm, entry.kid);
+            keys.insert(entry.kid.clone(), key);
+        }
+        let signing_kid = config.signing_kid.clone()
+            .or_else(|| config.keys.first().map(|key| key.kid.clone()))
+            .ok_or_else(|| "auth.keys has no keys".to_string())?;
+        if !keys.contains_key(&signing_kid) {
+            return Err(format!("auth.signing_kid {signing_kid:?} is not listed in auth.keys"));
+        }
+        Ok(Self { keys, signing_kid })
+    }
+
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.keys[&self.signing_kid]
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&SigningKey> {
+        self.keys.get(kid)
+    }
+
+    /// public halves of all keys, served at **`/.well-known/jwks.json`**
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet { keys: self.keys.values().map(|key| key.jwk.clone()).collect() }
+    }
+}
+
+/// loads the key store, must be called once at startup
+pub fn init() -> Result<(), String> {
+    let store = KeyStore::from_config(&config::get().auth)?;
+    KEY_STORE.set(store).map_err(|_| "jwt key store is already initialized".to_string())
+}
+
+pub fn store() -> &'static KeyStore {
+    KEY_STORE.get().expect("jwt key store must be initialized at startup")
+}
@@ -0,0 +1,229 @@
+use std::time::Duration;
+use chrono::Utc;
+use futures::future::BoxFuture;
+use ldap3::{dn_escape, ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use log::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{config::LdapConfig, db, errors::MyError, models::User, PGPool};
+
+use super::{account, credentials::{CredentialBackend, LDAP_SOURCE}, crypto, password, rbac::Role};
+
+const MEMBER_OF_ATTRIBUTE: &str = "memberOf";
+/// result code of a bind with a wrong password or an unknown dn
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// binds to the directory as the user, creates the local account on first login</br>
+/// and keeps its role in line with the user's groups on every login
+pub struct LdapBackend {
+    config: &'static LdapConfig,
+    /// normalized group dn and the role its members get
+    group_roles: Vec<(String, Role)>,
+}
+
+/// the user as found in the directory
+struct DirectoryUser {
+    email: Option<String>,
+    groups: Vec<String>,
+}
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+fn directory_error(err: LdapError) -> MyError {
+    error!("[{:} : {:}] LDAP ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+/// lowercases a dn and drops the spaces around its separators so that equal dns compare equal
+fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.split('=').map(str::trim).collect::<Vec<_>>().join("="))
+        .collect::<Vec<_>>()
+        .join(",")
+        .to_lowercase()
+}
+
+fn attribute(entry: &SearchEntry, name: &str) -> Vec<String> {
+    entry.attrs.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, values)| values.clone())
+        .unwrap_or_default()
+}
+
+impl LdapBackend {
+    pub fn new(config: &'static LdapConfig) -> Self {
+        info!("configured ldap directory at {:}", config.url);
+        let group_roles = config.group_roles.iter()
+            .map(|group_role| (normalize_dn(&group_role.group), group_role.role))
+            .collect();
+        Self { config, group_roles }
+    }
+
+    /// highest role any of the user's groups maps onto
+    fn role_for(&self, groups: &[String]) -> Option<Role> {
+        groups.iter()
+            .map(|group| normalize_dn(group))
+            .filter_map(|group| self.group_roles.iter().find(|(dn, _)| *dn == group).map(|(_, role)| *role))
+            .max()
+            .or((!self.config.refuse_unmapped).then_some(self.config.default_role))
+    }
+
+    async fn connect(&self) -> Result<Ldap, MyError> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(self.config.timeout_secs))
+            .set_starttls(self.config.starttls);
+        let (conn, ldap) = LdapConnAsync::with_settings(settings, &self.config.url)
+            .await
+            .map_err(directory_error)?;
+        ldap3::drive!(conn);
+        Ok(ldap)
+    }
+
+    /// binds as **`dn`**, **`false`** means the directory rejected the password
+    async fn bind(ldap: &mut Ldap, dn: &str, pwd: &str) -> Result<bool, MyError> {
+        let res = ldap.simple_bind(dn, pwd).await.map_err(directory_error)?;
+        match res.rc {
+            0 => Ok(true),
+            INVALID_CREDENTIALS => Ok(false),
+            _ => Err(directory_error(LdapError::from(res))),
+        }
+    }
+
+    async fn search(&self, ldap: &mut Ldap, base: &str, scope: Scope, filter: &str) -> Result<Vec<SearchEntry>, MyError> {
+        let attrs = vec![self.config.email_attribute.as_str(), MEMBER_OF_ATTRIBUTE];
+        let (entries, _) = ldap.search(base, scope, filter, attrs)
+            .await
+            .and_then(|res| res.success())
+            .map_err(directory_error)?;
+        Ok(entries.into_iter().map(SearchEntry::construct).collect())
+    }
+
+    /// finds the user's dn either from the template or through the service account,</br>
+    /// **`None`** if there is no such user
+    async fn find_dn(&self, ldap: &mut Ldap, username: &str) -> Result<Option<String>, MyError> {
+        if let Some(template) = &self.config.user_dn {
+            return Ok(Some(template.replace("{username}", &dn_escape(username))));
+        }
+        if let (Some(bind_dn), Some(bind_password)) = (&self.config.bind_dn, &self.config.bind_password) {
+            if !Self::bind(ldap, bind_dn, bind_password).await? {
+                error!("[{:} : {:}] LDAP SERVICE ACCOUNT WAS REJECTED", file!(), line!());
+                return Err(MyError::InternalError);
+            }
+        }
+        let filter = self.config.user_filter.replace("{username}", &ldap_escape(username));
+        let entries = self.search(ldap, &self.config.search_base, Scope::Subtree, &filter).await?;
+        // an ambiguous filter must not pick one of several accounts
+        Ok(match entries.len() {
+            1 => entries.into_iter().next().map(|entry| entry.dn),
+            _ => None,
+        })
+    }
+
+    /// binds as the user and reads their address and groups, **`None`** for wrong credentials
+    async fn lookup(&self, username: &str, pwd: &str) -> Result<Option<DirectoryUser>, MyError> {
+        let mut ldap = self.connect().await?;
+        let Some(dn) = self.find_dn(&mut ldap, username).await? else {
+            return Ok(None);
+        };
+        if !Self::bind(&mut ldap, &dn, pwd).await? {
+            return Ok(None);
+        }
+        let entry = self.search(&mut ldap, &dn, Scope::Base, "(objectClass=*)")
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(MyError::InternalError)?;
+        let groups = match &self.config.group_base {
+            Some(group_base) => {
+                let filter = self.config.group_filter.replace("{dn}", &ldap_escape(&dn));
+                self.search(&mut ldap, group_base, Scope::Subtree, &filter)
+                    .await?
+                    .into_iter()
+                    .map(|group| group.dn)
+                    .collect()
+            },
+            None => attribute(&entry, MEMBER_OF_ATTRIBUTE),
+        };
+        if let Err(err) = ldap.unbind().await {
+            warn!("[{:} : {:}] LDAP UNBIND FAILED: {:?}", file!(), line!(), err);
+        }
+        Ok(Some(DirectoryUser {
+            email: attribute(&entry, &self.config.email_attribute).into_iter().next(),
+            groups,
+        }))
+    }
+
+    /// creates the local account on first login, afterwards only the role follows the directory
+    async fn sync_user(username: &str, directory_user: DirectoryUser, role: Role, pool: &PGPool) -> Result<Option<User>, MyError> {
+        match db::user::get_by_username(username.to_string(), pool).await {
+            Ok(user) if user.auth_source != LDAP_SOURCE => {
+                warn!("[{:} : {:}] LDAP LOGIN OF {:?} REFUSED, THE LOCAL ACCOUNT IS NOT OWNED BY THE DIRECTORY", file!(), line!(), username);
+                Ok(None)
+            },
+            Ok(user) if user.role == role.as_str() => Ok(Some(user)),
+            Ok(user) => {
+                db::user::set_role(user.id, role.as_str(), role.permissions().to_db(), pool)
+                    .await
+                    .map_err(internal)?;
+                info!("ROLE OF LDAP USER {:?} CHANGED FROM {:} TO {:}", username, user.role, role.as_str());
+                db::user::get_by_id(user.id, pool).await.map(Some).map_err(internal)
+            },
+            Err(sqlx::Error::RowNotFound) if account::is_reserved_username(username) => {
+                warn!("[{:} : {:}] LDAP LOGIN OF {:?} REFUSED, THE NAME IS RESERVED", file!(), line!(), username);
+                Ok(None)
+            },
+            Err(sqlx::Error::RowNotFound) => {
+                let id = Uuid::new_v4();
+                let email_verified_at = directory_user.email.as_ref().map(|_| Utc::now());
+                let user = User {
+                    id,
+                    // never checked, the directory holds the password
+                    pwd_hash: password::hash(&crypto::random_token(32)).await?,
+                    username: username.to_string(),
+                    email: directory_user.email,
+                    role: role.as_str().to_string(),
+                    permissions: role.permissions().to_db(),
+                    email_verified_at,
+                    auth_source: LDAP_SOURCE.to_string(),
+                    show_email: false,
+                    show_participations: false,
+                    listed: true,
+                    display_name: None,
+                    bio: None,
+                    time_zone: None,
+                    locale: None,
+                };
+                db::user::create(user, pool).await.map_err(internal)?;
+                info!("PROVISIONED LDAP USER {:?} AS {:}", username, role.as_str());
+                db::user::get_by_id(id, pool).await.map(Some).map_err(internal)
+            },
+            Err(err) => Err(internal(err)),
+        }
+    }
+}
+
+impl CredentialBackend for LdapBackend {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate<'a>(&'a self, username: &'a str, pwd: &'a str, pool: &'a PGPool) -> BoxFuture<'a, Result<Option<User>, MyError>> {
+        Box::pin(async move {
+            // an empty password is an anonymous bind, which most directories accept
+            if username.is_empty() || pwd.is_empty() {
+                return Ok(None);
+            }
+            let Some(directory_user) = self.lookup(username, pwd).await? else {
+                return Ok(None);
+            };
+            let Some(role) = self.role_for(&directory_user.groups) else {
+                info!("LDAP USER {:?} IS IN NO MAPPED GROUP", username);
+                return Ok(None);
+            };
+            Self::sync_user(username, directory_user, role, pool).await
+        })
+    }
+}
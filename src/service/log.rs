@@ -55,9 +55,10 @@ where
    }
 }
 
-pub fn init_logger() {
-   Builder::from_default_env()
-   .parse_default_env()
+/// **`filter`** uses the **`RUST_LOG`** syntax, see **`config::LogConfig`**
+pub fn init_logger(filter: &str) {
+   Builder::new()
+   .parse_filters(filter)
    .format(|buf, record| {
       let level = record.level();
       let color_level = match level {
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+use futures::future::BoxFuture;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use log::{error, info};
+
+use crate::{config::{self, MailConfig, SmtpTls}, errors::MyError};
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// delivers outgoing mail, handlers receive it as **`web::Data<dyn MailSender>`**
+pub trait MailSender: Send + Sync {
+    fn send(&self, mail: Mail) -> BoxFuture<'_, Result<(), MyError>>;
+}
+
+/// sends mail through an SMTP server, e.g. a relay in production
+/// or a local catcher such as MailHog during development
+pub struct SmtpSender {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+/// writes mail to the log instead of sending it, used when no SMTP server is configured
+pub struct LogSender;
+
+impl MailSender for SmtpSender {
+    fn send(&self, mail: Mail) -> BoxFuture<'_, Result<(), MyError>> {
+        Box::pin(async move {
+            let to: Mailbox = mail.to.parse().map_err(|_| MyError::BadClientData)?;
+            let message = Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(mail.subject)
+                .header(ContentType::TEXT_PLAIN)
+                .body(mail.body)
+                .map_err(|err| {
+                    error!("[{:} : {:}] MAIL BUILD ERROR: {:?}", file!(), line!(), err);
+                    MyError::InternalError
+                })?;
+            self.transport
+                .send(message)
+                .await
+                .map(|_| ())
+                .map_err(|err| {
+                    error!("[{:} : {:}] MAIL DELIVERY ERROR: {:?}", file!(), line!(), err);
+                    MyError::InternalError
+                })
+        })
+    }
+}
+
+impl MailSender for LogSender {
+    fn send(&self, mail: Mail) -> BoxFuture<'_, Result<(), MyError>> {
+        Box::pin(async move {
+            info!("MAIL TO {:} | {:}\n{:}", mail.to, mail.subject, mail.body);
+            Ok(())
+        })
+    }
+}
+
+impl SmtpSender {
+    /// **`None`** if **`mail.smtp_host`** is not set
+    pub fn from_config(config: &MailConfig) -> Result<Option<Self>, String> {
+        let Some(host) = &config.smtp_host else {
+            return Ok(None);
+        };
+        let mut builder = match config.smtp_tls {
+            SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpTls::Starttls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|err| format!("mail.smtp_host {host:?}: {err}"))?,
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                .map_err(|err| format!("mail.smtp_host {host:?}: {err}"))?,
+        };
+        if let Some(port) = config.smtp_port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        let from = config.from.parse().map_err(|err| format!("mail.from {:?}: {err}", config.from))?;
+        Ok(Some(Self { transport: builder.build(), from }))
+    }
+}
+
+/// SMTP sender if one is configured, the log otherwise
+pub fn from_config() -> Result<Arc<dyn MailSender>, String> {
+    Ok(match SmtpSender::from_config(&config::get().mail)? {
+        Some(sender) => {
+            info!("sending mail through SMTP");
+            Arc::new(sender)
+        },
+        None => {
+            info!("mail.smtp_host is not set, mail is written to the log");
+            Arc::new(LogSender)
+        }
+    })
+}
+
+/// checks that **`email`** is a single valid address
+pub fn is_valid_address(email: &str) -> bool {
+    email.parse::<lettre::Address>().is_ok()
+}
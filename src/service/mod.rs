@@ -1,5 +1,26 @@
 pub mod user;
 pub mod event;
+pub mod event_role;
 pub mod auth;
+pub mod account;
+pub mod api_key;
+pub mod calendar_feed;
+pub mod cookies;
+pub mod credentials;
 pub mod crypto;
+pub mod email_verification;
+pub mod icalendar;
+pub mod import;
+pub mod keys;
+pub mod ldap;
+pub mod mail;
+pub mod oidc;
+pub mod one_time_token;
+pub mod password;
+pub mod password_reset;
+pub mod rbac;
+pub mod recurrence;
+pub mod session;
+pub mod throttle;
+pub mod two_factor;
 pub mod log;
\ No newline at end of file
@@ -0,0 +1,103 @@
+use chrono::{Duration, Utc};
+use log::{error, warn};
+use uuid::Uuid;
+
+use crate::{db, dto::{OneTimeClaims, TokenType}, errors::MyError, models::OneTimeToken, PGPool};
+
+use super::auth::jwt;
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+/// issues a signed token of **`token_type`** valid for **`ttl`** seconds,</br>
+/// tokens of the same type issued to the user before stop working
+pub async fn issue(
+    token_type: TokenType,
+    user_id: Uuid,
+    email: Option<String>,
+    ttl: i64,
+    pool: &PGPool
+) -> Result<String, MyError> {
+    let now = Utc::now();
+    let jti = Uuid::new_v4();
+    let claims = OneTimeClaims {
+        sub: user_id,
+        typ: token_type,
+        jti,
+        email: email.clone(),
+        iss: jwt::issuer().to_string(),
+        aud: jwt::audience().to_string(),
+        iat: now.timestamp(),
+        nbf: now.timestamp(),
+        exp: now.timestamp() + ttl,
+    };
+    let token = jwt::sign(&claims).map_err(|err| {
+        error!("[{:} : {:}] TOKEN SIGNING ERROR: {:?}", file!(), line!(), err);
+        MyError::InternalError
+    })?;
+    db::one_time_token::invalidate(user_id, token_type.as_str(), pool)
+        .await
+        .map_err(internal)?;
+    db::one_time_token::create(OneTimeToken {
+        jti,
+        user_id,
+        purpose: token_type.as_str().to_string(),
+        email,
+        created_at: now,
+        expires_at: now + Duration::seconds(ttl),
+        used_at: None,
+    }, pool)
+    .await
+    .map_err(internal)?;
+    Ok(token)
+}
+
+/// checks the signature and type of **`token`** and uses it up</br>
+/// returns **`MyError::BadClientData`** for invalid, expired or already used tokens
+pub async fn consume(token_type: TokenType, token: &str, pool: &PGPool) -> Result<OneTimeToken, MyError> {
+    let claims = jwt::verify::<OneTimeClaims>(token)
+        .map_err(|err| {
+            warn!("[{:} : {:}] INVALID ONE-TIME TOKEN: {:?}", file!(), line!(), err);
+            MyError::BadClientData
+        })?
+        .claims;
+    if claims.typ != token_type {
+        return Err(MyError::BadClientData);
+    }
+    db::one_time_token::consume(claims.jti, token_type.as_str(), pool)
+        .await
+        .map_err(internal)?
+        .filter(|stored| stored.user_id == claims.sub)
+        .ok_or(MyError::BadClientData)
+}
+
+/// allows one token of **`token_type`** per **`interval`** seconds and at most **`per_hour`** an hour</br>
+/// returns **`MyError::TooManyRequests`** with the seconds left until the next one otherwise
+pub async fn throttle(
+    token_type: TokenType,
+    user_id: Uuid,
+    interval: i64,
+    per_hour: usize,
+    pool: &PGPool
+) -> Result<(), MyError> {
+    let now = Utc::now();
+    let hour_ago = now - Duration::hours(1);
+    let issued = db::one_time_token::issued_since(user_id, token_type.as_str(), hour_ago, pool)
+        .await
+        .map_err(internal)?;
+    let wait_interval = issued.first()
+        .map_or(0, |last| (*last + Duration::seconds(interval) - now).num_seconds());
+    let wait_hourly = if issued.len() >= per_hour {
+        // the oldest token in the window has to leave it first
+        issued.get(per_hour - 1).map_or(0, |oldest| (*oldest - hour_ago).num_seconds())
+    } else {
+        0
+    };
+    let retry_after = wait_interval.max(wait_hourly);
+    if retry_after > 0 {
+        return Err(MyError::TooManyRequests { retry_after: retry_after as u64 });
+    }
+    Ok(())
+}
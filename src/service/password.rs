@@ -0,0 +1,165 @@
+use std::sync::OnceLock;
+use actix_web::web;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use log::error;
+
+use crate::{config::{self, PasswordConfig}, errors::MyError};
+
+use super::crypto;
+
+/// Argon2id hash of a random password, checked against when there is no stored hash
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// result of checking a password against a stored hash
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Invalid,
+    /// password matches, **`needs_rehash`** is set when the stored hash
+    /// is a legacy SHA3 digest or uses outdated cost parameters
+    Valid { needs_rehash: bool },
+}
+
+/// Argon2id with the cost parameters of **`[password]`**, they are checked at startup
+fn hasher(config: &PasswordConfig) -> Result<Argon2<'static>, MyError> {
+    let params = Params::new(config.memory_kib, config.iterations, config.parallelism, None)
+        .map_err(|err| {
+            error!("[{:} : {:}] INVALID ARGON2 PARAMS: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// runs the hashing on the blocking thread pool, a hash takes long enough to stall an actix worker
+async fn blocking<T: Send + 'static>(work: impl FnOnce(&PasswordConfig) -> Result<T, MyError> + Send + 'static) -> Result<T, MyError> {
+    web::block(move || work(&config::get().password))
+        .await
+        .map_err(|err| {
+            error!("[{:} : {:}] PASSWORD HASHING TASK FAILED: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })?
+}
+
+/// rules every new password has to pass, on registration and on change</br>
+/// returns **`MyError::BadClientData`** otherwise
+pub fn check_new(pwd: &str, pwd_confirm: &str) -> Result<(), MyError> {
+    if pwd != pwd_confirm {
+        return Err(MyError::BadClientData);
+    }
+    Ok(())
+}
+
+/// hashes **`pwd`** with Argon2id and a random salt</br>
+/// returns the hash as a PHC string
+pub async fn hash(pwd: &str) -> Result<String, MyError> {
+    let pwd = pwd.to_string();
+    blocking(move |config| hash_with(&pwd, config)).await
+}
+
+fn hash_with(pwd: &str, config: &PasswordConfig) -> Result<String, MyError> {
+    let salt = SaltString::generate(&mut OsRng);
+    hasher(config)?
+        .hash_password(pwd.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| {
+            error!("[{:} : {:}] PASSWORD HASHING ERROR: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })
+}
+
+/// unsalted SHA3-256 hex digests written before Argon2id was introduced
+fn is_legacy(stored: &str) -> bool {
+    stored.len() == 64 && stored.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// checks **`pwd`** against **`stored`**, which is either a PHC string</br>
+/// or a legacy SHA3-256 digest
+pub async fn verify(pwd: &str, stored: &str) -> Result<Verification, MyError> {
+    let (pwd, stored) = (pwd.to_string(), stored.to_string());
+    blocking(move |config| verify_with(&pwd, &stored, config)).await
+}
+
+fn verify_with(pwd: &str, stored: &str, config: &PasswordConfig) -> Result<Verification, MyError> {
+    if is_legacy(stored) {
+        let digest = crypto::get_sha3_256_hash(&pwd.to_string());
+        let stored = stored.to_ascii_uppercase();
+        return Ok(if crypto::constant_time_eq(digest.as_bytes(), stored.as_bytes()) {
+            Verification::Valid { needs_rehash: true }
+        } else {
+            Verification::Invalid
+        });
+    }
+    let parsed = PasswordHash::new(stored).map_err(|err| {
+        error!("[{:} : {:}] MALFORMED PASSWORD HASH: {:?}", file!(), line!(), err);
+        MyError::InternalError
+    })?;
+    let hasher = hasher(config)?;
+    if hasher.verify_password(pwd.as_bytes(), &parsed).is_err() {
+        return Ok(Verification::Invalid);
+    }
+    let current = hasher.params();
+    let needs_rehash = parsed.algorithm != Algorithm::Argon2id.ident()
+        || Params::try_from(&parsed).map_or(true, |stored_params| {
+            stored_params.m_cost() != current.m_cost()
+                || stored_params.t_cost() != current.t_cost()
+                || stored_params.p_cost() != current.p_cost()
+        });
+    Ok(Verification::Valid { needs_rehash })
+}
+
+/// does the work of a failed **`verify`** for logins without a stored hash to check,</br>
+/// so the response time does not tell whether the username exists
+pub async fn verify_dummy(pwd: &str) {
+    let pwd = pwd.to_string();
+    let _ = blocking(move |config| {
+        let stored = match DUMMY_HASH.get() {
+            Some(stored) => stored,
+            None => DUMMY_HASH.get_or_init(|| hash_with(&crypto::random_token(32), config).unwrap_or_default()),
+        };
+        verify_with(&pwd, stored, config)
+    }).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// cheap parameters, the defaults take too long for a test
+    fn cheap() -> PasswordConfig {
+        PasswordConfig { memory_kib: 1024, iterations: 1, parallelism: 1 }
+    }
+
+    #[test]
+    fn verifies_its_own_hashes() {
+        let stored = hash_with("correct horse", &cheap()).unwrap();
+        assert!(stored.starts_with("$argon2id$"));
+        assert_eq!(verify_with("correct horse", &stored, &cheap()).unwrap(), Verification::Valid { needs_rehash: false });
+        assert_eq!(verify_with("wrong horse", &stored, &cheap()).unwrap(), Verification::Invalid);
+        assert_ne!(hash_with("correct horse", &cheap()).unwrap(), stored);
+    }
+
+    #[test]
+    fn upgrades_changed_costs() {
+        let stored = hash_with("correct horse", &cheap()).unwrap();
+        let stronger = PasswordConfig { iterations: 2, ..cheap() };
+        assert_eq!(verify_with("correct horse", &stored, &stronger).unwrap(), Verification::Valid { needs_rehash: true });
+    }
+
+    #[test]
+    fn upgrades_legacy_digests() {
+        let digest = crypto::get_sha3_256_hash(&"correct horse".to_string());
+        assert!(is_legacy(&digest));
+        assert_eq!(verify_with("correct horse", &digest, &cheap()).unwrap(), Verification::Valid { needs_rehash: true });
+        assert_eq!(verify_with("correct horse", &digest.to_lowercase(), &cheap()).unwrap(), Verification::Valid { needs_rehash: true });
+        assert_eq!(verify_with("wrong horse", &digest, &cheap()).unwrap(), Verification::Invalid);
+    }
+
+    #[actix_web::test]
+    async fn hashes_off_the_worker() {
+        let stored = hash("correct horse").await.unwrap();
+        assert_eq!(verify("correct horse", &stored).await.unwrap(), Verification::Valid { needs_rehash: false });
+        assert_eq!(verify("wrong horse", &stored).await.unwrap(), Verification::Invalid);
+    }
+}
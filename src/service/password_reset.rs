@@ -0,0 +1,56 @@
+use log::{error, info, warn};
+
+use crate::{config, db, dto::{ResetPasswordDto, TokenType}, errors::MyError, PGPool};
+
+use super::{mail::{Mail, MailSender}, one_time_token, user};
+
+/// seconds between two reset mails for the same account
+const RESEND_INTERVAL: i64 = 60;
+const RESEND_PER_HOUR: usize = 5;
+
+/// mails a reset token to every account registered with **`email`**</br>
+/// always succeeds so the response does not tell which addresses are registered
+pub async fn forgot(email: &str, mailer: &dyn MailSender, pool: &PGPool) -> Result<(), MyError> {
+    let users = db::user::get_by_email(email, pool)
+        .await
+        .map_err(|err| {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })?;
+    for user in users {
+        if let Err(err) = one_time_token::throttle(TokenType::PasswordReset, user.id, RESEND_INTERVAL, RESEND_PER_HOUR, pool).await {
+            info!("PASSWORD RESET MAIL FOR {:?} SKIPPED: {:?}", user.id, err);
+            continue;
+        }
+        let token = one_time_token::issue(
+            TokenType::PasswordReset,
+            user.id,
+            Some(email.to_string()),
+            config::get().auth.password_reset_ttl,
+            pool
+        ).await?;
+        let mail = Mail {
+            to: email.to_string(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Someone asked to reset the password of {}. If it was you, use this token within {} minutes:\n\n{}\n\n\
+                Otherwise you can ignore this mail.\n",
+                user.username,
+                config::get().auth.password_reset_ttl / 60,
+                token
+            ),
+        };
+        if let Err(err) = mailer.send(mail).await {
+            warn!("[{:} : {:}] FAILED TO SEND PASSWORD RESET MAIL: {:?}", file!(), line!(), err);
+        }
+    }
+    Ok(())
+}
+
+/// sets a new password with a token from **`forgot`**, every session of the user is revoked
+pub async fn reset(dto: ResetPasswordDto, pool: &PGPool) -> Result<(), MyError> {
+    // check the new password first so a typo does not burn the token
+    super::password::check_new(&dto.pwd, &dto.pwd_confirm)?;
+    let token = one_time_token::consume(TokenType::PasswordReset, &dto.token, pool).await?;
+    user::set_password(token.user_id, &dto.pwd, &dto.pwd_confirm, pool).await
+}
@@ -0,0 +1,210 @@
+use std::future::{ready, Ready};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::MyError;
+
+use super::auth::UserAuthData;
+
+bitflags! {
+    /// global permissions, stored in **`users.permissions`**
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct Permissions: u32 {
+        const CREATE_EVENT = 1;
+        /// edit or moderate events created by other users
+        const MODERATE = 1 << 1;
+        const MANAGE_USERS = 1 << 2;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Organizer,
+    Moderator,
+    Admin,
+}
+
+/// role given to newly registered users
+pub const DEFAULT_ROLE: Role = Role::Organizer;
+
+impl Role {
+    /// permissions granted when a user is assigned the role
+    pub fn permissions(&self) -> Permissions {
+        match self {
+            Role::User => Permissions::empty(),
+            Role::Organizer => Permissions::CREATE_EVENT,
+            Role::Moderator => Permissions::CREATE_EVENT | Permissions::MODERATE,
+            Role::Admin => Permissions::all(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Organizer => "organizer",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn from_db(role: &str) -> Option<Self> {
+        match role {
+            "user" => Some(Role::User),
+            "organizer" => Some(Role::Organizer),
+            "moderator" => Some(Role::Moderator),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+impl Permissions {
+    /// reads the **`users.permissions`** column, unknown bits are dropped
+    pub fn from_db(bits: i32) -> Self {
+        Permissions::from_bits_truncate(bits as u32)
+    }
+
+    pub fn to_db(self) -> i32 {
+        self.bits() as i32
+    }
+}
+
+bitflags! {
+    /// what a member may do within a single event
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct EventPermissions: u32 {
+        const EDIT = 1;
+        const INVITE = 1 << 1;
+        const MANAGE_PARTICIPANTS = 1 << 2;
+        const MANAGE_ROLES = 1 << 3;
+        const TRANSFER_OWNERSHIP = 1 << 4;
+    }
+}
+
+/// role of a user within one event, stored in **`event_roles`**
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventRole {
+    Attendee,
+    Moderator,
+    CoOrganizer,
+    Owner,
+}
+
+impl EventRole {
+    pub fn permissions(&self) -> EventPermissions {
+        match self {
+            EventRole::Attendee => EventPermissions::empty(),
+            EventRole::Moderator => EventPermissions::INVITE | EventPermissions::MANAGE_PARTICIPANTS,
+            EventRole::CoOrganizer => EventPermissions::all() - EventPermissions::TRANSFER_OWNERSHIP,
+            EventRole::Owner => EventPermissions::all(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventRole::Attendee => "attendee",
+            EventRole::Moderator => "moderator",
+            EventRole::CoOrganizer => "co_organizer",
+            EventRole::Owner => "owner",
+        }
+    }
+
+    pub fn from_db(role: &str) -> Option<Self> {
+        match role {
+            "attendee" => Some(EventRole::Attendee),
+            "moderator" => Some(EventRole::Moderator),
+            "co_organizer" => Some(EventRole::CoOrganizer),
+            "owner" => Some(EventRole::Owner),
+            _ => None,
+        }
+    }
+}
+
+bitflags! {
+    /// what an api key may be used for, stored in **`api_keys.scopes`**
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ApiScopes: u32 {
+        const READ_EVENTS = 1;
+        const WRITE_EVENTS = 1 << 1;
+        const INVITE = 1 << 2;
+    }
+}
+
+/// single api key scope as it appears in requests and responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    ReadEvents,
+    WriteEvents,
+    Invite,
+}
+
+impl ApiScope {
+    pub fn flag(&self) -> ApiScopes {
+        match self {
+            ApiScope::ReadEvents => ApiScopes::READ_EVENTS,
+            ApiScope::WriteEvents => ApiScopes::WRITE_EVENTS,
+            ApiScope::Invite => ApiScopes::INVITE,
+        }
+    }
+}
+
+impl ApiScopes {
+    pub fn from_list(scopes: &[ApiScope]) -> Self {
+        scopes.iter().fold(ApiScopes::empty(), |acc, scope| acc | scope.flag())
+    }
+
+    pub fn to_list(self) -> Vec<ApiScope> {
+        [ApiScope::ReadEvents, ApiScope::WriteEvents, ApiScope::Invite]
+            .into_iter()
+            .filter(|scope| self.contains(scope.flag()))
+            .collect()
+    }
+
+    /// reads the **`api_keys.scopes`** column, unknown bits are dropped
+    pub fn from_db(bits: i32) -> Self {
+        ApiScopes::from_bits_truncate(bits as u32)
+    }
+
+    pub fn to_db(self) -> i32 {
+        self.bits() as i32
+    }
+}
+
+/// the authenticated user, set by **`AuthMiddleware`**</br>
+/// rejects the request with **`MyError::Unauthorized`** outside an authenticated scope
+impl FromRequest for UserAuthData {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        use actix_web::HttpMessage;
+        ready(req.extensions()
+            .get::<UserAuthData>()
+            .cloned()
+            .ok_or(MyError::Unauthorized))
+    }
+}
+
+/// extractor for an authenticated user holding every permission in **`BITS`**,</br>
+/// e.g. **`Require<{ Permissions::CREATE_EVENT.bits() }>`**</br>
+/// rejects the request with **`MyError::Forbidden`** otherwise
+pub struct Require<const BITS: u32>(pub UserAuthData);
+
+impl<const BITS: u32> FromRequest for Require<BITS> {
+    type Error = MyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let required = Permissions::from_bits_retain(BITS);
+        ready(match UserAuthData::from_request(req, payload).into_inner() {
+            Ok(user) if user.permissions.contains(required) => Ok(Require(user)),
+            Ok(_) => Err(MyError::Forbidden),
+            Err(err) => Err(err),
+        })
+    }
+}
@@ -0,0 +1,563 @@
+use std::{fmt, str::FromStr};
+use chrono::{DateTime, Datelike, Duration, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+
+/// periods (days, weeks, months or years) walked at most in one go, rules without **`COUNT`** start</br>
+/// walking at the requested window, so only a **`COUNT`** that takes longer to reach runs into it
+const MAX_PERIODS: i64 = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// the part of an RFC 5545 **`RRULE`** the service understands:</br>
+/// **`FREQ`** (daily to yearly), **`INTERVAL`**, **`COUNT`**, **`UNTIL`**, **`BYDAY`**, **`BYMONTHDAY`**, **`BYMONTH`** and **`WKST`**
+#[derive(Debug, Clone, PartialEq)]
+pub struct RRule {
+    pub freq: Frequency,
+    pub interval: u32,
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+    /// weekdays, optionally the n-th of the month or year, negative counts from the end
+    pub by_day: Vec<(Option<i32>, Weekday)>,
+    /// negative counts from the end of the month
+    pub by_month_day: Vec<i32>,
+    pub by_month: Vec<u32>,
+    pub week_start: Weekday,
+}
+
+fn parse_weekday(value: &str) -> Result<Weekday, String> {
+    match value {
+        "MO" => Ok(Weekday::Mon),
+        "TU" => Ok(Weekday::Tue),
+        "WE" => Ok(Weekday::Wed),
+        "TH" => Ok(Weekday::Thu),
+        "FR" => Ok(Weekday::Fri),
+        "SA" => Ok(Weekday::Sat),
+        "SU" => Ok(Weekday::Sun),
+        _ => Err(format!("{value:?} is not a weekday")),
+    }
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+/// **`MO`**, **`1MO`** or **`-1FR`**
+fn parse_by_day(value: &str) -> Result<(Option<i32>, Weekday), String> {
+    let split = value.len().saturating_sub(2);
+    let (ordinal, weekday) = value.split_at(split);
+    let weekday = parse_weekday(weekday)?;
+    if ordinal.is_empty() {
+        return Ok((None, weekday));
+    }
+    match ordinal.parse::<i32>() {
+        Ok(n) if n != 0 && n.abs() <= 53 => Ok((Some(n), weekday)),
+        _ => Err(format!("{value:?} is not a valid BYDAY entry")),
+    }
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{key}={value} is not a number"))
+}
+
+/// **`YYYYMMDD`**, taken as the whole day, or **`YYYYMMDDTHHMMSS`** with an optional **`Z`**, always UTC
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    let invalid = || format!("UNTIL={value} is not a date");
+    let raw = value.strip_suffix('Z').unwrap_or(value);
+    let naive = if raw.len() == 8 {
+        NaiveDate::parse_from_str(raw, "%Y%m%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(23, 59, 59))
+            .ok_or_else(invalid)?
+    } else {
+        NaiveDateTime::parse_from_str(raw, "%Y%m%dT%H%M%S").map_err(|_| invalid())?
+    };
+    Ok(Utc.from_utc_datetime(&naive))
+}
+
+fn list<T>(value: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    value.split(',').map(|item| parse(item.trim())).collect()
+}
+
+impl FromStr for RRule {
+    type Err = String;
+
+    /// accepts the value with or without the **`RRULE:`** prefix, keys in any order
+    fn from_str(value: &str) -> Result<Self, String> {
+        let value = value.trim().to_ascii_uppercase();
+        let value = value.strip_prefix("RRULE:").unwrap_or(&value);
+        let mut freq = None;
+        let mut rule = RRule {
+            freq: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            by_month_day: Vec::new(),
+            by_month: Vec::new(),
+            week_start: Weekday::Mon,
+        };
+        for part in value.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| format!("{part:?} is not KEY=VALUE"))?;
+            match key {
+                "FREQ" => freq = Some(match value {
+                    "DAILY" => Frequency::Daily,
+                    "WEEKLY" => Frequency::Weekly,
+                    "MONTHLY" => Frequency::Monthly,
+                    "YEARLY" => Frequency::Yearly,
+                    _ => return Err(format!("FREQ={value} is not supported")),
+                }),
+                "INTERVAL" => rule.interval = parse_number(key, value)?,
+                "COUNT" => rule.count = Some(parse_number(key, value)?),
+                "UNTIL" => rule.until = Some(parse_until(value)?),
+                "BYDAY" => rule.by_day = list(value, parse_by_day)?,
+                "BYMONTHDAY" => rule.by_month_day = list(value, |day| parse_number(key, day))?,
+                "BYMONTH" => rule.by_month = list(value, |month| parse_number(key, month))?,
+                "WKST" => rule.week_start = parse_weekday(value)?,
+                _ => return Err(format!("{key} is not supported")),
+            }
+        }
+        rule.freq = freq.ok_or("FREQ is required")?;
+        rule.validate()?;
+        Ok(rule)
+    }
+}
+
+impl RRule {
+    fn validate(&self) -> Result<(), String> {
+        if self.interval == 0 {
+            return Err("INTERVAL must be at least 1".to_string());
+        }
+        if self.count == Some(0) {
+            return Err("COUNT must be at least 1".to_string());
+        }
+        if self.count.is_some() && self.until.is_some() {
+            return Err("COUNT and UNTIL cannot be combined".to_string());
+        }
+        if self.by_month_day.iter().any(|day| *day == 0 || day.abs() > 31) {
+            return Err("BYMONTHDAY must be between 1 and 31 or -31 and -1".to_string());
+        }
+        if self.by_month.iter().any(|month| !(1..=12).contains(month)) {
+            return Err("BYMONTH must be between 1 and 12".to_string());
+        }
+        if self.freq == Frequency::Weekly && !self.by_month_day.is_empty() {
+            return Err("BYMONTHDAY cannot be used with FREQ=WEEKLY".to_string());
+        }
+        let ordinals = self.by_day.iter().any(|(ordinal, _)| ordinal.is_some());
+        if ordinals && matches!(self.freq, Frequency::Daily | Frequency::Weekly) {
+            return Err("numbered BYDAY entries need FREQ=MONTHLY or FREQ=YEARLY".to_string());
+        }
+        Ok(())
+    }
+
+    /// calls **`visit`** with every occurrence after **`start`** in order until it returns **`false`**,</br>
+    /// the rule ends or a period starts after **`last_date`**; periods before **`first_period`** are skipped,</br>
+    /// which only rules without **`COUNT`** can afford</br>
+    /// returns an error instead of walking more than **`MAX_PERIODS`** periods
+    fn walk(
+        &self,
+        start: DateTime<Utc>,
+        tz: Tz,
+        first_period: i64,
+        last_date: Option<NaiveDate>,
+        mut visit: impl FnMut(DateTime<Utc>) -> bool
+    ) -> Result<(), String> {
+        let mut produced = 1;
+        let local_start = start.with_timezone(&tz).naive_local();
+        for period in first_period..first_period + MAX_PERIODS {
+            if self.count.is_some_and(|count| produced >= count) {
+                return Ok(());
+            }
+            let Some((period_start, dates)) = self.period_dates(local_start.date(), period) else {
+                return Ok(());
+            };
+            if last_date.is_some_and(|last_date| period_start > last_date) {
+                return Ok(());
+            }
+            for date in dates {
+                let Some(dt) = to_utc(tz, date.and_time(local_start.time())) else {
+                    continue;
+                };
+                if dt <= start {
+                    continue;
+                }
+                if self.until.is_some_and(|until| dt > until) {
+                    return Ok(());
+                }
+                produced += 1;
+                if !visit(dt) || self.count.is_some_and(|count| produced >= count) {
+                    return Ok(());
+                }
+            }
+        }
+        Err(format!("the rule does not end within {MAX_PERIODS} periods"))
+    }
+
+    /// the last period starting before **`from`**, walking from there cannot miss an occurrence on or after it
+    fn period_before(&self, start: NaiveDate, from: NaiveDate) -> i64 {
+        let elapsed = match self.freq {
+            Frequency::Daily => (from - start).num_days(),
+            Frequency::Weekly => (from - start).num_days() / 7,
+            Frequency::Monthly => i64::from(from.year() - start.year()) * 12 + i64::from(from.month0()) - i64::from(start.month0()),
+            Frequency::Yearly => i64::from(from.year() - start.year()),
+        };
+        (elapsed / i64::from(self.interval) - 1).max(0)
+    }
+
+    /// starts of the occurrences in **`[from, to)`**, **`start`** itself is always the first one</br>
+    /// the rule is applied to the wall-clock time in **`tz`**, so a 09:00 meeting stays at 09:00 across DST changes;</br>
+    /// excluded dates are the caller's business, they still count towards **`COUNT`**</br>
+    /// returns an error if the window spans more than **`MAX_PERIODS`** periods or, for a rule with **`COUNT`**,</br>
+    /// ends that far from **`start`** without the count being reached
+    pub fn occurrences(&self, start: DateTime<Utc>, tz: Tz, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<DateTime<Utc>>, String> {
+        let mut found = Vec::new();
+        if start >= to {
+            return Ok(found);
+        }
+        if start >= from {
+            found.push(start);
+        }
+        let local_start = start.with_timezone(&tz).date_naive();
+        // occurrences have to be counted from the start, anything else can begin at the window
+        let first_period = match self.count {
+            Some(_) => 0,
+            None => self.period_before(local_start, from.with_timezone(&tz).date_naive()),
+        };
+        self.walk(start, tz, first_period, Some(to.with_timezone(&tz).date_naive()), |dt| {
+            if dt >= to {
+                return false;
+            }
+            if dt >= from {
+                found.push(dt);
+            }
+            true
+        })?;
+        Ok(found)
+    }
+
+    /// whether the rule puts an occurrence exactly at **`dt`**
+    pub fn is_occurrence(&self, start: DateTime<Utc>, tz: Tz, dt: DateTime<Utc>) -> Result<bool, String> {
+        Ok(self.occurrences(start, tz, dt, dt + Duration::seconds(1))?.first() == Some(&dt))
+    }
+
+    /// start of the last possible occurrence, **`None`** for a series without end</br>
+    /// returns an error for a **`COUNT`** not reached within **`MAX_PERIODS`** periods
+    pub fn end(&self, start: DateTime<Utc>, tz: Tz) -> Result<Option<DateTime<Utc>>, String> {
+        if let Some(until) = self.until {
+            return Ok(Some(until.max(start)));
+        }
+        if self.count.is_none() {
+            return Ok(None);
+        }
+        let mut last = start;
+        self.walk(start, tz, 0, None, |dt| {
+            last = dt;
+            true
+        })?;
+        Ok(Some(last))
+    }
+
+    /// the first day of the **`period`**-th period counted from the one of **`start`**</br>
+    /// and the dates in it the rule selects, in order
+    fn period_dates(&self, start: NaiveDate, period: i64) -> Option<(NaiveDate, Vec<NaiveDate>)> {
+        let step = period * i64::from(self.interval);
+        let (first, mut dates) = match self.freq {
+            Frequency::Daily => {
+                let date = start.checked_add_signed(Duration::days(step))?;
+                let weekday_matches = self.by_day.is_empty()
+                    || self.by_day.iter().any(|(_, weekday)| *weekday == date.weekday());
+                let day_matches = self.by_month_day.is_empty()
+                    || month_days(date.year(), date.month(), &self.by_month_day).contains(&date.day());
+                (date, if weekday_matches && day_matches { vec![date] } else { Vec::new() })
+            },
+            Frequency::Weekly => {
+                let offset = (7 + start.weekday().num_days_from_monday() - self.week_start.num_days_from_monday()) % 7;
+                let week = start.checked_sub_signed(Duration::days(offset.into()))?
+                    .checked_add_signed(Duration::weeks(step))?;
+                let days = (0..7)
+                    .filter_map(|day| week.checked_add_signed(Duration::days(day)))
+                    .filter(|date| if self.by_day.is_empty() {
+                        date.weekday() == start.weekday()
+                    } else {
+                        self.by_day.iter().any(|(_, weekday)| *weekday == date.weekday())
+                    })
+                    .collect();
+                (week, days)
+            },
+            Frequency::Monthly => {
+                let months = i64::from(start.year()) * 12 + i64::from(start.month0()) + step;
+                let year = i32::try_from(months.div_euclid(12)).ok()?;
+                let month = months.rem_euclid(12) as u32 + 1;
+                (NaiveDate::from_ymd_opt(year, month, 1)?, self.month_dates(year, month, start))
+            },
+            Frequency::Yearly => {
+                let year = start.year().checked_add(i32::try_from(step).ok()?)?;
+                let dates = if !self.by_month.is_empty() || !self.by_month_day.is_empty() {
+                    let months = if self.by_month.is_empty() { (1..=12).collect() } else { self.by_month.clone() };
+                    months.into_iter().flat_map(|month| self.month_dates(year, month, start)).collect()
+                } else if !self.by_day.is_empty() {
+                    let first = NaiveDate::from_ymd_opt(year, 1, 1)?;
+                    let last = NaiveDate::from_ymd_opt(year, 12, 31)?;
+                    weekdays_between(first, last, &self.by_day)
+                } else {
+                    NaiveDate::from_ymd_opt(year, start.month(), start.day()).into_iter().collect()
+                };
+                (NaiveDate::from_ymd_opt(year, 1, 1)?, dates)
+            },
+        };
+        if !self.by_month.is_empty() {
+            dates.retain(|date| self.by_month.contains(&date.month()));
+        }
+        dates.sort();
+        dates.dedup();
+        Some((first, dates))
+    }
+
+    /// dates of one month selected by **`BYMONTHDAY`** and **`BYDAY`**, the day of **`start`** without either
+    fn month_dates(&self, year: i32, month: u32, start: NaiveDate) -> Vec<NaiveDate> {
+        let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+            return Vec::new();
+        };
+        let last = last_day(year, month);
+        let mut dates: Vec<NaiveDate> = if !self.by_day.is_empty() {
+            weekdays_between(first, last, &self.by_day)
+        } else if !self.by_month_day.is_empty() {
+            (1..=last.day()).filter_map(|day| first.with_day(day)).collect()
+        } else {
+            first.with_day(start.day()).into_iter().collect()
+        };
+        if !self.by_month_day.is_empty() {
+            let days = month_days(year, month, &self.by_month_day);
+            dates.retain(|date| days.contains(&date.day()));
+        }
+        dates
+    }
+}
+
+fn last_day(year: i32, month: u32) -> NaiveDate {
+    let (next_year, next_month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+    NaiveDate::from_ymd_opt(next_year, next_month, 1)
+        .and_then(|next| next.pred_opt())
+        .unwrap_or(NaiveDate::MAX)
+}
+
+/// **`BYMONTHDAY`** resolved to days of the given month, days the month does not have are dropped
+fn month_days(year: i32, month: u32, by_month_day: &[i32]) -> Vec<u32> {
+    let length = last_day(year, month).day() as i32;
+    by_month_day.iter()
+        .map(|day| if *day > 0 { *day } else { length + 1 + day })
+        .filter(|day| (1..=length).contains(day))
+        .map(|day| day as u32)
+        .collect()
+}
+
+/// days in **`[first, last]`** matching **`BYDAY`**, numbered entries count within that range
+fn weekdays_between(first: NaiveDate, last: NaiveDate, by_day: &[(Option<i32>, Weekday)]) -> Vec<NaiveDate> {
+    let mut dates = Vec::new();
+    for (ordinal, weekday) in by_day {
+        let matching: Vec<NaiveDate> = first.iter_days()
+            .take_while(|date| *date <= last)
+            .filter(|date| date.weekday() == *weekday)
+            .collect();
+        match ordinal {
+            None => dates.extend(matching),
+            Some(n) if *n > 0 => dates.extend(matching.get(*n as usize - 1)),
+            Some(n) => dates.extend(matching.len().checked_sub(n.unsigned_abs() as usize).and_then(|i| matching.get(i))),
+        }
+    }
+    dates
+}
+
+/// a wall-clock time in **`tz`** as UTC, the earlier one when it is ambiguous;</br>
+/// times skipped by a DST change are read with the offset in effect before the gap, as RFC 5545 asks,</br>
+/// which moves them forward by the length of the gap, whatever that is
+pub fn to_utc(tz: Tz, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+    match tz.from_local_datetime(&local) {
+        LocalResult::Single(dt) => Some(dt.with_timezone(&Utc)),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest.with_timezone(&Utc)),
+        LocalResult::None => {
+            // a day ahead of the gap is well clear of it in every zone
+            let before = tz.offset_from_utc_datetime(&(local - Duration::days(1))).fix();
+            let utc = local.checked_sub_signed(Duration::seconds(before.local_minus_utc().into()))?;
+            Some(Utc.from_utc_datetime(&utc))
+        },
+    }
+}
+
+impl fmt::Display for RRule {
+    /// the normalized rule as stored, without the **`RRULE:`** prefix
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freq = match self.freq {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={freq}")?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self.by_day.iter()
+                .map(|(ordinal, weekday)| match ordinal {
+                    Some(n) => format!("{n}{}", weekday_code(*weekday)),
+                    None => weekday_code(*weekday).to_string(),
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(i32::to_string).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if !self.by_month.is_empty() {
+            let months: Vec<String> = self.by_month.iter().map(u32::to_string).collect();
+            write!(f, ";BYMONTH={}", months.join(","))?;
+        }
+        if self.week_start != Weekday::Mon {
+            write!(f, ";WKST={}", weekday_code(self.week_start))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    fn expand(rule: &str, start: &str, tz: Tz, from: &str, to: &str) -> Vec<DateTime<Utc>> {
+        let rule: RRule = rule.parse().unwrap();
+        rule.occurrences(utc(start), tz, utc(from), utc(to)).unwrap()
+    }
+
+    fn dates(values: &[&str]) -> Vec<DateTime<Utc>> {
+        values.iter().map(|value| utc(value)).collect()
+    }
+
+    #[test]
+    fn parses_and_normalizes() {
+        let rule: RRule = "rrule:byday=mo,-1fr;freq=monthly;interval=1;wkst=su".parse().unwrap();
+        assert_eq!(rule.to_string(), "FREQ=MONTHLY;BYDAY=MO,-1FR;WKST=SU");
+        assert_eq!(rule.to_string().parse::<RRule>().unwrap(), rule);
+        assert!("INTERVAL=2".parse::<RRule>().is_err());
+        assert!("FREQ=DAILY;COUNT=2;UNTIL=20240101".parse::<RRule>().is_err());
+        assert!("FREQ=WEEKLY;BYDAY=1MO".parse::<RRule>().is_err());
+        assert!("FREQ=MONTHLY;BYMONTHDAY=0".parse::<RRule>().is_err());
+        assert!("FREQ=HOURLY".parse::<RRule>().is_err());
+    }
+
+    #[test]
+    fn weekly_by_day() {
+        let found = expand("FREQ=WEEKLY;BYDAY=MO,WE,FR", "2024-01-01T09:00:00Z", Tz::UTC, "2024-01-01T00:00:00Z", "2024-01-08T00:00:00Z");
+        assert_eq!(found, dates(&["2024-01-01T09:00:00Z", "2024-01-03T09:00:00Z", "2024-01-05T09:00:00Z"]));
+    }
+
+    #[test]
+    fn monthly_last_friday() {
+        let found = expand("FREQ=MONTHLY;BYDAY=-1FR", "2024-01-26T18:00:00Z", Tz::UTC, "2024-01-01T00:00:00Z", "2024-04-01T00:00:00Z");
+        assert_eq!(found, dates(&["2024-01-26T18:00:00Z", "2024-02-23T18:00:00Z", "2024-03-29T18:00:00Z"]));
+    }
+
+    #[test]
+    fn monthly_by_month_day() {
+        let found = expand("FREQ=MONTHLY;BYMONTHDAY=31", "2024-01-31T12:00:00Z", Tz::UTC, "2024-01-01T00:00:00Z", "2024-06-01T00:00:00Z");
+        assert_eq!(found, dates(&["2024-01-31T12:00:00Z", "2024-03-31T12:00:00Z", "2024-05-31T12:00:00Z"]));
+        let found = expand("FREQ=MONTHLY;BYMONTHDAY=-1", "2024-01-31T12:00:00Z", Tz::UTC, "2024-01-01T00:00:00Z", "2024-04-01T00:00:00Z");
+        assert_eq!(found, dates(&["2024-01-31T12:00:00Z", "2024-02-29T12:00:00Z", "2024-03-31T12:00:00Z"]));
+    }
+
+    #[test]
+    fn count_includes_the_start() {
+        let rule: RRule = "FREQ=DAILY;INTERVAL=2;COUNT=3".parse().unwrap();
+        let start = utc("2024-01-01T09:00:00Z");
+        let found = rule.occurrences(start, Tz::UTC, start, utc("2025-01-01T00:00:00Z")).unwrap();
+        assert_eq!(found, dates(&["2024-01-01T09:00:00Z", "2024-01-03T09:00:00Z", "2024-01-05T09:00:00Z"]));
+        assert_eq!(rule.end(start, Tz::UTC).unwrap(), Some(utc("2024-01-05T09:00:00Z")));
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let rule: RRule = "FREQ=DAILY;UNTIL=20240103T090000Z".parse().unwrap();
+        let start = utc("2024-01-01T09:00:00Z");
+        let found = rule.occurrences(start, Tz::UTC, start, utc("2025-01-01T00:00:00Z")).unwrap();
+        assert_eq!(found, dates(&["2024-01-01T09:00:00Z", "2024-01-02T09:00:00Z", "2024-01-03T09:00:00Z"]));
+        assert_eq!(rule.end(start, Tz::UTC).unwrap(), Some(utc("2024-01-03T09:00:00Z")));
+        let endless: RRule = "FREQ=DAILY".parse().unwrap();
+        assert_eq!(endless.end(start, Tz::UTC).unwrap(), None);
+    }
+
+    #[test]
+    fn keeps_the_wall_clock_time_across_dst() {
+        let found = expand(
+            "FREQ=WEEKLY",
+            "2024-03-25T08:00:00Z",
+            chrono_tz::Europe::Berlin,
+            "2024-03-25T00:00:00Z",
+            "2024-04-02T00:00:00Z"
+        );
+        assert_eq!(found, dates(&["2024-03-25T08:00:00Z", "2024-04-01T07:00:00Z"]));
+    }
+
+    #[test]
+    fn skips_to_the_window_of_long_series() {
+        let found = expand("FREQ=DAILY", "1700-01-01T09:00:00Z", Tz::UTC, "2024-01-01T00:00:00Z", "2024-01-04T00:00:00Z");
+        assert_eq!(found, dates(&["2024-01-01T09:00:00Z", "2024-01-02T09:00:00Z", "2024-01-03T09:00:00Z"]));
+        let found = expand("FREQ=WEEKLY;INTERVAL=3;BYDAY=TU", "1700-01-05T09:00:00Z", Tz::UTC, "2024-01-01T00:00:00Z", "2024-03-01T00:00:00Z");
+        assert_eq!(found.len(), 3);
+        assert!(found.iter().all(|dt| dt.weekday() == Weekday::Tue));
+        assert_eq!((found[1] - found[0]).num_days(), 21);
+    }
+
+    #[test]
+    fn count_out_of_reach_is_an_error() {
+        let rule: RRule = "FREQ=DAILY;COUNT=200000".parse().unwrap();
+        let start = utc("1700-01-01T09:00:00Z");
+        assert!(rule.end(start, Tz::UTC).is_err());
+        assert!(rule.occurrences(start, Tz::UTC, utc("2024-01-01T00:00:00Z"), utc("2024-01-02T00:00:00Z")).is_err());
+    }
+
+    #[test]
+    fn checks_single_occurrences() {
+        let rule: RRule = "FREQ=WEEKLY;BYDAY=TU".parse().unwrap();
+        let start = utc("2024-01-02T10:00:00Z");
+        assert!(rule.is_occurrence(start, Tz::UTC, utc("2024-01-16T10:00:00Z")).unwrap());
+        assert!(!rule.is_occurrence(start, Tz::UTC, utc("2024-01-17T10:00:00Z")).unwrap());
+        assert!(!rule.is_occurrence(start, Tz::UTC, utc("2024-01-16T10:00:01Z")).unwrap());
+    }
+
+    #[test]
+    fn resolves_gaps_and_overlaps() {
+        let local = |value: &str| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M").unwrap();
+        let berlin = chrono_tz::Europe::Berlin;
+        assert_eq!(to_utc(berlin, local("2024-03-31 02:30")), Some(utc("2024-03-31T01:30:00Z")));
+        assert_eq!(to_utc(berlin, local("2024-10-27 02:30")), Some(utc("2024-10-27T00:30:00Z")));
+        // Lord Howe Island moves its clocks by half an hour, 02:15 becomes 02:45
+        let lord_howe = chrono_tz::Australia::Lord_Howe;
+        let moved = to_utc(lord_howe, local("2024-10-06 02:15")).unwrap();
+        assert_eq!(moved, utc("2024-10-05T15:45:00Z"));
+        assert_eq!(moved.with_timezone(&lord_howe).naive_local(), local("2024-10-06 02:45"));
+    }
+}
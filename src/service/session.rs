@@ -0,0 +1,117 @@
+use chrono::Utc;
+use log::{error, warn};
+use uuid::Uuid;
+
+use crate::{db, dto::{ClientInfo, SessionInfo}, errors::MyError, models::Session, PGPool};
+
+fn map_db_error(err: sqlx::Error) -> MyError {
+    match err {
+        sqlx::Error::RowNotFound => MyError::Unauthorized,
+        err => {
+            error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        }
+    }
+}
+
+/// starts a new token family for **`user_id`**</br>
+/// returns the session id and the jti of its first refresh token
+pub async fn start(user_id: Uuid, client: ClientInfo, pool: &PGPool) -> Result<(Uuid, Uuid), MyError> {
+    let now = Utc::now();
+    let session = Session {
+        id: Uuid::new_v4(),
+        user_id,
+        refresh_jti: Uuid::new_v4(),
+        created_at: now,
+        last_used_at: now,
+        revoked_at: None,
+        user_agent: client.user_agent,
+        ip: client.ip,
+    };
+    let ids = (session.id, session.refresh_jti);
+    db::session::create(session, pool)
+        .await
+        .map_err(map_db_error)?;
+    Ok(ids)
+}
+
+fn ensure_active(session: &Session) -> Result<(), MyError> {
+    if session.revoked_at.is_some() {
+        return Err(MyError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// checks that the session an **`access`** token belongs to has not been revoked</br>
+/// and records the use
+pub async fn check_access(id: Uuid, pool: &PGPool) -> Result<(), MyError> {
+    let session = db::session::get_by_id(id, pool)
+        .await
+        .map_err(map_db_error)?;
+    ensure_active(&session)?;
+    if let Err(err) = db::session::touch(id, pool).await {
+        warn!("[{:} : {:}] FAILED TO UPDATE SESSION LAST USE: {:?}", file!(), line!(), err);
+    }
+    Ok(())
+}
+
+/// checks that **`refresh_jti`** is the current refresh token of an active session</br>
+/// presenting an already rotated refresh token revokes the whole family
+pub async fn check_refresh(id: Uuid, refresh_jti: Uuid, pool: &PGPool) -> Result<(), MyError> {
+    let session = db::session::get_by_id(id, pool)
+        .await
+        .map_err(map_db_error)?;
+    ensure_active(&session)?;
+    if session.refresh_jti != refresh_jti {
+        warn!("[{:} : {:}] REFRESH TOKEN REUSE, REVOKING SESSION {:?}", file!(), line!(), id);
+        revoke(id, pool).await?;
+        return Err(MyError::Unauthorized);
+    }
+    Ok(())
+}
+
+/// invalidates **`refresh_jti`** and returns the jti of the next refresh token in the family
+pub async fn rotate(id: Uuid, refresh_jti: Uuid, pool: &PGPool) -> Result<Uuid, MyError> {
+    check_refresh(id, refresh_jti, pool).await?;
+    let new_jti = Uuid::new_v4();
+    let rows_affected = db::session::rotate(id, refresh_jti, new_jti, pool)
+        .await
+        .map_err(map_db_error)?;
+    if rows_affected == 0 {
+        // another request rotated the same token first
+        warn!("[{:} : {:}] CONCURRENT REFRESH, REVOKING SESSION {:?}", file!(), line!(), id);
+        revoke(id, pool).await?;
+        return Err(MyError::Unauthorized);
+    }
+    Ok(new_jti)
+}
+
+pub async fn revoke(id: Uuid, pool: &PGPool) -> Result<u64, MyError> {
+    db::session::revoke(id, pool)
+        .await
+        .map_err(map_db_error)
+}
+
+/// revokes every session of the user, returns the number of sessions ended
+pub async fn revoke_all(user_id: Uuid, pool: &PGPool) -> Result<u64, MyError> {
+    db::session::revoke_all(user_id, pool)
+        .await
+        .map_err(map_db_error)
+}
+
+/// lists active sessions of the user, **`current`** marks the one making the request
+pub async fn list(user_id: Uuid, current: Uuid, pool: &PGPool) -> Result<Vec<SessionInfo>, MyError> {
+    let sessions = db::session::get_active_by_user(user_id, pool)
+        .await
+        .map_err(map_db_error)?;
+    Ok(sessions.into_iter()
+        .map(|session| SessionInfo {
+            id: session.id,
+            user_agent: session.user_agent,
+            ip: session.ip,
+            created_at: session.created_at,
+            last_used_at: session.last_used_at,
+            current: session.id == current,
+        })
+        .collect())
+}
@@ -0,0 +1,208 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{error, warn};
+use uuid::Uuid;
+
+use crate::{db, dto::ClientInfo, errors::MyError, models::AuthThrottle, PGPool};
+
+/// how failures under one key slow down further attempts</br>
+/// after **`free_attempts`** each failure locks the key for **`base_delay`** seconds,
+/// doubled per failure up to **`max_delay`**, and **`lockout_after`** failures lock it for **`lockout`** seconds</br>
+/// failures older than **`window`** seconds are forgotten
+pub struct Policy {
+    pub scope: &'static str,
+    pub free_attempts: i32,
+    pub base_delay: i64,
+    pub max_delay: i64,
+    pub lockout_after: i32,
+    pub lockout: i64,
+    pub window: i64,
+}
+
+/// failed logins per username
+pub const ACCOUNT: Policy = Policy {
+    scope: "account",
+    free_attempts: 3,
+    base_delay: 1,
+    max_delay: 5 * 60,
+    lockout_after: 10,
+    lockout: 15 * 60,
+    window: 60 * 60,
+};
+
+/// failed logins per client address, shared by all usernames tried from it
+pub const IP: Policy = Policy {
+    scope: "ip",
+    free_attempts: 10,
+    base_delay: 1,
+    max_delay: 5 * 60,
+    lockout_after: 50,
+    lockout: 60 * 60,
+    window: 60 * 60,
+};
+
+/// every registration per client address counts
+pub const REGISTRATION: Policy = Policy {
+    scope: "register",
+    free_attempts: 5,
+    base_delay: 60,
+    max_delay: 60 * 60,
+    lockout_after: 20,
+    lockout: 24 * 60 * 60,
+    window: 24 * 60 * 60,
+};
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+impl Policy {
+    /// seconds the key stays locked after **`failures`** failures
+    fn delay(&self, failures: i32) -> i64 {
+        if failures >= self.lockout_after {
+            return self.lockout;
+        }
+        if failures <= self.free_attempts {
+            return 0;
+        }
+        let doublings = (failures - self.free_attempts - 1).min(30) as u32;
+        self.base_delay.saturating_mul(1 << doublings).min(self.max_delay)
+    }
+}
+
+fn retry_after(locked_until: DateTime<Utc>, now: DateTime<Utc>) -> u64 {
+    // round up so clients never retry a moment too early
+    ((locked_until - now).num_milliseconds() + 999).max(1000) as u64 / 1000
+}
+
+/// returns **`MyError::TooManyRequests`** while **`key`** is locked
+pub async fn check(policy: &Policy, key: &str, pool: &PGPool) -> Result<(), MyError> {
+    let now = Utc::now();
+    let state = db::throttle::get(policy.scope, key, pool)
+        .await
+        .map_err(internal)?;
+    match state.and_then(|state| state.locked_until) {
+        Some(locked_until) if locked_until > now => Err(MyError::TooManyRequests { retry_after: retry_after(locked_until, now) }),
+        _ => Ok(()),
+    }
+}
+
+/// counts a failure under **`key`** and locks it according to the policy
+pub async fn record_failure(policy: &Policy, key: &str, pool: &PGPool) -> Result<(), MyError> {
+    let now = Utc::now();
+    let failures = db::throttle::record_failure(policy.scope, key, now - Duration::seconds(policy.window), pool)
+        .await
+        .map_err(internal)?;
+    let delay = policy.delay(failures);
+    if delay > 0 {
+        warn!("{:} {:?} LOCKED FOR {:}s AFTER {:} FAILURES", policy.scope.to_uppercase(), key, delay, failures);
+        db::throttle::lock_until(policy.scope, key, now + Duration::seconds(delay), pool)
+            .await
+            .map_err(internal)?;
+    }
+    Ok(())
+}
+
+pub async fn clear(policy: &Policy, key: &str, pool: &PGPool) -> Result<u64, MyError> {
+    db::throttle::clear(policy.scope, key, pool)
+        .await
+        .map_err(internal)
+}
+
+/// rejects a login attempt while the account or the client address is locked
+pub async fn check_login(username: &str, client: &ClientInfo, pool: &PGPool) -> Result<(), MyError> {
+    check(&ACCOUNT, username, pool).await?;
+    if let Some(ip) = &client.ip {
+        check(&IP, ip, pool).await?;
+    }
+    Ok(())
+}
+
+/// counts a wrong password or second factor against the account and the client address
+pub async fn login_failed(username: &str, client: &ClientInfo, pool: &PGPool) {
+    if let Err(err) = record_failure(&ACCOUNT, username, pool).await {
+        warn!("[{:} : {:}] FAILED TO RECORD LOGIN FAILURE: {:?}", file!(), line!(), err);
+    }
+    if let Some(ip) = &client.ip {
+        if let Err(err) = record_failure(&IP, ip, pool).await {
+            warn!("[{:} : {:}] FAILED TO RECORD LOGIN FAILURE: {:?}", file!(), line!(), err);
+        }
+    }
+}
+
+/// forgets the failures of the account, the client address keeps its count
+pub async fn login_succeeded(username: &str, pool: &PGPool) {
+    if let Err(err) = clear(&ACCOUNT, username, pool).await {
+        warn!("[{:} : {:}] FAILED TO CLEAR LOGIN FAILURES: {:?}", file!(), line!(), err);
+    }
+}
+
+/// limits how many accounts one client address can register
+pub async fn check_registration(client: &ClientInfo, pool: &PGPool) -> Result<(), MyError> {
+    let Some(ip) = &client.ip else {
+        return Ok(());
+    };
+    check(&REGISTRATION, ip, pool).await?;
+    record_failure(&REGISTRATION, ip, pool).await
+}
+
+/// currently locked accounts and addresses
+pub async fn get_locked(pool: &PGPool) -> Result<Vec<AuthThrottle>, MyError> {
+    db::throttle::get_locked(Utc::now(), pool)
+        .await
+        .map_err(internal)
+}
+
+/// lifts the lockout of the account, returns **`MyError::NotFound`** for an unknown user
+pub async fn clear_account(user_id: Uuid, pool: &PGPool) -> Result<u64, MyError> {
+    let user = match db::user::get_by_id(user_id, pool).await {
+        Ok(user) => user,
+        Err(sqlx::Error::RowNotFound) => return Err(MyError::NotFound),
+        Err(err) => return Err(internal(err)),
+    };
+    clear(&ACCOUNT, &user.username, pool).await
+}
+
+/// lifts the login and registration lockouts of the client address
+pub async fn clear_ip(ip: &str, pool: &PGPool) -> Result<u64, MyError> {
+    Ok(clear(&IP, ip, pool).await? + clear(&REGISTRATION, ip, pool).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lets_free_attempts_through() {
+        assert_eq!(ACCOUNT.delay(0), 0);
+        assert_eq!(ACCOUNT.delay(ACCOUNT.free_attempts), 0);
+        assert_eq!(ACCOUNT.delay(ACCOUNT.free_attempts + 1), ACCOUNT.base_delay);
+    }
+
+    #[test]
+    fn doubles_up_to_max_delay() {
+        let delays: Vec<i64> = (6..=12).map(|failures| REGISTRATION.delay(failures)).collect();
+        assert_eq!(delays, [60, 120, 240, 480, 960, 1920, 3600]);
+        assert_eq!(REGISTRATION.delay(REGISTRATION.lockout_after - 1), REGISTRATION.max_delay);
+        // far past the free attempts the shift must not overflow
+        assert_eq!(IP.delay(IP.lockout_after - 1), IP.max_delay);
+    }
+
+    #[test]
+    fn locks_out_after_too_many_failures() {
+        assert_eq!(ACCOUNT.delay(ACCOUNT.lockout_after - 1), 32);
+        assert_eq!(ACCOUNT.delay(ACCOUNT.lockout_after), ACCOUNT.lockout);
+        assert_eq!(ACCOUNT.delay(ACCOUNT.lockout_after + 100), ACCOUNT.lockout);
+    }
+
+    #[test]
+    fn rounds_retry_after_up() {
+        let now = Utc::now();
+        assert_eq!(retry_after(now + Duration::milliseconds(1), now), 1);
+        assert_eq!(retry_after(now + Duration::milliseconds(1500), now), 2);
+        assert_eq!(retry_after(now + Duration::seconds(2), now), 2);
+        assert_eq!(retry_after(now + Duration::milliseconds(2001), now), 3);
+        // a lock that just ran out still asks for a second
+        assert_eq!(retry_after(now - Duration::seconds(5), now), 1);
+    }
+}
@@ -0,0 +1,211 @@
+use chrono::Utc;
+use log::error;
+use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
+
+use crate::{db, dto::{ClientInfo, DisableTwoFactorDto, RecoveryCodes, TotpEnrollment}, errors::MyError, models::UserTotp, PGPool};
+
+use super::{auth::{jwt, UserAuthData}, credentials, crypto, password::{self, Verification}, throttle};
+
+/// RFC 4226 recommends 160 bit secrets
+const SECRET_BYTES: usize = 20;
+const DIGITS: usize = 6;
+/// seconds per code
+const STEP: u64 = 30;
+/// codes from one step before or after the current one are accepted
+const SKEW: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+const RECOVERY_CODE_BYTES: usize = 10;
+
+fn internal(err: sqlx::Error) -> MyError {
+    error!("[{:} : {:}] INTERNAL SERVER ERROR: {:?}", file!(), line!(), err);
+    MyError::InternalError
+}
+
+fn totp(secret: &str, username: &str) -> Result<TOTP, MyError> {
+    let bytes = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|err| {
+            error!("[{:} : {:}] MALFORMED TOTP SECRET: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })?;
+    // labels are separated by ':' in the otpauth uri
+    TOTP::new(Algorithm::SHA1, DIGITS, SKEW as u8, STEP, bytes, Some(jwt::issuer().replace(':', "")), username.replace(':', "_"))
+        .map_err(|err| {
+            error!("[{:} : {:}] TOTP SETUP ERROR: {:?}", file!(), line!(), err);
+            MyError::InternalError
+        })
+}
+
+/// recovery codes are compared case-insensitively and without separators
+fn recovery_code_hash(code: &str) -> String {
+    let normalized: String = code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    crypto::get_sha3_256_hash(&normalized)
+}
+
+fn new_recovery_code() -> String {
+    let encoded = Secret::Raw(crypto::random_bytes(RECOVERY_CODE_BYTES)).to_encoded().to_string();
+    encoded.as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// the step within **`SKEW`** of **`now`** whose code is **`code`**
+fn matching_step(totp: &TOTP, code: &str, now: i64) -> Option<i64> {
+    let current = now / STEP as i64;
+    (current - SKEW..=current + SKEW).find(|step| totp.generate(*step as u64 * STEP) == code)
+}
+
+/// checks a TOTP code against the secret, a code is accepted only once
+async fn check_totp(totp_row: &UserTotp, username: &str, code: &str, pool: &PGPool) -> Result<bool, MyError> {
+    let totp = totp(&totp_row.secret, username)?;
+    match matching_step(&totp, code, Utc::now().timestamp()) {
+        Some(step) => db::two_factor::use_step(totp_row.user_id, step, pool)
+            .await
+            .map(|rows| rows > 0)
+            .map_err(internal),
+        None => Ok(false),
+    }
+}
+
+/// checks a TOTP code or uses up a recovery code of a user with 2fa enabled
+async fn check_code(user_id: Uuid, username: &str, code: &str, pool: &PGPool) -> Result<bool, MyError> {
+    let totp_row = match db::two_factor::get(user_id, pool).await.map_err(internal)? {
+        Some(row) if row.confirmed_at.is_some() => row,
+        _ => return Ok(false),
+    };
+    let code = code.trim();
+    if code.len() == DIGITS && code.chars().all(|c| c.is_ascii_digit()) {
+        return check_totp(&totp_row, username, code, pool).await;
+    }
+    db::two_factor::use_recovery_code(user_id, &recovery_code_hash(code), pool)
+        .await
+        .map(|rows| rows > 0)
+        .map_err(internal)
+}
+
+pub async fn is_enabled(user_id: Uuid, pool: &PGPool) -> Result<bool, MyError> {
+    db::two_factor::is_enabled(user_id, pool)
+        .await
+        .map_err(internal)
+}
+
+/// second login step, returns **`MyError::Unauthorized`** for a wrong or reused code
+pub async fn verify(user_id: Uuid, username: &str, code: &str, pool: &PGPool) -> Result<(), MyError> {
+    if check_code(user_id, username, code, pool).await? {
+        Ok(())
+    } else {
+        Err(MyError::Unauthorized)
+    }
+}
+
+/// starts enrollment with a new secret, replacing an unconfirmed one</br>
+/// returns **`MyError::BadClientData`** if 2fa is already enabled
+pub async fn enroll(user: &UserAuthData, pool: &PGPool) -> Result<TotpEnrollment, MyError> {
+    let secret = Secret::Raw(crypto::random_bytes(SECRET_BYTES)).to_encoded().to_string();
+    let totp = totp(&secret, &user.username)?;
+    match db::two_factor::set_pending(user.user_id, &secret, pool).await {
+        Ok(0) => Err(MyError::BadClientData),
+        Ok(_) => Ok(TotpEnrollment { secret, otpauth_uri: totp.get_url() }),
+        Err(err) => Err(internal(err)),
+    }
+}
+
+/// enables 2fa once the user proves the authenticator works</br>
+/// returns fresh recovery codes, they are not shown again
+pub async fn confirm(user: &UserAuthData, code: &str, pool: &PGPool) -> Result<RecoveryCodes, MyError> {
+    let totp_row = match db::two_factor::get(user.user_id, pool).await.map_err(internal)? {
+        Some(row) if row.confirmed_at.is_none() => row,
+        _ => return Err(MyError::BadClientData),
+    };
+    if !check_totp(&totp_row, &user.username, code.trim(), pool).await? {
+        return Err(MyError::BadClientData);
+    }
+    let recovery_codes: Vec<String> = (0..RECOVERY_CODES).map(|_| new_recovery_code()).collect();
+    let hashes: Vec<String> = recovery_codes.iter().map(|code| recovery_code_hash(code)).collect();
+    db::two_factor::confirm(user.user_id, &hashes, pool)
+        .await
+        .map_err(internal)?;
+    Ok(RecoveryCodes { recovery_codes })
+}
+
+/// turns 2fa off, requires a TOTP or recovery code and, for accounts with a local password, **`pwd`**</br>
+/// returns **`MyError::Forbidden`** if either is wrong, wrong ones count towards the login throttle
+pub async fn disable(user: &UserAuthData, dto: DisableTwoFactorDto, client: ClientInfo, pool: &PGPool) -> Result<(), MyError> {
+    let account = db::user::get_by_id(user.user_id, pool)
+        .await
+        .map_err(internal)?;
+    if !is_enabled(user.user_id, pool).await? {
+        return Err(MyError::BadClientData);
+    }
+    throttle::check_login(&account.username, &client, pool).await?;
+    let pwd_ok = if account.auth_source == credentials::LOCAL_SOURCE {
+        let pwd = dto.pwd.ok_or(MyError::Forbidden)?;
+        password::verify(&pwd, &account.pwd_hash).await? != Verification::Invalid
+    } else {
+        // directory and OIDC accounts have no password of ours, the code alone proves it
+        true
+    };
+    if !pwd_ok || !check_code(user.user_id, &user.username, &dto.code, pool).await? {
+        throttle::login_failed(&account.username, &client, pool).await;
+        return Err(MyError::Forbidden);
+    }
+    throttle::login_succeeded(&account.username, pool).await;
+    db::two_factor::delete(user.user_id, pool)
+        .await
+        .map_err(internal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the SHA1 secret of RFC 6238 appendix B, base32 encoded
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        let totp = totp(RFC_SECRET, "alice").unwrap();
+        // the appendix lists 8 digits, ours are the last 6 of them
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(totp.generate(time), code, "at {time}");
+        }
+    }
+
+    #[test]
+    fn accepts_one_step_of_skew() {
+        let totp = totp(RFC_SECRET, "alice").unwrap();
+        let now: i64 = 1111111111;
+        let current = now / STEP as i64;
+        let code_at = |step: i64| totp.generate(step as u64 * STEP);
+        assert_eq!(matching_step(&totp, &code_at(current), now), Some(current));
+        assert_eq!(matching_step(&totp, &code_at(current - 1), now), Some(current - 1));
+        assert_eq!(matching_step(&totp, &code_at(current + 1), now), Some(current + 1));
+        assert_eq!(matching_step(&totp, &code_at(current - 2), now), None);
+        assert_eq!(matching_step(&totp, &code_at(current + 2), now), None);
+    }
+
+    #[test]
+    fn normalizes_recovery_codes() {
+        let code = new_recovery_code();
+        assert!(code.split('-').all(|group| group.len() == 4));
+        let hash = recovery_code_hash(&code);
+        assert_eq!(recovery_code_hash(&code.to_lowercase()), hash);
+        assert_eq!(recovery_code_hash(&code.replace('-', "")), hash);
+        assert_eq!(recovery_code_hash(&format!(" {} ", code.replace('-', " "))), hash);
+        assert_ne!(recovery_code_hash(&code[1..]), hash);
+    }
+}
@@ -1,77 +1,337 @@
-use crate::{dto::NewUserDto, PGPool, models::{User, Event}, errors::MyError, service::auth, ACCESS_TOKEN_EXP};
+use std::ops::RangeInclusive;
+use chrono_tz::Tz;
+use crate::{dto::{ChangePasswordDto, ClientInfo, NewUserDto, PrivacySettings, PrivateProfile, PublicProfile, TokenPair, UpdateProfileDto, UpdateUserDto}, PGPool, models::{User, Event}, errors::MyError};
 use crate::db;
+use log::warn;
 use uuid::Uuid;
 
-use super::crypto;
+use super::{account, auth::{self, UserAuthData}, credentials, email_verification, mail::{self, MailSender}, password::{self, Verification}, rbac::{self, Permissions, Role}, session, throttle};
 
-pub async fn create(dto: NewUserDto, pool: &PGPool) -> Result<u64, MyError>{
+/// limits follow the column sizes of **`users`**
+const USERNAME_LEN: RangeInclusive<usize> = 3..=24;
+const EMAIL_MAX: usize = 50;
+const DISPLAY_NAME_MAX: usize = 64;
+const BIO_MAX: usize = 1000;
+const TIME_ZONE_MAX: usize = 64;
+const LOCALE_MAX: usize = 35;
+
+/// creates the user and mails a verification token if an **`email`** was given</br>
+/// **`username`** and **`email`** are checked like a change through **`update_me`**
+pub async fn create(dto: NewUserDto, mailer: &dyn MailSender, pool: &PGPool) -> Result<u64, MyError>{
     let NewUserDto{username, email, pwd, pwd_confirm} = dto;
-    if db::user::exists(username.clone(), pool).await {
-        return Err(MyError::BadClientData);
+    let username = username.trim().to_string();
+    let email = email.map(|email| email.trim().to_string());
+    let invalid_email = email.as_deref().is_some_and(|email| !valid_email(email));
+    if !valid_username(&username) || invalid_email || db::user::exists(username.clone(), pool).await {
+        Err(MyError::BadClientData)
     } else {
-        let pwd_hash: String = crypto::get_sha3_256_hash(&pwd);
-        let pwd_confirm_hash: String = crypto::get_sha3_256_hash(&pwd_confirm);
-        let access_token: Option<String>;
-        let refresh_token: Option<String>;
         let id = Uuid::new_v4();
-        
-        if let Ok(v) = auth::jwt::create(
-            &auth::jwt::TokenType::Access, &id, &username, ACCESS_TOKEN_EXP
-        ) {
-            access_token = Some(v);
-        } else {
-            return Err(MyError::AuthError);
-        }
-        if let Ok(v) = auth::jwt::create(
-            &auth::jwt::TokenType::Refresh, &id, &username, ACCESS_TOKEN_EXP
-        ) {
-            refresh_token = Some(v);
-        } else {
-            return Err(MyError::AuthError);
-        }
-        if pwd_hash.eq(&pwd_confirm_hash) {
+        if password::check_new(&pwd, &pwd_confirm).is_ok() {
+            let pwd_hash: String = password::hash(&pwd).await?;
             let res = db::user::create(User { 
                 id,
                 pwd_hash, 
                 username, 
-                email,
-                access_token,
-                refresh_token
+                email: email.clone(),
+                role: rbac::DEFAULT_ROLE.as_str().to_string(),
+                permissions: rbac::DEFAULT_ROLE.permissions().to_db(),
+                email_verified_at: None,
+                auth_source: credentials::LOCAL_SOURCE.to_string(),
+                show_email: false,
+                show_participations: false,
+                listed: true,
+                display_name: None,
+                bio: None,
+                time_zone: None,
+                locale: None
             }, pool)
             .await;
-            match res {
-                Ok(value) => Ok(value.rows_affected()),
-                Err(_) => Err(MyError::BadClientData)
+            let rows = match res {
+                Ok(value) => value.rows_affected(),
+                Err(_) => return Err(MyError::BadClientData)
+            };
+            if let Some(email) = email {
+                // the account exists either way, the user can ask for another mail
+                if let Err(err) = email_verification::send(id, &email, mailer, pool).await {
+                    warn!("[{:} : {:}] FAILED TO SEND VERIFICATION MAIL: {:?}", file!(), line!(), err);
+                }
             }
+            Ok(rows)
         } else {
-            return Err(MyError::BadClientData);
+            Err(MyError::BadClientData)
         }
     }
 }
 
-pub async fn get_all(pool: &PGPool) -> Result<Vec<User>, MyError> {
-    let result = db::user::get_all(pool).await;
+/// user managers and the owner see a profile in full
+fn sees_everything(user: &User, viewer: &UserAuthData) -> bool {
+    user.id == viewer.user_id || viewer.permissions.contains(Permissions::MANAGE_USERS)
+}
+
+fn public_profile(user: User, viewer: &UserAuthData) -> PublicProfile {
+    let show_email = user.show_email || sees_everything(&user, viewer);
+    PublicProfile {
+        id: user.id,
+        username: user.username,
+        display_name: user.display_name,
+        bio: user.bio,
+        email: user.email.filter(|_| show_email),
+    }
+}
+
+fn map_db_error(err: sqlx::Error) -> MyError {
+    match err {
+        sqlx::Error::RowNotFound => MyError::NotFound,
+        _ => MyError::InternalError
+    }
+}
+
+/// listed users, user managers also see unlisted ones
+pub async fn get_all(viewer: &UserAuthData, pool: &PGPool) -> Result<Vec<PublicProfile>, MyError> {
+    let result = if viewer.permissions.contains(Permissions::MANAGE_USERS) {
+        db::user::get_all(pool).await
+    } else {
+        db::user::get_listed(viewer.user_id, pool).await
+    };
     match result {
-        Ok(users) => Ok(users),
+        Ok(users) => Ok(users.into_iter().map(|user| public_profile(user, viewer)).collect()),
+        Err(_) => Err(MyError::InternalError)
+    }
+}
+
+pub async fn get_by_id(id: Uuid, viewer: &UserAuthData, pool: &PGPool) -> Result<PublicProfile, MyError> {
+    let user = db::user::get_by_id(id, pool)
+        .await
+        .map_err(map_db_error)?;
+    Ok(public_profile(user, viewer))
+}
+
+/// the caller's own profile including private fields
+pub async fn get_me(viewer: &UserAuthData, pool: &PGPool) -> Result<PrivateProfile, MyError> {
+    let user = db::user::get_by_id(viewer.user_id, pool)
+        .await
+        .map_err(map_db_error)?;
+    Ok(PrivateProfile {
+        id: user.id,
+        username: user.username,
+        email: user.email,
+        email_verified_at: user.email_verified_at,
+        display_name: user.display_name,
+        bio: user.bio,
+        time_zone: user.time_zone,
+        locale: user.locale,
+        role: user.role,
+        auth_source: user.auth_source,
+        privacy: PrivacySettings {
+            show_email: user.show_email,
+            show_participations: user.show_participations,
+            listed: user.listed,
+        },
+    })
+}
+
+fn valid_email(email: &str) -> bool {
+    email.len() <= EMAIL_MAX && mail::is_valid_address(email)
+}
+
+fn valid_username(username: &str) -> bool {
+    USERNAME_LEN.contains(&username.chars().count())
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        && !account::is_reserved_username(username)
+}
+
+/// BCP 47 shaped: a 2-3 letter language followed by 1-8 character subtags, e.g. **`pt-BR`**
+fn valid_locale(locale: &str) -> bool {
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+    locale.len() <= LOCALE_MAX
+        && (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|tag| (1..=8).contains(&tag.len()) && tag.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// trims a clearable text field, an empty value clears it</br>
+/// returns **`MyError::BadClientData`** if it is too long or holds control characters other than newlines
+fn clearable(value: Option<String>, max_len: usize, multiline: bool) -> Result<Option<Option<String>>, MyError> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(Some(None));
+    }
+    let bad_char = value.chars().any(|c| c.is_control() && !(multiline && matches!(c, '\n' | '\r' | '\t')));
+    if bad_char || value.chars().count() > max_len {
+        return Err(MyError::BadClientData);
+    }
+    Ok(Some(Some(value.to_string())))
+}
+
+/// validated partial update of the caller's own profile</br>
+/// a new **`email`** is unverified until the mailed token is used,</br>
+/// accounts owned by the directory cannot be renamed
+pub async fn update_me(
+    viewer: &UserAuthData,
+    dto: UpdateProfileDto,
+    mailer: &dyn MailSender,
+    pool: &PGPool
+) -> Result<PrivateProfile, MyError> {
+    let user = db::user::get_by_id(viewer.user_id, pool)
+        .await
+        .map_err(map_db_error)?;
+    let username = dto.username
+        .map(|username| username.trim().to_string())
+        .filter(|username| *username != user.username);
+    if let Some(username) = &username {
+        if user.auth_source == credentials::LDAP_SOURCE {
+            return Err(MyError::Forbidden);
+        }
+        if !valid_username(username) || db::user::exists(username.clone(), pool).await {
+            return Err(MyError::BadClientData);
+        }
+    }
+    let email = dto.email
+        .map(|email| email.trim().to_string())
+        .filter(|email| Some(email) != user.email.as_ref());
+    if email.as_deref().is_some_and(|email| !valid_email(email)) {
+        return Err(MyError::BadClientData);
+    }
+    let time_zone = clearable(dto.time_zone, TIME_ZONE_MAX, false)?;
+    if let Some(Some(time_zone)) = &time_zone {
+        if time_zone.parse::<Tz>().is_err() {
+            return Err(MyError::BadClientData);
+        }
+    }
+    let locale = clearable(dto.locale, LOCALE_MAX, false)?;
+    if locale.as_ref().is_some_and(|locale| locale.as_deref().is_some_and(|locale| !valid_locale(locale))) {
+        return Err(MyError::BadClientData);
+    }
+    let user_fields = UpdateUserDto {
+        username,
+        email: email.clone(),
+        display_name: clearable(dto.display_name, DISPLAY_NAME_MAX, false)?,
+        bio: clearable(dto.bio, BIO_MAX, true)?,
+        time_zone,
+        locale,
+        ..Default::default()
+    };
+    match db::user::set_fields(user.id, user_fields, pool).await {
+        Ok(_) => {},
+        // another request took the name in the meantime
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => return Err(MyError::BadClientData),
+        Err(_) => return Err(MyError::InternalError)
+    }
+    if let Some(email) = email {
+        if let Err(err) = email_verification::send(user.id, &email, mailer, pool).await {
+            warn!("[{:} : {:}] FAILED TO SEND VERIFICATION MAIL: {:?}", file!(), line!(), err);
+        }
+    }
+    get_me(viewer, pool).await
+}
+
+pub async fn set_privacy(viewer: &UserAuthData, privacy: PrivacySettings, pool: &PGPool) -> Result<PrivacySettings, MyError> {
+    match db::user::set_privacy(viewer.user_id, &privacy, pool).await {
+        Ok(0) => Err(MyError::NotFound),
+        Ok(_) => Ok(privacy),
         Err(_) => Err(MyError::InternalError)
     }
 }
 
-pub async fn get_by_id(id: Uuid, pool: &PGPool) -> Result<User, MyError> {
-    let result = db::user::get_by_id(id, pool)
+/// returns **`MyError::Forbidden`** unless the user shares their participations
+pub async fn get_user_participations(id: Uuid, viewer: &UserAuthData, pool: &PGPool) -> Result<Vec<Event>, MyError> {
+    let user = db::user::get_by_id(id, pool)
+        .await
+        .map_err(map_db_error)?;
+    if !user.show_participations && !sees_everything(&user, viewer) {
+        return Err(MyError::Forbidden);
+    }
+    let result = db::user::get_user_participations(id, pool)
         .await;
     match result {
-        Ok(user) => Ok(user),
+        Ok(val) => Ok(val),
         Err(_) => Err(MyError::InternalError)
     }
 }
 
-pub async fn get_user_participations(id: Uuid, pool: &PGPool) -> Result<Vec<Event>, MyError> {
-    let result = db::user::get_user_participations(id, pool)
+/// assigns **`role`** and resets the user's permissions to the role defaults
+pub async fn set_role(id: Uuid, role: Role, pool: &PGPool) -> Result<u64, MyError> {
+    let result = db::user::set_role(id, role.as_str(), role.permissions().to_db(), pool)
         .await;
     match result {
+        Ok(0) => Err(MyError::BadClientData),
         Ok(val) => Ok(val),
         Err(_) => Err(MyError::InternalError)
     }
 }
 
+/// replaces the password and revokes every session of the user
+pub async fn set_password(id: Uuid, pwd: &str, pwd_confirm: &str, pool: &PGPool) -> Result<(), MyError> {
+    password::check_new(pwd, pwd_confirm)?;
+    let user_fields = UpdateUserDto {
+        pwd_hash: Some(password::hash(pwd).await?),
+        ..Default::default()
+    };
+    match db::user::set_fields(id, user_fields, pool).await {
+        Ok(0) => return Err(MyError::NotFound),
+        Ok(_) => {},
+        Err(_) => return Err(MyError::InternalError)
+    }
+    session::revoke_all(id, pool).await?;
+    Ok(())
+}
+
+/// changes the password of a logged in user after checking **`current_pwd`**</br>
+/// every session is revoked, the caller gets a fresh token pair</br>
+/// wrong **`current_pwd`** attempts count like failed logins, returns **`MyError::TooManyRequests`** while locked
+pub async fn change_password(
+    user_auth_data: &UserAuthData,
+    dto: ChangePasswordDto,
+    client: ClientInfo,
+    pool: &PGPool
+) -> Result<TokenPair, MyError> {
+    let user = db::user::get_by_id(user_auth_data.user_id, pool)
+        .await
+        .map_err(|_| MyError::InternalError)?;
+    throttle::check_login(&user.username, &client, pool).await?;
+    if password::verify(&dto.current_pwd, &user.pwd_hash).await? == Verification::Invalid {
+        throttle::login_failed(&user.username, &client, pool).await;
+        return Err(MyError::Forbidden);
+    }
+    throttle::login_succeeded(&user.username, pool).await;
+    set_password(user_auth_data.user_id, &dto.pwd, &dto.pwd_confirm, pool).await?;
+    auth::jwt::issue_pair(&user_auth_data.user_id, &user_auth_data.username, client, pool).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_usernames() {
+        assert!(valid_username("alice"));
+        assert!(valid_username("a.b_c-1"));
+        assert!(!valid_username("al"));
+        assert!(!valid_username(&"a".repeat(25)));
+        assert!(!valid_username("bad name"));
+        assert!(!valid_username("a:b"));
+        assert!(!valid_username("ünïcode"));
+        assert!(!valid_username("deleted-0123456789abcdef"));
+        assert!(!valid_username("Deleted-x"));
+        assert!(valid_username("deleted"));
+    }
+
+    #[test]
+    fn checks_emails() {
+        assert!(valid_email("alice@example.com"));
+        assert!(!valid_email("alice"));
+        assert!(!valid_email(&format!("{}@example.com", "a".repeat(EMAIL_MAX))));
+    }
+
+    #[test]
+    fn checks_locales() {
+        assert!(valid_locale("de"));
+        assert!(valid_locale("pt-BR"));
+        assert!(!valid_locale("german"));
+        assert!(!valid_locale("de-"));
+    }
+}